@@ -0,0 +1,261 @@
+#![allow(clippy::result_large_err)]
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use kucoin_rs::kucoin::error::APIError;
+use kucoin_rs::kucoin::model::websocket::KucoinWebsocketMsg;
+use kucoin_rs::kucoin::websocket::parse_message;
+use kucoin_rs::tokio_tungstenite::tungstenite::Message;
+
+const LEVEL2: &str = r#"{"type":"message","topic":"/market/level2:BTC-USDT","subject":"trade.l2update","data":{"sequenceStart":1545896669105,"sequenceEnd":1545896669106,"symbol":"BTC-USDT","changes":{"asks":[["6","1","1545896669105"]],"bids":[["4","1","1545896669106"]]}}}"#;
+const MATCH: &str = r#"{"type":"message","topic":"/market/match:BTC-USDT","subject":"trade.l3match","data":{"sequence":"1545896669145","type":"match","symbol":"BTC-USDT","side":"buy","price":"0.08200000000000000000","size":"0.01022222000000000000","tradeId":"5c24c5da03aa673885cd67aa","takerOrderId":"5c24c5d903aa6772d55b371e","makerOrderId":"5c2187d003aa677bd09d5c93","time":"1545913818099033203"}}"#;
+const TRADE_ORDERS: &str = r#"{"type":"message","topic":"/spotMarket/tradeOrders","subject":"orderChange","channelType":"private","data":{"symbol":"KCS-USDT","orderType":"limit","side":"sell","liquidity":"taker","type":"match","orderId":"5efab07953bdea00089965d2","orderTime":1593487481683297666,"size":"0.1","filledSize":"0.1","price":"0.938","matchPrice":"0.96","matchSize":"0.1","tradeId":"5efab07a4ee4c7000a82d6d9","clientOid":"1593487481000313","remainSize":"0","status":"match","ts":1593487482038606180}}"#;
+
+fn bench_parse(c: &mut Criterion) {
+    let feeds = [
+        ("level2", LEVEL2),
+        ("match", MATCH),
+        ("trade_orders", TRADE_ORDERS),
+    ];
+    for (name, frame) in feeds.iter() {
+        let mut group = c.benchmark_group(*name);
+        group.throughput(Throughput::Bytes(frame.len() as u64));
+        group.bench_function("dispatch", |b| {
+            b.iter(|| parse_message(black_box(Message::Text(frame.to_string()))).unwrap())
+        });
+        group.bench_function("substring", |b| {
+            b.iter(|| legacy_parse_message(black_box(Message::Text(frame.to_string()))).unwrap())
+        });
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_parse);
+criterion_main!(benches);
+
+// The substring matching parse_message used before dispatching on the frame envelope,
+// kept as the baseline for comparison.
+fn legacy_parse_message(msg: Message) -> Result<KucoinWebsocketMsg, APIError> {
+    match msg {
+        Message::Text(msg) => {
+            if msg.contains("\"type\":\"welcome\"") || msg.contains("\"type\":\"ack\"") {
+                Ok(KucoinWebsocketMsg::WelcomeMsg(serde_json::from_str(&msg)?))
+            } else if msg.contains("\"type\":\"ping\"") {
+                Ok(KucoinWebsocketMsg::PingMsg(serde_json::from_str(&msg)?))
+            } else if msg.contains("\"type\":\"pong\"") {
+                Ok(KucoinWebsocketMsg::PongMsg(serde_json::from_str(&msg)?))
+            } else if msg.contains("\"topic\":\"/contract") {
+                legacy_parse_futures_message(&msg)
+            } else if msg.contains("\"subject\":\"trade.ticker\"") {
+                Ok(KucoinWebsocketMsg::TickerMsg(serde_json::from_str(&msg)?))
+            } else if msg.contains("\"topic\":\"/market/ticker:all\"") {
+                Ok(KucoinWebsocketMsg::AllTickerMsg(serde_json::from_str(
+                    &msg,
+                )?))
+            } else if msg.contains("\"subject\":\"trade.snapshot\"") {
+                Ok(KucoinWebsocketMsg::SnapshotMsg(serde_json::from_str(&msg)?))
+            } else if msg.contains("\"subject\":\"trade.l2update\"") {
+                Ok(KucoinWebsocketMsg::OrderBookMsg(serde_json::from_str(
+                    &msg,
+                )?))
+            } else if msg.contains("/market/match:") {
+                Ok(KucoinWebsocketMsg::MatchMsg(serde_json::from_str(&msg)?))
+            } else if msg.contains("\"subject\":\"trade.l3received\"") {
+                Ok(KucoinWebsocketMsg::Level3ReceivedMsg(serde_json::from_str(
+                    &msg,
+                )?))
+            } else if msg.contains("\"subject\":\"trade.l3open\"") {
+                Ok(KucoinWebsocketMsg::Level3OpenMsg(serde_json::from_str(
+                    &msg,
+                )?))
+            } else if msg.contains("\"subject\":\"trade.l3done\"") {
+                Ok(KucoinWebsocketMsg::Level3DoneMsg(serde_json::from_str(
+                    &msg,
+                )?))
+            } else if msg.contains("\"subject\":\"trade.l3match\"") {
+                Ok(KucoinWebsocketMsg::Level3MatchMsg(serde_json::from_str(
+                    &msg,
+                )?))
+            } else if msg.contains("\"subject\":\"trade.l3change\"") {
+                Ok(KucoinWebsocketMsg::Level3ChangeMsg(serde_json::from_str(
+                    &msg,
+                )?))
+            } else if msg.contains("\"subject\":\"level2\"") {
+                Ok(KucoinWebsocketMsg::OrderBookDepthMsg(serde_json::from_str(
+                    &msg,
+                )?))
+            } else if msg.contains("\"subject\":\"received\"") {
+                Ok(KucoinWebsocketMsg::FullMatchReceivedMsg(
+                    serde_json::from_str(&msg)?,
+                ))
+            } else if msg.contains("\"subject\":\"open\"") {
+                Ok(KucoinWebsocketMsg::FullMatchOpenMsg(serde_json::from_str(
+                    &msg,
+                )?))
+            } else if msg.contains("\"subject\":\"done\"") {
+                Ok(KucoinWebsocketMsg::FullMatchDoneMsg(serde_json::from_str(
+                    &msg,
+                )?))
+            } else if msg.contains("\"subject\":\"match\"") {
+                Ok(KucoinWebsocketMsg::FullMatchMatchMsg(serde_json::from_str(
+                    &msg,
+                )?))
+            } else if msg.contains("\"subject\":\"update\"") {
+                Ok(KucoinWebsocketMsg::FullMatchChangeMsg(
+                    serde_json::from_str(&msg)?,
+                ))
+            } else if msg.contains("/indicator/index:") {
+                Ok(KucoinWebsocketMsg::IndexPriceMsg(serde_json::from_str(
+                    &msg,
+                )?))
+            } else if msg.contains("/indicator/markPrice:") {
+                Ok(KucoinWebsocketMsg::MarketPriceMsg(serde_json::from_str(
+                    &msg,
+                )?))
+            } else if msg.contains("/margin/fundingBook:") {
+                Ok(KucoinWebsocketMsg::OrderBookChangeMsg(
+                    serde_json::from_str(&msg)?,
+                ))
+            } else if msg.contains("\"type\":\"stop\"") || msg.contains("\"type\":\"activate\"") {
+                Ok(KucoinWebsocketMsg::StopOrderMsg(serde_json::from_str(
+                    &msg,
+                )?))
+            } else if msg.contains("/account/balance") {
+                Ok(KucoinWebsocketMsg::BalancesMsg(serde_json::from_str(&msg)?))
+            } else if msg.contains("debt.ratio") {
+                Ok(KucoinWebsocketMsg::DebtRatioMsg(serde_json::from_str(
+                    &msg,
+                )?))
+            } else if msg.contains("position.status") {
+                Ok(KucoinWebsocketMsg::PositionChangeMsg(serde_json::from_str(
+                    &msg,
+                )?))
+            } else if msg.contains("order.open") {
+                Ok(KucoinWebsocketMsg::MarginTradeOpenMsg(
+                    serde_json::from_str(&msg)?,
+                ))
+            } else if msg.contains("order.update") {
+                Ok(KucoinWebsocketMsg::MarginTradeUpdateMsg(
+                    serde_json::from_str(&msg)?,
+                ))
+            } else if msg.contains("order.done") {
+                Ok(KucoinWebsocketMsg::MarginTradeDoneMsg(
+                    serde_json::from_str(&msg)?,
+                ))
+            } else if msg.contains("\"topic\":\"/spotMarket/tradeOrdersV2\"") {
+                legacy_parse_hf_trade_message(&msg)
+            } else if msg.contains("error") {
+                Ok(KucoinWebsocketMsg::Error(serde_json::from_str(&msg)?))
+            } else if msg.contains("\"topic\":\"/spotMarket/tradeOrders\"") {
+                if msg.contains("\"type\":\"open\"") {
+                    Ok(KucoinWebsocketMsg::TradeOpenMsg(serde_json::from_str(
+                        &msg,
+                    )?))
+                } else if msg.contains("\"type\":\"match\"") {
+                    Ok(KucoinWebsocketMsg::TradeMatchMsg(serde_json::from_str(
+                        &msg,
+                    )?))
+                } else if msg.contains("\"type\":\"filled\"") {
+                    Ok(KucoinWebsocketMsg::TradeFilledMsg(serde_json::from_str(
+                        &msg,
+                    )?))
+                } else if msg.contains("\"type\":\"canceled\"") {
+                    Ok(KucoinWebsocketMsg::TradeCanceledMsg(serde_json::from_str(
+                        &msg,
+                    )?))
+                } else if msg.contains("\"type\":\"update\"") {
+                    Ok(KucoinWebsocketMsg::TradeUpdateMsg(serde_json::from_str(
+                        &msg,
+                    )?))
+                } else {
+                    Err(APIError::Other(
+                        "No KucoinWebSocketMsg type to parse".to_string(),
+                    ))
+                }
+            } else {
+                Err(APIError::Other(
+                    "No KucoinWebSocketMsg type to parse".to_string(),
+                ))
+            }
+        }
+        Message::Binary(b) => Ok(KucoinWebsocketMsg::Binary(b)),
+        Message::Pong(..) => Ok(KucoinWebsocketMsg::Pong),
+        Message::Ping(..) => Ok(KucoinWebsocketMsg::Ping),
+        Message::Close(..) => Err(APIError::Other("Socket closed error".to_string())),
+    }
+}
+
+fn legacy_parse_hf_trade_message(msg: &str) -> Result<KucoinWebsocketMsg, APIError> {
+    if msg.contains("\"type\":\"received\"") {
+        Ok(KucoinWebsocketMsg::HfTradeReceivedMsg(
+            serde_json::from_str(msg)?,
+        ))
+    } else if msg.contains("\"type\":\"open\"") {
+        Ok(KucoinWebsocketMsg::HfTradeOpenMsg(serde_json::from_str(
+            msg,
+        )?))
+    } else if msg.contains("\"type\":\"match\"") {
+        Ok(KucoinWebsocketMsg::HfTradeMatchMsg(serde_json::from_str(
+            msg,
+        )?))
+    } else if msg.contains("\"type\":\"filled\"") {
+        Ok(KucoinWebsocketMsg::HfTradeFilledMsg(serde_json::from_str(
+            msg,
+        )?))
+    } else if msg.contains("\"type\":\"canceled\"") {
+        Ok(KucoinWebsocketMsg::HfTradeCanceledMsg(
+            serde_json::from_str(msg)?,
+        ))
+    } else if msg.contains("\"type\":\"update\"") {
+        Ok(KucoinWebsocketMsg::HfTradeUpdateMsg(serde_json::from_str(
+            msg,
+        )?))
+    } else {
+        Err(APIError::Other(
+            "No KucoinWebSocketMsg type to parse".to_string(),
+        ))
+    }
+}
+
+fn legacy_parse_futures_message(msg: &str) -> Result<KucoinWebsocketMsg, APIError> {
+    if msg.contains("/contractMarket/tickerV2:") {
+        Ok(KucoinWebsocketMsg::FuturesTickerMsg(serde_json::from_str(
+            msg,
+        )?))
+    } else if msg.contains("/contractMarket/level2:") {
+        Ok(KucoinWebsocketMsg::FuturesOrderBookMsg(
+            serde_json::from_str(msg)?,
+        ))
+    } else if msg.contains("/contractMarket/execution:") {
+        Ok(KucoinWebsocketMsg::FuturesExecutionMsg(
+            serde_json::from_str(msg)?,
+        ))
+    } else if msg.contains("\"subject\":\"mark.index.price\"") {
+        Ok(KucoinWebsocketMsg::FuturesMarkIndexPriceMsg(
+            serde_json::from_str(msg)?,
+        ))
+    } else if msg.contains("\"subject\":\"funding.rate\"") {
+        Ok(KucoinWebsocketMsg::FuturesFundingRateMsg(
+            serde_json::from_str(msg)?,
+        ))
+    } else if msg.contains("\"subject\":\"position.change\"") {
+        Ok(KucoinWebsocketMsg::FuturesPositionChangeMsg(
+            serde_json::from_str(msg)?,
+        ))
+    } else if msg.contains("/contractMarket/tradeOrders") {
+        Ok(KucoinWebsocketMsg::FuturesOrderMsg(serde_json::from_str(
+            msg,
+        )?))
+    } else if msg.contains("\"subject\":\"orderMargin.change\"") {
+        Ok(KucoinWebsocketMsg::FuturesOrderMarginMsg(
+            serde_json::from_str(msg)?,
+        ))
+    } else if msg.contains("\"subject\":\"availableBalance.change\"") {
+        Ok(KucoinWebsocketMsg::FuturesAvailableBalanceMsg(
+            serde_json::from_str(msg)?,
+        ))
+    } else {
+        Err(APIError::Other(
+            "No KucoinWebSocketMsg type to parse".to_string(),
+        ))
+    }
+}
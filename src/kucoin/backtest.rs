@@ -0,0 +1,925 @@
+use std::collections::HashMap;
+
+use futures::{pin_mut, Stream, StreamExt};
+
+use super::error::APIError;
+use super::exchange::{MarketData, OrderKind, OrderRequest, Side, TimeInForce};
+use super::model::market::{Candle, Klines, SymbolList};
+use super::model::trade::FillsInfo;
+use super::model::websocket::KucoinWebsocketMsg;
+use super::utils::{format_amount, round_down, round_up};
+
+/// How resting limit orders are filled when the market reaches their price.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QueueModel {
+    /// Filled in full as soon as the market trades at the order's price.
+    Touch,
+    /// Filled in full only once the market trades through the order's price, as if every
+    /// order already resting at that price was ahead of it.
+    TradeThrough,
+    /// Filled when the market trades at the order's price, up to the fraction of the traded
+    /// volume, e.g. 0.1 for a tenth of a candle's volume.
+    VolumeShare(f64),
+}
+
+/// Price impact applied to market orders.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SlippageModel {
+    None,
+    /// Fills are this many basis points worse than the market price.
+    FixedBps(f64),
+    /// Fills are this many price increments of the symbol worse than the market price.
+    Ticks(u32),
+}
+
+/// Starting cash, fees and fill models of a Backtester, see BacktestConfig::new for the defaults.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BacktestConfig {
+    /// Cash in the quote currency shared by the backtested symbols
+    pub initial_cash: f64,
+    pub maker_fee: f64,
+    pub taker_fee: f64,
+    pub queue: QueueModel,
+    pub slippage: SlippageModel,
+}
+
+impl Default for BacktestConfig {
+    fn default() -> Self {
+        BacktestConfig::new()
+    }
+}
+
+impl BacktestConfig {
+    /// 10000 in cash, Kucoin's base fee rates of 0.1%, TradeThrough and no slippage.
+    pub fn new() -> Self {
+        BacktestConfig {
+            initial_cash: 10_000.0,
+            maker_fee: 0.001,
+            taker_fee: 0.001,
+            queue: QueueModel::TradeThrough,
+            slippage: SlippageModel::None,
+        }
+    }
+
+    pub fn initial_cash(&mut self, c: f64) -> &mut Self {
+        self.initial_cash = c;
+        self
+    }
+
+    pub fn maker_fee(&mut self, f: f64) -> &mut Self {
+        self.maker_fee = f;
+        self
+    }
+
+    pub fn taker_fee(&mut self, f: f64) -> &mut Self {
+        self.taker_fee = f;
+        self
+    }
+
+    pub fn queue(&mut self, q: QueueModel) -> &mut Self {
+        self.queue = q;
+        self
+    }
+
+    pub fn slippage(&mut self, s: SlippageModel) -> &mut Self {
+        self.slippage = s;
+        self
+    }
+
+    pub fn build(&self) -> Self {
+        self.clone()
+    }
+}
+
+/// Increments and minimum size of a symbol, which simulated prices and sizes are rounded to.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SymbolRules {
+    pub price_increment: f64,
+    pub base_increment: f64,
+    pub base_min_size: f64,
+}
+
+impl SymbolRules {
+    pub fn from_symbol(symbol: &SymbolList) -> Option<Self> {
+        Some(SymbolRules {
+            price_increment: symbol.price_increment.parse().ok()?,
+            base_increment: symbol.base_increment.parse().ok()?,
+            base_min_size: symbol.base_min_size.parse().ok()?,
+        })
+    }
+}
+
+/// Historical market data driving a Backtester.
+#[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
+pub enum MarketEvent {
+    /// Candle of the symbol, e.g. from get_klines or load_candles
+    Candle { symbol: String, candle: Candle },
+    /// Websocket message, e.g. from a Replay. Ticker and match messages move the market,
+    /// other messages are only passed to the strategy.
+    Message(KucoinWebsocketMsg),
+}
+
+/// Candle events of the symbol in the order of the candles.
+pub fn candle_events(symbol: &str, candles: Vec<Candle>) -> Vec<MarketEvent> {
+    candles
+        .into_iter()
+        .map(|candle| MarketEvent::Candle {
+            symbol: symbol.to_string(),
+            candle,
+        })
+        .collect()
+}
+
+/// Candles of the symbol between start_at and end_at (in seconds), oldest first. Kucoin
+/// returns at most 1500 candles per call, so the window is paged backwards from end_at.
+pub async fn load_candles<M: MarketData + ?Sized>(
+    market: &M,
+    symbol: &str,
+    interval: Klines,
+    start_at: i64,
+    end_at: i64,
+) -> Result<Vec<Candle>, APIError> {
+    let mut candles: Vec<Candle> = Vec::new();
+    let mut end = end_at;
+    loop {
+        let batch = market
+            .candles(symbol, interval, Some(start_at), Some(end))
+            .await?;
+        let oldest = match batch.iter().map(|c| c.time).min() {
+            Some(oldest) => oldest,
+            None => break,
+        };
+        candles.extend(batch);
+        if oldest <= start_at || oldest >= end {
+            break;
+        }
+        end = oldest;
+    }
+    candles.sort_by_key(|c| c.time);
+    candles.dedup_by_key(|c| c.time);
+    Ok(candles)
+}
+
+// Where the market of a symbol traded during an event.
+struct Tick {
+    symbol: String,
+    mark: f64,
+    // Prices market buys and sells fill at before slippage
+    take_buy: f64,
+    take_sell: f64,
+    // Whether those are quotes an order at the price takes, rather than traded prices
+    quoted: bool,
+    // Opening price of a candle, which resting orders the market gapped through fill at
+    open: Option<f64>,
+    // Lowest price sellers traded or offered at and the volume there, and the same for buyers
+    low: (f64, f64),
+    high: (f64, f64),
+}
+
+impl Tick {
+    fn from_event(event: &MarketEvent) -> Option<Tick> {
+        match event {
+            MarketEvent::Candle { symbol, candle } => {
+                let open = candle.open.parse().ok()?;
+                let volume = candle.volume.parse().ok()?;
+                Some(Tick {
+                    symbol: symbol.clone(),
+                    mark: candle.close.parse().ok()?,
+                    take_buy: open,
+                    take_sell: open,
+                    quoted: false,
+                    open: Some(open),
+                    low: (candle.low.parse().ok()?, volume),
+                    high: (candle.high.parse().ok()?, volume),
+                })
+            }
+            MarketEvent::Message(KucoinWebsocketMsg::MatchMsg(r)) => {
+                let price = r.data.price.parse().ok()?;
+                let size = r.data.size.parse().ok()?;
+                Some(Tick {
+                    symbol: r.data.symbol.clone(),
+                    mark: price,
+                    take_buy: price,
+                    take_sell: price,
+                    quoted: false,
+                    open: None,
+                    low: (price, size),
+                    high: (price, size),
+                })
+            }
+            MarketEvent::Message(KucoinWebsocketMsg::TickerMsg(r)) => {
+                let ask = r.data.best_ask.parse().ok()?;
+                let bid = r.data.best_bid.parse().ok()?;
+                Some(Tick {
+                    symbol: r.topic.rsplit(':').next()?.to_string(),
+                    mark: r.data.price.parse().ok()?,
+                    take_buy: ask,
+                    take_sell: bid,
+                    quoted: true,
+                    open: None,
+                    low: (ask, r.data.best_ask_size.parse().ok()?),
+                    high: (bid, r.data.best_bid_size.parse().ok()?),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+fn event_time(event: &MarketEvent) -> Option<i64> {
+    match event {
+        MarketEvent::Candle { candle, .. } => Some(candle.time * 1000),
+        MarketEvent::Message(msg) => msg.timestamp().map(|t| t as i64),
+    }
+}
+
+/// Order resting in, or waiting for the next event of, a SimBroker.
+#[derive(Debug, Clone)]
+pub struct SimOrder {
+    pub id: String,
+    pub request: OrderRequest,
+    pub filled: f64,
+    pub created_at: i64,
+    /// Whether the order has been in the book since an earlier event of its symbol
+    pub resting: bool,
+}
+
+/// Simulated account a backtested strategy trades through. Market orders and new limit orders
+/// are matched from the next event of their symbol on, so a strategy cannot trade on the
+/// event it is reacting to. Buys are capped by the cash and sells by the position, with the
+/// rest of the order cancelled.
+pub struct SimBroker {
+    config: BacktestConfig,
+    rules: HashMap<String, SymbolRules>,
+    cash: f64,
+    positions: HashMap<String, f64>,
+    marks: HashMap<String, f64>,
+    orders: Vec<SimOrder>,
+    fills: Vec<FillsInfo>,
+    fees: f64,
+    turnover: f64,
+    next_id: u64,
+    time: i64,
+}
+
+impl SimBroker {
+    fn new(config: BacktestConfig) -> Self {
+        SimBroker {
+            cash: config.initial_cash,
+            config,
+            rules: HashMap::new(),
+            positions: HashMap::new(),
+            marks: HashMap::new(),
+            orders: Vec::new(),
+            fills: Vec::new(),
+            fees: 0.0,
+            turnover: 0.0,
+            next_id: 0,
+            time: 0,
+        }
+    }
+
+    /// Time of the current event in milliseconds since the epoch.
+    pub fn time(&self) -> i64 {
+        self.time
+    }
+
+    pub fn cash(&self) -> f64 {
+        self.cash
+    }
+
+    /// Base currency held of the symbol.
+    pub fn position(&self, symbol: &str) -> f64 {
+        self.positions.get(symbol).copied().unwrap_or_default()
+    }
+
+    /// Last traded price, or candle close, of the symbol.
+    pub fn mark(&self, symbol: &str) -> Option<f64> {
+        self.marks.get(symbol).copied()
+    }
+
+    /// Cash plus the positions valued at their marks.
+    pub fn equity(&self) -> f64 {
+        self.cash
+            + self
+                .positions
+                .iter()
+                .map(|(symbol, size)| size * self.mark(symbol).unwrap_or_default())
+                .sum::<f64>()
+    }
+
+    pub fn open_orders(&self) -> &[SimOrder] {
+        &self.orders
+    }
+
+    pub fn fills(&self) -> &[FillsInfo] {
+        &self.fills
+    }
+
+    /// Queues the order, returning its id. Prices are rounded to the symbol's price increment,
+    /// down for buys and up for sells, and sizes down to its base increment.
+    #[allow(clippy::result_large_err)]
+    pub fn submit(&mut self, order: &OrderRequest) -> Result<String, APIError> {
+        let rules = self.rules.get(&order.symbol).copied().unwrap_or_default();
+        let mut request = order.clone();
+        if let OrderKind::Limit { price, size } = &mut request.kind {
+            let p = parse_positive(price)?;
+            let s = round_down(parse_positive(size)?, rules.base_increment);
+            if s <= 0.0 || s < rules.base_min_size {
+                return Err(APIError::Other(format!(
+                    "Order size {} below the minimum of {}",
+                    size, order.symbol
+                )));
+            }
+            let p = match order.side {
+                Side::Buy => round_down(p, rules.price_increment),
+                Side::Sell => round_up(p, rules.price_increment),
+            };
+            *price = format_amount(p);
+            *size = format_amount(s);
+        }
+        if let OrderKind::Market { size, funds } = &request.kind {
+            match (size, funds) {
+                (Some(s), None) => {
+                    parse_positive(s)?;
+                }
+                (None, Some(f)) => {
+                    parse_positive(f)?;
+                }
+                _ => {
+                    return Err(APIError::Other(
+                        "Market orders need either a size or funds".to_string(),
+                    ))
+                }
+            }
+        }
+        self.next_id += 1;
+        let id = format!("{:024x}", self.next_id);
+        self.orders.push(SimOrder {
+            id: id.clone(),
+            request,
+            filled: 0.0,
+            created_at: self.time,
+            resting: false,
+        });
+        Ok(id)
+    }
+
+    pub fn cancel(&mut self, order_id: &str) -> bool {
+        let before = self.orders.len();
+        self.orders.retain(|o| o.id != order_id);
+        self.orders.len() < before
+    }
+
+    /// Cancels the open orders of the symbol, or all of them.
+    pub fn cancel_all(&mut self, symbol: Option<&str>) {
+        self.orders
+            .retain(|o| symbol.is_some_and(|s| s != o.request.symbol));
+    }
+
+    fn slipped(&self, side: Side, price: f64, rules: &SymbolRules) -> f64 {
+        let sign = match side {
+            Side::Buy => 1.0,
+            Side::Sell => -1.0,
+        };
+        let price = match self.config.slippage {
+            SlippageModel::None => price,
+            SlippageModel::FixedBps(bps) => price * (1.0 + sign * bps / 10_000.0),
+            SlippageModel::Ticks(ticks) => price + sign * ticks as f64 * rules.price_increment,
+        };
+        match side {
+            Side::Buy => round_up(price, rules.price_increment),
+            Side::Sell => round_down(price, rules.price_increment),
+        }
+    }
+
+    // Matches the orders of the tick's symbol, all placed on earlier events. Market, IOC and
+    // FOK orders are cancelled after their first tick. Limit orders crossing the take price on
+    // their first tick fill there as takers, like market orders capped at their price.
+    fn match_tick(&mut self, tick: &Tick) {
+        let rules = self.rules.get(&tick.symbol).copied().unwrap_or_default();
+        let mut open = Vec::new();
+        for mut order in std::mem::take(&mut self.orders) {
+            if order.request.symbol != tick.symbol {
+                open.push(order);
+                continue;
+            }
+            let side = order.request.side;
+            let take = match side {
+                Side::Buy => tick.take_buy,
+                Side::Sell => tick.take_sell,
+            };
+            let (price, qty, liquidity, remaining) = match &order.request.kind {
+                OrderKind::Market { size, funds } => {
+                    let price = self.slipped(side, take, &rules);
+                    let qty = match (size, funds) {
+                        (Some(s), _) => s.parse().unwrap_or_default(),
+                        (_, Some(f)) => f.parse::<f64>().unwrap_or_default() / price,
+                        _ => 0.0,
+                    };
+                    (price, qty, "taker", 0.0)
+                }
+                OrderKind::Limit { price, size } => {
+                    let limit: f64 = price.parse().unwrap_or_default();
+                    let remaining = size.parse::<f64>().unwrap_or_default() - order.filled;
+                    let crosses = match side {
+                        Side::Buy => limit > take || (tick.quoted && limit == take),
+                        Side::Sell => limit < take || (tick.quoted && limit == take),
+                    };
+                    if !order.resting && take > 0.0 && crosses {
+                        let price = match side {
+                            Side::Buy => self.slipped(side, take, &rules).min(limit),
+                            Side::Sell => self.slipped(side, take, &rules).max(limit),
+                        };
+                        (price, remaining, "taker", remaining)
+                    } else {
+                        let (reached, volume) = match side {
+                            Side::Buy => tick.low,
+                            Side::Sell => tick.high,
+                        };
+                        let through = match side {
+                            Side::Buy => reached < limit,
+                            Side::Sell => reached > limit,
+                        };
+                        let qty = match self.config.queue {
+                            _ if !through && reached != limit => 0.0,
+                            QueueModel::Touch => remaining,
+                            QueueModel::TradeThrough if through => remaining,
+                            QueueModel::TradeThrough => 0.0,
+                            QueueModel::VolumeShare(share) => remaining.min(share * volume),
+                        };
+                        // A candle may open through the price, filling at the better open
+                        let price = match (side, tick.open) {
+                            (Side::Buy, Some(open)) => limit.min(open),
+                            (Side::Sell, Some(open)) => limit.max(open),
+                            (_, None) => limit,
+                        };
+                        (price, qty, "maker", remaining)
+                    }
+                }
+            };
+            let filled = self.fill(
+                &order,
+                price,
+                round_down(qty, rules.base_increment),
+                liquidity,
+            );
+            order.filled += filled;
+            let resting = match order.request.time_in_force {
+                Some(TimeInForce::IOC) | Some(TimeInForce::FOK) => false,
+                _ => remaining - filled > 1e-12,
+            };
+            if resting {
+                order.resting = true;
+                open.push(order);
+            }
+        }
+        self.orders = open;
+    }
+
+    // Books a fill of up to qty, capped by the cash or position, returning the filled size.
+    fn fill(&mut self, order: &SimOrder, price: f64, qty: f64, liquidity: &str) -> f64 {
+        if qty <= 0.0 || price <= 0.0 {
+            return 0.0;
+        }
+        let symbol = &order.request.symbol;
+        let fee_rate = match liquidity {
+            "maker" => self.config.maker_fee,
+            _ => self.config.taker_fee,
+        };
+        let increment = self.rules.get(symbol).map_or(0.0, |r| r.base_increment);
+        let qty = match order.request.side {
+            Side::Buy => qty.min(round_down(
+                self.cash.max(0.0) / (price * (1.0 + fee_rate)),
+                increment,
+            )),
+            Side::Sell => qty.min(self.position(symbol)),
+        };
+        if order.request.time_in_force == Some(TimeInForce::FOK) {
+            if let OrderKind::Limit { size, .. } = &order.request.kind {
+                if qty < size.parse::<f64>().unwrap_or_default() - 1e-12 {
+                    return 0.0;
+                }
+            }
+        }
+        if qty <= 1e-12 {
+            return 0.0;
+        }
+        let funds = price * qty;
+        let fee = funds * fee_rate;
+        let position = self.positions.entry(symbol.clone()).or_default();
+        match order.request.side {
+            Side::Buy => {
+                *position += qty;
+                self.cash -= funds + fee;
+            }
+            Side::Sell => {
+                *position -= qty;
+                self.cash += funds - fee;
+            }
+        }
+        self.fees += fee;
+        self.turnover += funds;
+        self.next_id += 1;
+        let quote = symbol.rsplit('-').next().unwrap_or_default().to_string();
+        self.fills.push(FillsInfo {
+            symbol: symbol.clone(),
+            trade_id: format!("{:024x}", self.next_id),
+            order_id: order.id.clone(),
+            counter_order_id: String::new(),
+            side: order.request.side.as_str().to_string(),
+            liquidity: liquidity.to_string(),
+            force_taker: false,
+            price: format_amount(price),
+            size: format_amount(qty),
+            funds: format_amount(funds),
+            fee: format_amount(fee),
+            fee_rate: format_amount(fee_rate),
+            fee_currency: quote,
+            stop: String::new(),
+            r#type: match order.request.kind {
+                OrderKind::Limit { .. } => "limit".to_string(),
+                OrderKind::Market { .. } => "market".to_string(),
+            },
+            created_at: self.time,
+            trade_type: "TRADE".to_string(),
+        });
+        qty
+    }
+}
+
+#[allow(clippy::result_large_err)]
+fn parse_positive(s: &str) -> Result<f64, APIError> {
+    s.parse::<f64>()
+        .ok()
+        .filter(|a| *a > 0.0 && a.is_finite())
+        .ok_or_else(|| APIError::Other(format!("Invalid order amount {}", s)))
+}
+
+/// Strategy driven by a Backtester, called once per event after the orders placed on earlier
+/// events have been matched against it. Implemented by closures taking the same arguments.
+pub trait BacktestStrategy {
+    fn on_event(&mut self, event: &MarketEvent, broker: &mut SimBroker);
+}
+
+impl<F: FnMut(&MarketEvent, &mut SimBroker)> BacktestStrategy for F {
+    fn on_event(&mut self, event: &MarketEvent, broker: &mut SimBroker) {
+        self(event, broker)
+    }
+}
+
+/// Outcome of a backtest. Amounts are in the quote currency.
+#[derive(Debug, Clone)]
+pub struct BacktestReport {
+    pub initial_equity: f64,
+    pub final_equity: f64,
+    /// Change in equity, net of fees
+    pub pnl: f64,
+    /// Largest fall of the equity from a previous peak
+    pub max_drawdown: f64,
+    /// Largest fall of the equity as a fraction of the peak it fell from, which need not be
+    /// the fall of max_drawdown
+    pub max_drawdown_pct: f64,
+    /// Notional value of every fill
+    pub turnover: f64,
+    pub fees: f64,
+    pub fills: Vec<FillsInfo>,
+    /// Equity after each event with its time in milliseconds
+    pub equity_curve: Vec<(i64, f64)>,
+}
+
+/// Runs a BacktestStrategy over historical candles or recorded websocket messages.
+///
+/// ```ignore
+/// let candles = load_candles(&api, "BTC-USDT", Klines::K1hour, start, end).await?;
+/// let config = BacktestConfig::new().queue(QueueModel::Touch).build();
+/// let mut backtest = Backtester::new(config, |event: &MarketEvent, broker: &mut SimBroker| {
+///     if broker.position("BTC-USDT") == 0.0 {
+///         let _ = broker.submit(&OrderRequest::market("1", "BTC-USDT", Side::Buy, "0.01"));
+///     }
+/// });
+/// backtest.symbol(&btc_usdt);
+/// let report = backtest.run(candle_events("BTC-USDT", candles));
+/// ```
+pub struct Backtester<S> {
+    broker: SimBroker,
+    strategy: S,
+    peak: f64,
+    max_drawdown: f64,
+    max_drawdown_pct: f64,
+    equity_curve: Vec<(i64, f64)>,
+}
+
+impl<S: BacktestStrategy> Backtester<S> {
+    pub fn new(config: BacktestConfig, strategy: S) -> Self {
+        let peak = config.initial_cash;
+        Backtester {
+            broker: SimBroker::new(config),
+            strategy,
+            peak,
+            max_drawdown: 0.0,
+            max_drawdown_pct: 0.0,
+            equity_curve: Vec::new(),
+        }
+    }
+
+    /// Rounds orders of the symbol to its increments. Symbols without rules are not rounded.
+    pub fn symbol(&mut self, symbol: &SymbolList) -> &mut Self {
+        if let Some(rules) = SymbolRules::from_symbol(symbol) {
+            self.broker.rules.insert(symbol.symbol.clone(), rules);
+        }
+        self
+    }
+
+    pub fn broker(&self) -> &SimBroker {
+        &self.broker
+    }
+
+    pub fn strategy(&self) -> &S {
+        &self.strategy
+    }
+
+    /// Matches open orders against the event, then hands it to the strategy.
+    pub fn step(&mut self, event: &MarketEvent) {
+        if let Some(time) = event_time(event) {
+            self.broker.time = self.broker.time.max(time);
+        }
+        if let Some(tick) = Tick::from_event(event) {
+            self.broker.match_tick(&tick);
+            self.broker.marks.insert(tick.symbol, tick.mark);
+        }
+        self.strategy.on_event(event, &mut self.broker);
+
+        let equity = self.broker.equity();
+        self.equity_curve.push((self.broker.time, equity));
+        self.peak = self.peak.max(equity);
+        let drawdown = self.peak - equity;
+        self.max_drawdown = self.max_drawdown.max(drawdown);
+        if self.peak > 0.0 {
+            self.max_drawdown_pct = self.max_drawdown_pct.max(drawdown / self.peak);
+        }
+    }
+
+    pub fn run<I: IntoIterator<Item = MarketEvent>>(mut self, events: I) -> BacktestReport {
+        for event in events {
+            self.step(&event);
+        }
+        self.report()
+    }
+
+    /// Runs over a stream of websocket messages such as a Replay, skipping errors.
+    pub async fn run_stream<St>(mut self, stream: St) -> BacktestReport
+    where
+        St: Stream<Item = Result<KucoinWebsocketMsg, APIError>>,
+    {
+        pin_mut!(stream);
+        while let Some(msg) = stream.next().await {
+            if let Ok(msg) = msg {
+                self.step(&MarketEvent::Message(msg));
+            }
+        }
+        self.report()
+    }
+
+    pub fn report(&self) -> BacktestReport {
+        let initial_equity = self.broker.config.initial_cash;
+        let final_equity = self.broker.equity();
+        BacktestReport {
+            initial_equity,
+            final_equity,
+            pnl: final_equity - initial_equity,
+            max_drawdown: self.max_drawdown,
+            max_drawdown_pct: self.max_drawdown_pct,
+            turnover: self.broker.turnover,
+            fees: self.broker.fees,
+            fills: self.broker.fills.clone(),
+            equity_curve: self.equity_curve.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::kucoin::backtest::{
+        candle_events, load_candles, BacktestConfig, Backtester, MarketEvent, QueueModel,
+        SimBroker, SlippageModel,
+    };
+    use crate::kucoin::error::APIError;
+    use crate::kucoin::exchange::{MarketData, OrderRequest, Side};
+    use crate::kucoin::model::market::{
+        Candle, Klines, OrderBook, OrderBookType, SymbolList, Ticker, TradeHistories,
+    };
+    use crate::kucoin::websocket::parse_message;
+    use async_trait::async_trait;
+    use tokio_tungstenite::tungstenite::Message;
+
+    fn candle(time: i64, open: &str, close: &str, high: &str, low: &str) -> Candle {
+        Candle {
+            time,
+            open: open.to_string(),
+            close: close.to_string(),
+            high: high.to_string(),
+            low: low.to_string(),
+            volume: "10".to_string(),
+            turnover: "1000".to_string(),
+        }
+    }
+
+    fn btc_usdt() -> SymbolList {
+        SymbolList {
+            symbol: "BTC-USDT".to_string(),
+            name: "BTC-USDT".to_string(),
+            base_currency: "BTC".to_string(),
+            quote_currency: "USDT".to_string(),
+            base_min_size: "0.01".to_string(),
+            base_max_size: "10000".to_string(),
+            quote_max_size: "99999999".to_string(),
+            base_increment: "0.001".to_string(),
+            quote_increment: "0.01".to_string(),
+            price_increment: "0.1".to_string(),
+            fee_currency: "USDT".to_string(),
+            enable_trading: true,
+            is_margin_enabled: true,
+        }
+    }
+
+    #[test]
+    fn candles_drive_strategy_fills_and_report() {
+        let config = BacktestConfig::new()
+            .slippage(SlippageModel::Ticks(1))
+            .build();
+        let mut backtest = Backtester::new(config, |_: &MarketEvent, broker: &mut SimBroker| {
+            let position = broker.position("BTC-USDT");
+            if broker.fills().is_empty() && broker.open_orders().is_empty() {
+                let tiny = OrderRequest::limit("0", "BTC-USDT", Side::Buy, "90", "0.001");
+                assert!(broker.submit(&tiny).is_err());
+                let buy = OrderRequest::market("1", "BTC-USDT", Side::Buy, "1.23456");
+                broker.submit(&buy).unwrap();
+            } else if position > 0.0 && broker.open_orders().is_empty() {
+                let sell = OrderRequest::limit("2", "BTC-USDT", Side::Sell, "110.05", "1.234");
+                broker.submit(&sell).unwrap();
+            }
+        });
+        backtest.symbol(&btc_usdt());
+        let candles = vec![
+            candle(60, "100", "100", "101", "99"),
+            candle(120, "102", "104", "105", "101"),
+            candle(180, "104", "96", "106", "95"),
+            candle(240, "96", "110", "111", "95"),
+        ];
+        let report = backtest.run(candle_events("BTC-USDT", candles));
+
+        // Bought at the next open plus a tick of slippage, sold once the price traded through
+        let fills: Vec<_> = report
+            .fills
+            .iter()
+            .map(|f| (f.price.as_str(), f.size.as_str(), f.liquidity.as_str()))
+            .collect();
+        assert_eq!(
+            fills,
+            vec![("102.1", "1.234", "taker"), ("110.1", "1.234", "maker")]
+        );
+        assert_eq!(format!("{:.4}", report.pnl), "9.6101");
+        assert_eq!(format!("{:.4}", report.turnover), "261.8548");
+        assert_eq!(format!("{:.4}", report.fees), "0.2619");
+        assert_eq!(format!("{:.3}", report.max_drawdown), "9.872");
+        assert_eq!(report.equity_curve.len(), 4);
+        assert_eq!(report.equity_curve[3].0, 240_000);
+    }
+
+    #[test]
+    fn crossing_limits_take_and_resting_limits_make() {
+        let config = BacktestConfig::new()
+            .maker_fee(0.0)
+            .taker_fee(0.002)
+            .build();
+        let mut backtest = Backtester::new(config, |_: &MarketEvent, broker: &mut SimBroker| {
+            if broker.time() == 60_000 {
+                let crossing = OrderRequest::limit("1", "BTC-USDT", Side::Buy, "105", "1");
+                broker.submit(&crossing).unwrap();
+                let resting = OrderRequest::limit("2", "BTC-USDT", Side::Buy, "95", "1");
+                broker.submit(&resting).unwrap();
+            }
+        });
+        backtest.symbol(&btc_usdt());
+        let candles = vec![
+            candle(60, "100", "100", "101", "99"),
+            candle(120, "101", "100", "102", "99"),
+            candle(180, "94", "96", "97", "93"),
+        ];
+        let report = backtest.run(candle_events("BTC-USDT", candles));
+
+        // The crossing buy fills at the open it crossed, the resting one at the open it gapped
+        let fills: Vec<_> = report
+            .fills
+            .iter()
+            .map(|f| (f.price.as_str(), f.fee.as_str(), f.liquidity.as_str()))
+            .collect();
+        assert_eq!(fills, vec![("101", "0.202", "taker"), ("94", "0", "maker")]);
+    }
+
+    #[test]
+    fn drawdown_pct_is_tracked_apart_from_the_largest_drawdown() {
+        let config = BacktestConfig::new()
+            .initial_cash(200.0)
+            .maker_fee(0.0)
+            .taker_fee(0.0)
+            .build();
+        let mut backtest = Backtester::new(config, |_: &MarketEvent, broker: &mut SimBroker| {
+            if broker.fills().is_empty() && broker.open_orders().is_empty() {
+                let buy = OrderRequest::market("1", "BTC-USDT", Side::Buy, "1");
+                broker.submit(&buy).unwrap();
+            }
+        });
+        backtest.symbol(&btc_usdt());
+        let candles = vec![
+            candle(60, "100", "100", "100", "100"),
+            candle(120, "100", "100", "100", "100"),
+            candle(180, "100", "50", "100", "50"),
+            candle(240, "50", "1000", "1000", "50"),
+            candle(300, "1000", "900", "1000", "900"),
+        ];
+        let report = backtest.run(candle_events("BTC-USDT", candles));
+
+        // 200 fell to 150 by 25%, then 1100 to 1000 by a larger 100 but only 9%
+        assert_eq!(format!("{:.2}", report.max_drawdown), "100.00");
+        assert_eq!(format!("{:.2}", report.max_drawdown_pct), "0.25");
+    }
+
+    struct Klines5;
+
+    #[async_trait]
+    impl MarketData for Klines5 {
+        async fn symbols(&self, _: Option<&str>) -> Result<Vec<SymbolList>, APIError> {
+            Ok(vec![btc_usdt()])
+        }
+
+        async fn ticker(&self, _: &str) -> Result<Ticker, APIError> {
+            Err(APIError::Other("No ticker".to_string()))
+        }
+
+        async fn order_book(&self, _: &str, _: OrderBookType) -> Result<OrderBook, APIError> {
+            Err(APIError::Other("No order book".to_string()))
+        }
+
+        async fn trades(&self, _: &str) -> Result<Vec<TradeHistories>, APIError> {
+            Ok(Vec::new())
+        }
+
+        // Newest first and at most two per call
+        async fn candles(
+            &self,
+            _: &str,
+            _: Klines,
+            start_at: Option<i64>,
+            end_at: Option<i64>,
+        ) -> Result<Vec<Candle>, APIError> {
+            Ok((1..=5)
+                .rev()
+                .map(|i| i * 60)
+                .filter(|t| *t >= start_at.unwrap() && *t < end_at.unwrap())
+                .take(2)
+                .map(|t| candle(t, "100", "100", "100", "100"))
+                .collect())
+        }
+    }
+
+    #[tokio::test]
+    #[allow(clippy::result_large_err)]
+    async fn replayed_trades_fill_by_volume_share() {
+        let candles = load_candles(&Klines5, "BTC-USDT", Klines::K1min, 60, 600)
+            .await
+            .unwrap();
+        let times: Vec<_> = candles.iter().map(|c| c.time).collect();
+        assert_eq!(times, vec![60, 120, 180, 240, 300]);
+
+        let config = BacktestConfig::new()
+            .initial_cash(1000.0)
+            .queue(QueueModel::VolumeShare(0.5))
+            .build();
+        let backtest = Backtester::new(config, |_: &MarketEvent, broker: &mut SimBroker| {
+            if broker.open_orders().is_empty() && broker.fills().is_empty() {
+                let buy = OrderRequest::limit("1", "BTC-USDT", Side::Buy, "100", "3");
+                broker.submit(&buy).unwrap();
+            }
+        });
+        let trade = |price: &str, size: &str| {
+            let frame = format!(
+                r#"{{"type":"message","topic":"/market/match:BTC-USDT","subject":"trade.l3match","data":{{"sequence":"1","type":"match","symbol":"BTC-USDT","side":"sell","price":"{}","size":"{}","tradeId":"1","takerOrderId":"2","makerOrderId":"3","time":"1545913818099033203"}}}}"#,
+                price, size
+            );
+            parse_message(Message::Text(frame))
+        };
+        let trades = vec![
+            trade("101", "1"),
+            trade("100", "2"),
+            trade("99", "4"),
+            trade("99", "4"),
+        ];
+        let report = backtest.run_stream(futures::stream::iter(trades)).await;
+        let sizes: Vec<_> = report.fills.iter().map(|f| f.size.as_str()).collect();
+        assert_eq!(sizes, vec!["1", "2"]);
+        assert!(report.fills.iter().all(|f| f.price == "100"));
+    }
+}
@@ -0,0 +1,456 @@
+use std::collections::{HashMap, VecDeque};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::Stream;
+use tokio::sync::Notify;
+
+use super::error::APIError;
+use super::model::websocket::KucoinWebsocketMsg;
+use super::utils::get_time;
+use super::websocket::topic_matches;
+
+/// How the messages of a topic are buffered while the consumer is behind. Limits apply to
+/// each symbol of the topic separately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BufferPolicy {
+    /// Buffer every message.
+    #[default]
+    Unbounded,
+    /// Buffer up to n messages, then stop reading the connection until the consumer catches
+    /// up. Other topics on the same connection wait as well, and so do Kucoin's replies to
+    /// subscribe and unsubscribe requests sent over it, which fail after ACK_TIMEOUT unless the
+    /// consumer keeps reading meanwhile.
+    Block(usize),
+    /// Buffer up to n messages, dropping the oldest to make room for new ones.
+    DropOldest(usize),
+    /// Buffer only the latest message of each symbol and subject.
+    Conflate,
+}
+
+/// Delivery counters and lag of the messages of a topic, e.g. /market/level2:BTC-USDT.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TopicMetrics {
+    pub received: u64,
+    pub delivered: u64,
+    pub dropped: u64,
+    pub conflated: u64,
+    /// Messages waiting for the consumer
+    pub queued: usize,
+    pub max_queued: usize,
+    /// Milliseconds from the exchange timestamp of the message, or its receive time when it
+    /// has none, to its delivery
+    pub last_lag_ms: i64,
+    pub max_lag_ms: i64,
+    /// Milliseconds from the receipt of the message to its delivery
+    pub last_queue_ms: u64,
+    pub max_queue_ms: u64,
+}
+
+/// Reported on the stream returned by KucoinWebsocket::lag_events for each message delivered
+/// later than the threshold.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LagEvent {
+    pub topic: String,
+    pub lag_ms: i64,
+    pub queue_ms: u64,
+    pub queued: usize,
+}
+
+// Policies, metrics and lag listeners shared by every queue of a KucoinWebsocket.
+#[derive(Default)]
+pub(crate) struct Buffering {
+    policies: Mutex<Vec<(String, BufferPolicy)>>,
+    metrics: Mutex<HashMap<String, TopicMetrics>>,
+    lag: Mutex<Vec<(u64, UnboundedSender<LagEvent>)>>,
+}
+
+impl Buffering {
+    pub(crate) fn set_policy(&self, topic: String, policy: BufferPolicy) {
+        let mut policies = self.policies.lock().unwrap();
+        policies.retain(|(t, _)| *t != topic);
+        if policy != BufferPolicy::Unbounded {
+            policies.push((topic, policy));
+        }
+    }
+
+    pub(crate) fn metrics(&self) -> HashMap<String, TopicMetrics> {
+        self.metrics.lock().unwrap().clone()
+    }
+
+    pub(crate) fn lag_events(&self, threshold_ms: u64) -> UnboundedReceiver<LagEvent> {
+        let (tx, rx) = mpsc::unbounded();
+        self.lag.lock().unwrap().push((threshold_ms, tx));
+        rx
+    }
+
+    // The policy set last for a subscribed topic covering the message topic.
+    fn policy(&self, topic: &str) -> BufferPolicy {
+        self.policies
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .find(|(t, _)| topic_matches(t, topic))
+            .map_or(BufferPolicy::Unbounded, |(_, p)| *p)
+    }
+
+    fn update<F: FnOnce(&mut TopicMetrics)>(&self, topic: &str, f: F) {
+        let mut metrics = self.metrics.lock().unwrap();
+        match metrics.get_mut(topic) {
+            Some(m) => f(m),
+            None => f(metrics.entry(topic.to_string()).or_default()),
+        }
+    }
+
+    fn delivered(&self, entry: &Entry) {
+        let topic = match &entry.topic {
+            Some(topic) => topic,
+            None => return,
+        };
+        let now = get_time() as u64;
+        let queue_ms = now.saturating_sub(entry.received);
+        let lag_ms = now as i64 - entry.ts.unwrap_or(entry.received) as i64;
+        let mut queued = 0;
+        self.update(topic, |m| {
+            m.delivered += 1;
+            m.queued = m.queued.saturating_sub(1);
+            m.last_lag_ms = lag_ms;
+            m.max_lag_ms = m.max_lag_ms.max(lag_ms);
+            m.last_queue_ms = queue_ms;
+            m.max_queue_ms = m.max_queue_ms.max(queue_ms);
+            queued = m.queued;
+        });
+        let mut lag = self.lag.lock().unwrap();
+        lag.retain(|(_, tx)| !tx.is_closed());
+        for (_, tx) in lag.iter().filter(|(t, _)| lag_ms > *t as i64) {
+            let _ = tx.unbounded_send(LagEvent {
+                topic: topic.clone(),
+                lag_ms,
+                queue_ms,
+                queued,
+            });
+        }
+    }
+}
+
+struct Entry {
+    topic: Option<String>,
+    received: u64,
+    ts: Option<u64>,
+    msg: Result<KucoinWebsocketMsg, APIError>,
+}
+
+impl Entry {
+    fn subject(&self) -> Option<&str> {
+        self.msg.as_ref().ok().and_then(|m| m.subject())
+    }
+}
+
+enum Offer {
+    Queued,
+    Dropped,
+    Conflated,
+}
+
+struct State {
+    entries: VecDeque<Entry>,
+    queued: HashMap<String, usize>,
+    senders: usize,
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+impl State {
+    // Buffers the entry under the policy, or hands it back when it has to wait for room.
+    #[allow(clippy::result_large_err)]
+    fn offer(&mut self, entry: Entry, policy: BufferPolicy) -> Result<Offer, Entry> {
+        let queued = entry
+            .topic
+            .as_ref()
+            .and_then(|t| self.queued.get(t))
+            .map_or(0, |n| *n);
+        match policy {
+            BufferPolicy::Block(cap) if queued >= cap.max(1) => return Err(entry),
+            BufferPolicy::DropOldest(cap) if queued >= cap.max(1) => {
+                if let Some(i) = self.entries.iter().position(|e| e.topic == entry.topic) {
+                    self.entries.remove(i);
+                    self.entries.push_back(entry);
+                    return Ok(Offer::Dropped);
+                }
+            }
+            BufferPolicy::Conflate => {
+                let latest = self
+                    .entries
+                    .iter()
+                    .position(|e| e.topic == entry.topic && e.subject() == entry.subject());
+                if let Some(i) = latest {
+                    self.entries[i] = entry;
+                    return Ok(Offer::Conflated);
+                }
+            }
+            _ => {}
+        }
+        if let Some(topic) = &entry.topic {
+            *self.queued.entry(topic.clone()).or_default() += 1;
+        }
+        self.entries.push_back(entry);
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+        Ok(Offer::Queued)
+    }
+
+    fn pop(&mut self) -> Option<Entry> {
+        let entry = self.entries.pop_front()?;
+        if let Some(topic) = &entry.topic {
+            if let Some(n) = self.queued.get_mut(topic) {
+                *n -= 1;
+                if *n == 0 {
+                    self.queued.remove(topic);
+                }
+            }
+        }
+        Some(entry)
+    }
+}
+
+struct Shared {
+    state: Mutex<State>,
+    space: Notify,
+    buffering: Arc<Buffering>,
+}
+
+// Sending half of the queue of a KucoinWebsocket or TopicStream.
+pub(crate) struct QueueSender {
+    shared: Arc<Shared>,
+}
+
+// Receiving half of a queue, yields the buffered messages in order. Ends once every
+// QueueSender is dropped.
+pub(crate) struct QueueReceiver {
+    shared: Arc<Shared>,
+}
+
+pub(crate) fn queue(buffering: Arc<Buffering>) -> (QueueSender, QueueReceiver) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            entries: VecDeque::new(),
+            queued: HashMap::new(),
+            senders: 1,
+            closed: false,
+            waker: None,
+        }),
+        space: Notify::new(),
+        buffering,
+    });
+    (
+        QueueSender {
+            shared: shared.clone(),
+        },
+        QueueReceiver { shared },
+    )
+}
+
+impl QueueSender {
+    // Buffers a message received at the time (ms since the epoch) under its topic's policy,
+    // waiting for room when the policy blocks. Returns false once the receiver is dropped.
+    pub(crate) async fn send(
+        &self,
+        msg: Result<KucoinWebsocketMsg, APIError>,
+        received: u64,
+    ) -> bool {
+        let buffering = &self.shared.buffering;
+        let (topic, ts) = match &msg {
+            Ok(m) => (m.topic().map(str::to_string), m.timestamp()),
+            Err(_) => (None, None),
+        };
+        let policy = match &topic {
+            Some(topic) => {
+                buffering.update(topic, |m| m.received += 1);
+                buffering.policy(topic)
+            }
+            None => BufferPolicy::Unbounded,
+        };
+        let mut entry = Entry {
+            topic,
+            received,
+            ts,
+            msg,
+        };
+        loop {
+            let space = self.shared.space.notified();
+            {
+                let mut state = self.shared.state.lock().unwrap();
+                if state.closed {
+                    return false;
+                }
+                let topic = entry.topic.clone();
+                match state.offer(entry, policy) {
+                    Ok(offer) => {
+                        drop(state);
+                        if let Some(topic) = topic {
+                            buffering.update(&topic, |m| match offer {
+                                Offer::Queued => {
+                                    m.queued += 1;
+                                    m.max_queued = m.max_queued.max(m.queued);
+                                }
+                                Offer::Dropped => m.dropped += 1,
+                                Offer::Conflated => m.conflated += 1,
+                            });
+                        }
+                        return true;
+                    }
+                    Err(full) => entry = full,
+                }
+            }
+            space.await;
+        }
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.shared.state.lock().unwrap().closed
+    }
+}
+
+impl Clone for QueueSender {
+    fn clone(&self) -> Self {
+        self.shared.state.lock().unwrap().senders += 1;
+        QueueSender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl Drop for QueueSender {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.senders -= 1;
+        if state.senders == 0 {
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl Stream for QueueReceiver {
+    type Item = Result<KucoinWebsocketMsg, APIError>;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let shared = &self.shared;
+        let mut state = shared.state.lock().unwrap();
+        match state.pop() {
+            Some(entry) => {
+                drop(state);
+                shared.space.notify_waiters();
+                shared.buffering.delivered(&entry);
+                Poll::Ready(Some(entry.msg))
+            }
+            None if state.senders == 0 => Poll::Ready(None),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for QueueReceiver {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.closed = true;
+        let entries: Vec<Entry> = state.entries.drain(..).collect();
+        state.queued.clear();
+        drop(state);
+        self.shared.space.notify_waiters();
+        for topic in entries.iter().filter_map(|e| e.topic.as_ref()) {
+            self.shared
+                .buffering
+                .update(topic, |m| m.queued = m.queued.saturating_sub(1));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::kucoin::buffer::{queue, BufferPolicy, Buffering};
+    use crate::kucoin::model::websocket::{KucoinWebsocketMsg, WSTopic};
+    use crate::kucoin::websocket::parse_message;
+    use futures::{FutureExt, StreamExt};
+    use std::sync::Arc;
+    use tokio_tungstenite::tungstenite::Message;
+
+    fn matched(symbol: &str, price: &str) -> KucoinWebsocketMsg {
+        let frame = format!(
+            r#"{{"type":"message","topic":"/market/match:{0}","subject":"trade.l3match","data":{{"sequence":"1545896669145","type":"match","symbol":"{0}","side":"buy","price":"{1}","size":"0.0102","tradeId":"5c24c5da03aa673885cd67aa","takerOrderId":"5c24c5d903aa6772d55b371e","makerOrderId":"5c2187d003aa677bd09d5c93","time":"1545913818099033203"}}}}"#,
+            symbol, price
+        );
+        parse_message(Message::Text(frame)).unwrap()
+    }
+
+    fn price(msg: Option<Result<KucoinWebsocketMsg, crate::kucoin::error::APIError>>) -> String {
+        match msg {
+            Some(Ok(KucoinWebsocketMsg::MatchMsg(m))) => m.data.price,
+            m => panic!("Unexpected message {:?}", m),
+        }
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_and_conflate_per_symbol() {
+        let buffering = Arc::new(Buffering::default());
+        let pairs = vec!["BTC-USDT".to_string(), "ETH-USDT".to_string()];
+        buffering.set_policy(WSTopic::Match(pairs).topic(), BufferPolicy::DropOldest(2));
+        let (tx, mut rx) = queue(buffering.clone());
+        for p in &["1", "2", "3"] {
+            assert!(tx.send(Ok(matched("BTC-USDT", p)), 0).await);
+        }
+        assert!(tx.send(Ok(matched("ETH-USDT", "9")), 0).await);
+        assert_eq!(price(rx.next().await), "2");
+        assert_eq!(price(rx.next().await), "3");
+        assert_eq!(price(rx.next().await), "9");
+
+        let metrics = buffering.metrics();
+        let btc = &metrics["/market/match:BTC-USDT"];
+        assert_eq!((btc.received, btc.delivered, btc.dropped), (3, 2, 1));
+        assert_eq!(btc.queued, 0);
+        assert_eq!(btc.max_queued, 2);
+
+        buffering.set_policy(
+            WSTopic::Match(vec!["BTC-USDT".to_string()]).topic(),
+            BufferPolicy::Conflate,
+        );
+        for p in &["4", "5", "6"] {
+            assert!(tx.send(Ok(matched("BTC-USDT", p)), 0).await);
+        }
+        assert_eq!(price(rx.next().await), "6");
+        assert_eq!(buffering.metrics()["/market/match:BTC-USDT"].conflated, 2);
+    }
+
+    #[tokio::test]
+    async fn block_waits_for_consumer_and_reports_lag() {
+        let buffering = Arc::new(Buffering::default());
+        buffering.set_policy(
+            WSTopic::Match(vec!["BTC-USDT".to_string()]).topic(),
+            BufferPolicy::Block(1),
+        );
+        let mut lag = buffering.lag_events(1_000);
+        let (tx, mut rx) = queue(buffering.clone());
+        assert!(tx.send(Ok(matched("BTC-USDT", "1")), 0).await);
+
+        let mut blocked = Box::pin(tx.send(Ok(matched("BTC-USDT", "2")), 0));
+        assert!((&mut blocked).now_or_never().is_none());
+        assert_eq!(price(rx.next().await), "1");
+        assert!(blocked.await);
+        assert_eq!(price(rx.next().await), "2");
+
+        // The match time is from 2018 so both deliveries are far behind the exchange
+        let event = lag.next().await.unwrap();
+        assert_eq!(event.topic, "/market/match:BTC-USDT");
+        assert!(event.lag_ms > 1_000);
+
+        drop(rx);
+        assert!(!tx.send(Ok(matched("BTC-USDT", "3")), 0).await);
+    }
+}
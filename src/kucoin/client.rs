@@ -42,8 +42,8 @@ pub enum KucoinEnv {
 #[derive(Debug, Clone)]
 pub struct Kucoin {
     credentials: Option<Credentials>,
-    environment: KucoinEnv,
     pub prefix: String,
+    pub futures_prefix: String,
     pub client: reqwest::Client,
 }
 
@@ -60,10 +60,14 @@ impl Kucoin {
             KucoinEnv::Live => String::from("https://api.kucoin.com"),
             KucoinEnv::Sandbox => String::from("https://openapi-sandbox.kucoin.com"),
         };
+        let futures_prefix = match environment {
+            KucoinEnv::Live => String::from("https://api-futures.kucoin.com"),
+            KucoinEnv::Sandbox => String::from("https://api-sandbox-futures.kucoin.com"),
+        };
         Ok(Kucoin {
             credentials,
-            environment,
             prefix,
+            futures_prefix,
             client,
         })
     }
@@ -79,19 +83,11 @@ impl Kucoin {
         match sign {
             Some(sign) => {
                 let resp = self.client.get(req_url).headers(sign).send().await?;
-                if resp.status().is_success() {
-                    Ok(resp)
-                } else {
-                    Ok(resp)
-                }
+                Ok(resp)
             }
             None => {
                 let resp = self.client.get(req_url).send().await?;
-                if resp.status().is_success() {
-                    Ok(resp)
-                } else {
-                    Ok(resp)
-                }
+                Ok(resp)
             }
         }
     }
@@ -112,26 +108,39 @@ impl Kucoin {
                     .json(&json!(p))
                     .send()
                     .await?;
-                if resp.status().is_success() {
-                    Ok(resp)
-                } else {
-                    Ok(resp)
-                }
+                Ok(resp)
             } else {
                 let resp = self.client.post(req_url).headers(s).send().await?;
-                if resp.status().is_success() {
-                    Ok(resp)
-                } else if resp.status().is_server_error() {
-                    Ok(resp)
-                } else {
-                    Ok(resp)
-                }
+                Ok(resp)
             }
         } else {
             panic!("Unsigned POST request...")
         }
     }
 
+    // POST request for endpoints whose body is not a flat map of strings,
+    // such as batch order placement. Pair with sign_headers_json.
+    pub async fn post_json(
+        &self,
+        url: String,
+        sign: Option<HeaderMap>,
+        body: serde_json::Value,
+    ) -> Result<reqwest::Response, APIError> {
+        let req_url = reqwest::Url::parse(&url).unwrap();
+        if let Some(s) = sign {
+            let resp = self
+                .client
+                .post(req_url)
+                .headers(s)
+                .json(&body)
+                .send()
+                .await?;
+            Ok(resp)
+        } else {
+            panic!("Unsigned POST request...")
+        }
+    }
+
     pub async fn delete(
         &self,
         url: String,
@@ -140,13 +149,7 @@ impl Kucoin {
         let req_url = reqwest::Url::parse(&url).unwrap();
         if let Some(s) = sign {
             let resp = self.client.delete(req_url).headers(s).send().await?;
-            if resp.status().is_success() {
-                Ok(resp)
-            } else if resp.status().is_server_error() {
-                Ok(resp)
-            } else {
-                Ok(resp)
-            }
+            Ok(resp)
         } else {
             panic!("Unsigned DELETE request...")
         }
@@ -159,20 +162,8 @@ impl Kucoin {
         query: Option<String>,
         method: Method,
     ) -> Result<HeaderMap, failure::Error> {
-        let mut headers = HeaderMap::new();
         let nonce = get_time().to_string();
-        let mut api_key: &str = "";
-        let mut secret_key: &str = "";
-        let mut passphrase: &str = "";
         let mut str_to_sign: String = String::new();
-        match &self.credentials {
-            Some(c) => {
-                api_key = &c.api_key;
-                secret_key = &c.secret_key;
-                passphrase = &c.passphrase;
-            }
-            None => (),
-        }
         match method {
             Method::GET => {
                 let meth = "GET";
@@ -203,19 +194,44 @@ impl Kucoin {
                 }
             }
         }
-        let mut hmac_sign = HmacSha256::new_varkey(secret_key.as_bytes()).expect("HMAC can take key of any size");
+        Ok(self.signed_headers(&nonce, &str_to_sign))
+    }
+
+    /// Signs a POST request whose body is sent as provided through post_json.
+    pub fn sign_headers_json(
+        &self,
+        endpoint: String,
+        body: &serde_json::Value,
+    ) -> Result<HeaderMap, failure::Error> {
+        let nonce = get_time().to_string();
+        let str_to_sign = format!("{}{}{}{}", nonce, "POST", endpoint, body);
+        Ok(self.signed_headers(&nonce, &str_to_sign))
+    }
+
+    fn signed_headers(&self, nonce: &str, str_to_sign: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let mut api_key: &str = "";
+        let mut secret_key: &str = "";
+        let mut passphrase: &str = "";
+        if let Some(c) = &self.credentials {
+            api_key = &c.api_key;
+            secret_key = &c.secret_key;
+            passphrase = &c.passphrase;
+        }
+        let mut hmac_sign =
+            HmacSha256::new_varkey(secret_key.as_bytes()).expect("HMAC can take key of any size");
         hmac_sign.input(str_to_sign.as_bytes());
         let sign_result = hmac_sign.result();
         let sign_bytes = sign_result.code();
-        let sign_digest = encode(&sign_bytes);
+        let sign_digest = encode(sign_bytes);
         let mut hmac_passphrase = HmacSha256::new_varkey(secret_key.as_bytes()).expect("HMAC can take key of any size");
         hmac_passphrase.input(passphrase.as_bytes());
         let passphrase_result = hmac_passphrase.result();
         let passphrase_bytes = passphrase_result.code();
-        let passphrase_digest = encode(&passphrase_bytes);
+        let passphrase_digest = encode(passphrase_bytes);
         headers.insert(
             HeaderName::from_static("kc-api-key"),
-            HeaderValue::from_str(&api_key).unwrap(),
+            HeaderValue::from_str(api_key).unwrap(),
         );
         headers.insert(
             HeaderName::from_static("kc-api-sign"),
@@ -223,7 +239,7 @@ impl Kucoin {
         );
         headers.insert(
             HeaderName::from_static("kc-api-timestamp"),
-            HeaderValue::from_str(&nonce).unwrap(),
+            HeaderValue::from_str(nonce).unwrap(),
         );
         headers.insert(
             HeaderName::from_static("kc-api-passphrase"),
@@ -233,6 +249,6 @@ impl Kucoin {
             HeaderName::from_static("kc-api-key-version"),
             HeaderValue::from_str("2").unwrap(),
         );
-        Ok(headers)
+        headers
     }
 }
@@ -1,3 +1,6 @@
+// failure's Fail derive expands to impls inside a const item, which newer compilers lint
+#![allow(non_local_definitions)]
+
 #[derive(Fail, Debug)]
 pub enum APIError {
     #[fail(display = "Serde issue parsing error {}", _0)]
@@ -6,6 +9,13 @@ pub enum APIError {
     Websocket(#[fail(cause)] tokio_tungstenite::tungstenite::Error),
     #[fail(display = "REST Call error {}", _0)]
     HTTP(#[fail(cause)] reqwest::Error),
+    #[fail(display = "Websocket frame parse error {}, frame: {}", msg, frame)]
+    Parse { msg: String, frame: String },
+    #[fail(
+        display = "Websocket connection closed, no longer subscribed to {:?}",
+        _0
+    )]
+    Disconnected(Vec<String>),
     #[fail(display = "Other issue {}", _0)]
     Other(String),
 }
@@ -0,0 +1,474 @@
+use async_trait::async_trait;
+
+use super::client::Kucoin;
+use super::error::APIError;
+use super::model::market::{
+    Candle, Klines, OrderBook, OrderBookType, SymbolList, Ticker, TradeHistories,
+};
+use super::model::trade::{CancelByClientOidResp, CancelResp, FillsInfo, OrderInfo, OrderResp};
+use super::model::user::Accounts;
+use super::trade::{FillsOptionals, OrderInfoOptionals, OrderOptionals};
+use super::utils::get_time;
+
+/// Side of an order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+impl Side {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Side::Buy => "buy",
+            Side::Sell => "sell",
+        }
+    }
+
+    pub fn opposite(&self) -> Side {
+        match self {
+            Side::Buy => Side::Sell,
+            Side::Sell => Side::Buy,
+        }
+    }
+
+    /// Reads a Kucoin side such as the side of an OrderInfo or FillsInfo.
+    pub fn parse(side: &str) -> Option<Side> {
+        match side {
+            "buy" => Some(Side::Buy),
+            "sell" => Some(Side::Sell),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeInForce {
+    GTC,
+    GTT,
+    IOC,
+    FOK,
+}
+
+impl TimeInForce {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TimeInForce::GTC => "GTC",
+            TimeInForce::GTT => "GTT",
+            TimeInForce::IOC => "IOC",
+            TimeInForce::FOK => "FOK",
+        }
+    }
+
+    pub fn parse(time_in_force: &str) -> Option<TimeInForce> {
+        match time_in_force {
+            "GTC" => Some(TimeInForce::GTC),
+            "GTT" => Some(TimeInForce::GTT),
+            "IOC" => Some(TimeInForce::IOC),
+            "FOK" => Some(TimeInForce::FOK),
+            _ => None,
+        }
+    }
+}
+
+/// Price and amount of an OrderRequest, as decimal strings.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OrderKind {
+    Limit {
+        price: String,
+        size: String,
+    },
+    /// Market order for a size in the base currency or funds in the quote currency.
+    Market {
+        size: Option<String>,
+        funds: Option<String>,
+    },
+}
+
+/// Order placed through OrderEntry::place_order. The constructors cover the required fields,
+/// the remaining fields can be set directly.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderRequest {
+    pub client_oid: String,
+    pub symbol: String,
+    pub side: Side,
+    pub kind: OrderKind,
+    pub time_in_force: Option<TimeInForce>,
+    pub post_only: bool,
+    pub remark: Option<String>,
+}
+
+impl OrderRequest {
+    pub fn limit(client_oid: &str, symbol: &str, side: Side, price: &str, size: &str) -> Self {
+        OrderRequest::new(
+            client_oid,
+            symbol,
+            side,
+            OrderKind::Limit {
+                price: price.to_string(),
+                size: size.to_string(),
+            },
+        )
+    }
+
+    pub fn market(client_oid: &str, symbol: &str, side: Side, size: &str) -> Self {
+        OrderRequest::new(
+            client_oid,
+            symbol,
+            side,
+            OrderKind::Market {
+                size: Some(size.to_string()),
+                funds: None,
+            },
+        )
+    }
+
+    pub fn market_funds(client_oid: &str, symbol: &str, side: Side, funds: &str) -> Self {
+        OrderRequest::new(
+            client_oid,
+            symbol,
+            side,
+            OrderKind::Market {
+                size: None,
+                funds: Some(funds.to_string()),
+            },
+        )
+    }
+
+    fn new(client_oid: &str, symbol: &str, side: Side, kind: OrderKind) -> Self {
+        OrderRequest {
+            client_oid: client_oid.to_string(),
+            symbol: symbol.to_string(),
+            side,
+            kind,
+            time_in_force: None,
+            post_only: false,
+            remark: None,
+        }
+    }
+}
+
+/// Public market data of an exchange.
+#[async_trait]
+pub trait MarketData: Send + Sync {
+    async fn symbols(&self, market: Option<&str>) -> Result<Vec<SymbolList>, APIError>;
+
+    async fn ticker(&self, symbol: &str) -> Result<Ticker, APIError>;
+
+    async fn order_book(&self, symbol: &str, depth: OrderBookType) -> Result<OrderBook, APIError>;
+
+    /// Most recent trades of the symbol.
+    async fn trades(&self, symbol: &str) -> Result<Vec<TradeHistories>, APIError>;
+
+    /// Candles of the symbol between start_at and end_at, in seconds since the epoch.
+    async fn candles(
+        &self,
+        symbol: &str,
+        interval: Klines,
+        start_at: Option<i64>,
+        end_at: Option<i64>,
+    ) -> Result<Vec<Candle>, APIError>;
+}
+
+/// Placing, cancelling and querying spot orders.
+#[async_trait]
+pub trait OrderEntry: Send + Sync {
+    async fn place_order(&self, order: &OrderRequest) -> Result<OrderResp, APIError>;
+
+    async fn cancel(&self, order_id: &str) -> Result<CancelResp, APIError>;
+
+    async fn cancel_by_client_oid(
+        &self,
+        client_oid: &str,
+    ) -> Result<CancelByClientOidResp, APIError>;
+
+    /// Cancels every open order, or those of the symbol.
+    async fn cancel_all(&self, symbol: Option<&str>) -> Result<CancelResp, APIError>;
+
+    async fn order(&self, order_id: &str) -> Result<OrderInfo, APIError>;
+
+    /// Every open order, or those of the symbol.
+    async fn open_orders(&self, symbol: Option<&str>) -> Result<Vec<OrderInfo>, APIError>;
+
+    /// Most recent fills, optionally of a symbol or order.
+    async fn fills(
+        &self,
+        symbol: Option<&str>,
+        order_id: Option<&str>,
+    ) -> Result<Vec<FillsInfo>, APIError>;
+}
+
+/// Balances of the account.
+#[async_trait]
+pub trait Account: Send + Sync {
+    /// Accounts, optionally of a currency or account type such as trade.
+    async fn accounts(
+        &self,
+        currency: Option<&str>,
+        acct_type: Option<&str>,
+    ) -> Result<Vec<Accounts>, APIError>;
+
+    /// Trade account of the currency, None when it has never been funded.
+    async fn trade_balance(&self, currency: &str) -> Result<Option<Accounts>, APIError> {
+        let accounts = self.accounts(Some(currency), Some("trade")).await?;
+        Ok(accounts.into_iter().next())
+    }
+
+    /// Current time of the account's server in milliseconds, the clock its balance pushes
+    /// are stamped with. Defaults to the local time.
+    async fn server_time(&self) -> Result<i64, APIError> {
+        Ok(get_time() as i64)
+    }
+}
+
+// Largest page Kucoin returns for order and fill lists.
+const MAX_PAGE_SIZE: i32 = 500;
+
+#[async_trait]
+impl MarketData for Kucoin {
+    async fn symbols(&self, market: Option<&str>) -> Result<Vec<SymbolList>, APIError> {
+        self.get_symbol_list(market).await?.into_data()
+    }
+
+    async fn ticker(&self, symbol: &str) -> Result<Ticker, APIError> {
+        self.get_ticker(symbol).await?.into_data()
+    }
+
+    async fn order_book(&self, symbol: &str, depth: OrderBookType) -> Result<OrderBook, APIError> {
+        self.get_orderbook(symbol, depth).await?.into_data()
+    }
+
+    async fn trades(&self, symbol: &str) -> Result<Vec<TradeHistories>, APIError> {
+        self.get_trade_histories(symbol).await?.into_data()
+    }
+
+    #[allow(clippy::result_large_err)]
+    async fn candles(
+        &self,
+        symbol: &str,
+        interval: Klines,
+        start_at: Option<i64>,
+        end_at: Option<i64>,
+    ) -> Result<Vec<Candle>, APIError> {
+        let rows = self
+            .get_klines(interval, symbol, start_at, end_at)
+            .await?
+            .into_data()?;
+        rows.iter()
+            .map(|row| {
+                Candle::from_row(row)
+                    .ok_or_else(|| APIError::Other(format!("Unexpected kline row {:?}", row)))
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl OrderEntry for Kucoin {
+    async fn place_order(&self, order: &OrderRequest) -> Result<OrderResp, APIError> {
+        let mut optionals = OrderOptionals::new();
+        if let Some(t) = order.time_in_force {
+            optionals.time_in_force(t.as_str());
+        }
+        if order.post_only {
+            optionals.post_only(true);
+        }
+        if let Some(r) = &order.remark {
+            optionals.remark(r);
+        }
+        let resp = match &order.kind {
+            OrderKind::Limit { price, size } => {
+                self.post_limit_order(
+                    &order.client_oid,
+                    &order.symbol,
+                    order.side.as_str(),
+                    price,
+                    size,
+                    Some(optionals),
+                )
+                .await?
+            }
+            OrderKind::Market { size, funds } => {
+                self.post_market_order_str(
+                    &order.client_oid,
+                    &order.symbol,
+                    order.side.as_str(),
+                    size.as_deref(),
+                    funds.as_deref(),
+                    Some(optionals),
+                )
+                .await?
+            }
+        };
+        resp.into_data()
+    }
+
+    async fn cancel(&self, order_id: &str) -> Result<CancelResp, APIError> {
+        self.cancel_order(order_id).await?.into_data()
+    }
+
+    async fn cancel_by_client_oid(
+        &self,
+        client_oid: &str,
+    ) -> Result<CancelByClientOidResp, APIError> {
+        self.cancel_order_by_client_oid(client_oid)
+            .await?
+            .into_data()
+    }
+
+    async fn cancel_all(&self, symbol: Option<&str>) -> Result<CancelResp, APIError> {
+        self.cancel_all_orders(symbol, None).await?.into_data()
+    }
+
+    async fn order(&self, order_id: &str) -> Result<OrderInfo, APIError> {
+        self.get_order(order_id).await?.into_data()
+    }
+
+    async fn open_orders(&self, symbol: Option<&str>) -> Result<Vec<OrderInfo>, APIError> {
+        let mut orders = Vec::new();
+        let mut page = 1;
+        loop {
+            let mut opts = OrderInfoOptionals::new();
+            opts.status("active")
+                .current_page(page)
+                .page_size(MAX_PAGE_SIZE);
+            if let Some(s) = symbol {
+                opts.symbol(s);
+            }
+            let resp = self.get_orders(Some(opts.build())).await?.into_data()?;
+            orders.extend(resp.items);
+            if page >= resp.total_page {
+                return Ok(orders);
+            }
+            page += 1;
+        }
+    }
+
+    async fn fills(
+        &self,
+        symbol: Option<&str>,
+        order_id: Option<&str>,
+    ) -> Result<Vec<FillsInfo>, APIError> {
+        let mut opts = FillsOptionals::new();
+        opts.page_size(MAX_PAGE_SIZE);
+        if let Some(s) = symbol {
+            opts.symbol(s);
+        }
+        if let Some(o) = order_id {
+            opts.order_id(o);
+        }
+        let resp = self.get_fills(Some(opts.build())).await?.into_data()?;
+        Ok(resp.items)
+    }
+}
+
+#[async_trait]
+impl Account for Kucoin {
+    async fn accounts(
+        &self,
+        currency: Option<&str>,
+        acct_type: Option<&str>,
+    ) -> Result<Vec<Accounts>, APIError> {
+        self.get_accounts_list(currency, acct_type)
+            .await?
+            .into_data()
+    }
+
+    async fn server_time(&self) -> Result<i64, APIError> {
+        self.get_server_time().await?.into_data()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::kucoin::error::APIError;
+    use crate::kucoin::exchange::{MarketData, OrderKind, OrderRequest, Side};
+    use crate::kucoin::model::market::{
+        Candle, Klines, OrderBook, OrderBookType, SymbolList, Ticker, TradeHistories,
+    };
+    use async_trait::async_trait;
+
+    struct StubMarket;
+
+    #[async_trait]
+    impl MarketData for StubMarket {
+        async fn symbols(&self, _: Option<&str>) -> Result<Vec<SymbolList>, APIError> {
+            Ok(Vec::new())
+        }
+
+        async fn ticker(&self, symbol: &str) -> Result<Ticker, APIError> {
+            Ok(Ticker {
+                sequence: "1".to_string(),
+                best_ask: "101".to_string(),
+                size: "1".to_string(),
+                price: symbol.len().to_string(),
+                best_bid_size: "1".to_string(),
+                best_bid: "99".to_string(),
+                best_ask_size: "1".to_string(),
+                time: 0,
+            })
+        }
+
+        async fn order_book(&self, _: &str, _: OrderBookType) -> Result<OrderBook, APIError> {
+            Err(APIError::Other("No order book".to_string()))
+        }
+
+        async fn trades(&self, _: &str) -> Result<Vec<TradeHistories>, APIError> {
+            Ok(Vec::new())
+        }
+
+        async fn candles(
+            &self,
+            _: &str,
+            _: Klines,
+            _: Option<i64>,
+            _: Option<i64>,
+        ) -> Result<Vec<Candle>, APIError> {
+            let row: Vec<String> = [
+                "1545904980",
+                "0.058",
+                "0.049",
+                "0.058",
+                "0.049",
+                "0.018",
+                "0.000945",
+            ]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+            Ok(Candle::from_row(&row).into_iter().collect())
+        }
+    }
+
+    async fn mid_price<M: MarketData>(market: &M, symbol: &str) -> Result<f64, APIError> {
+        let ticker = market.ticker(symbol).await?;
+        let bid: f64 = ticker.best_bid.parse().unwrap();
+        let ask: f64 = ticker.best_ask.parse().unwrap();
+        Ok((bid + ask) / 2.0)
+    }
+
+    #[tokio::test]
+    async fn strategy_code_runs_against_any_market() {
+        assert_eq!(mid_price(&StubMarket, "BTC-USDT").await.unwrap(), 100.0);
+        let market: Box<dyn MarketData> = Box::new(StubMarket);
+        let candles = market
+            .candles("BTC-USDT", Klines::K1min, None, None)
+            .await
+            .unwrap();
+        assert_eq!(candles[0].time, 1545904980);
+        assert_eq!(candles[0].close, "0.049");
+        assert_eq!(candles[0].turnover, "0.000945");
+        assert!(Candle::from_row(&["1".to_string()]).is_none());
+
+        let order = OrderRequest::market_funds("1", "BTC-USDT", Side::Buy, "10");
+        assert_eq!(
+            order.kind,
+            OrderKind::Market {
+                size: None,
+                funds: Some("10".to_string())
+            }
+        );
+        assert_eq!(order.side.opposite(), Side::parse("sell").unwrap());
+    }
+}
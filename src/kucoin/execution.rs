@@ -0,0 +1,471 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::{pin_mut, FutureExt, Stream, StreamExt};
+
+use super::backtest::SymbolRules;
+use super::error::APIError;
+use super::exchange::{OrderEntry, OrderRequest, Side, TimeInForce};
+use super::model::market::Candle;
+use super::model::websocket::KucoinWebsocketMsg;
+use super::utils::{broadcast, format_amount, get_time, round_down, round_up};
+
+/// How an Execution sizes its slices.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExecutionAlgo {
+    /// Equal slices spread evenly over the window, the last slice sending whatever remains
+    Twap,
+    /// Slices weighted by the volume profile set with ExecutionConfig::volume_profile, so more
+    /// is sent when more usually trades, the last slice sending whatever remains. Slices are
+    /// equal like Twap without a profile
+    Vwap,
+    /// Percent of volume: each slice is this share of the volume traded since the previous
+    /// one, e.g. 0.1 for 10%, the last slice included, so what the volume didn't allow by the
+    /// end of the window is left unfilled
+    Pov { participation: f64 },
+}
+
+/// What to execute and how, built with ExecutionConfig::new and its setters.
+#[derive(Debug, Clone)]
+pub struct ExecutionConfig {
+    symbol: String,
+    side: Side,
+    size: f64,
+    window: Duration,
+    slices: u32,
+    algo: ExecutionAlgo,
+    profile: Vec<f64>,
+    limit_price: Option<f64>,
+    aggressive: bool,
+    rules: SymbolRules,
+    client_oid: String,
+}
+
+impl ExecutionConfig {
+    /// Executes size of the symbol within the window, by default as 10 TWAP slices that take
+    /// liquidity.
+    pub fn new(symbol: &str, side: Side, size: f64, window: Duration) -> Self {
+        ExecutionConfig {
+            symbol: symbol.to_string(),
+            side,
+            size,
+            window,
+            slices: 10,
+            algo: ExecutionAlgo::Twap,
+            profile: Vec::new(),
+            limit_price: None,
+            aggressive: true,
+            rules: SymbolRules::default(),
+            client_oid: format!("exec-{}", get_time()),
+        }
+    }
+
+    pub fn slices(&mut self, slices: u32) -> &mut Self {
+        self.slices = slices.max(1);
+        self
+    }
+
+    pub fn algo(&mut self, algo: ExecutionAlgo) -> &mut Self {
+        self.algo = algo;
+        self
+    }
+
+    /// Volume profile of Vwap slices from historical candles covering a window like this one,
+    /// e.g. the same hours of the previous day from get_klines, oldest first.
+    pub fn volume_profile(&mut self, candles: &[Candle]) -> &mut Self {
+        self.profile = candles
+            .iter()
+            .map(|c| c.volume.parse::<f64>().unwrap_or(0.0).max(0.0))
+            .collect();
+        self
+    }
+
+    /// Worst price of any slice, the highest for buys and the lowest for sells.
+    pub fn limit_price(&mut self, price: f64) -> &mut Self {
+        self.limit_price = Some(price);
+        self
+    }
+
+    /// Rests each slice at the best price of its own side until the next slice, instead of
+    /// crossing the spread with an IOC order.
+    pub fn passive(&mut self) -> &mut Self {
+        self.aggressive = false;
+        self
+    }
+
+    /// Increments and minimum size slices are rounded to, e.g. from SymbolRules::from_symbol.
+    pub fn rules(&mut self, rules: SymbolRules) -> &mut Self {
+        self.rules = rules;
+        self
+    }
+
+    /// Prefix of the client_oid of each slice, followed by the slice number.
+    pub fn client_oid(&mut self, prefix: &str) -> &mut Self {
+        self.client_oid = prefix.to_string();
+        self
+    }
+
+    // Size of slice k, given the size filled so far and the volume traded since the
+    // previous slice.
+    fn slice_size(&self, k: u32, filled: f64, volume: f64) -> f64 {
+        let remaining = (self.size - filled).max(0.0);
+        let size = match self.algo {
+            ExecutionAlgo::Twap | ExecutionAlgo::Vwap if k + 1 >= self.slices => remaining,
+            ExecutionAlgo::Twap => self.size * (k + 1) as f64 / self.slices as f64 - filled,
+            ExecutionAlgo::Vwap => self.size * self.profile_share(k) - filled,
+            ExecutionAlgo::Pov { participation } => volume * participation,
+        };
+        round_down(size.clamp(0.0, remaining), self.rules.base_increment)
+    }
+
+    // Share of the profile's volume traded by the end of slice k, the candles spread evenly
+    // over the window.
+    fn profile_share(&self, k: u32) -> f64 {
+        let elapsed = (k + 1) as f64 / self.slices as f64;
+        let total: f64 = self.profile.iter().sum();
+        if total <= 0.0 {
+            return elapsed;
+        }
+        let position = elapsed * self.profile.len() as f64;
+        let whole = (position as usize).min(self.profile.len());
+        let mut share: f64 = self.profile[..whole].iter().sum();
+        if let Some(partial) = self.profile.get(whole) {
+            share += partial * (position - whole as f64);
+        }
+        share / total
+    }
+}
+
+/// State of an Execution, sent after each slice and returned once it is done.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExecutionProgress {
+    pub slices_sent: u32,
+    pub filled: f64,
+    pub remaining: f64,
+    /// Quote currency spent or received
+    pub funds: f64,
+    pub avg_price: Option<f64>,
+    /// Mid price, or last trade price, when the first slice was due
+    pub arrival_price: Option<f64>,
+    /// Cost of avg_price against arrival_price, positive when worse
+    pub slippage_bps: Option<f64>,
+    /// Whether the window is over
+    pub done: bool,
+    /// Whether the whole size was filled
+    pub completed: bool,
+}
+
+#[derive(Debug, Default)]
+struct MarketState {
+    bid: Option<f64>,
+    ask: Option<f64>,
+    last: Option<f64>,
+    volume: f64,
+}
+
+impl MarketState {
+    fn on_message(&mut self, symbol: &str, msg: &KucoinWebsocketMsg) {
+        match msg {
+            KucoinWebsocketMsg::TickerMsg(r) | KucoinWebsocketMsg::AllTickerMsg(r) => {
+                let ticker_symbol = match msg {
+                    KucoinWebsocketMsg::AllTickerMsg(_) => r.subject.as_str(),
+                    _ => r.topic.rsplit(':').next().unwrap_or_default(),
+                };
+                if ticker_symbol == symbol {
+                    self.bid = r.data.best_bid.parse().ok().or(self.bid);
+                    self.ask = r.data.best_ask.parse().ok().or(self.ask);
+                    self.last = r.data.price.parse().ok().or(self.last);
+                }
+            }
+            KucoinWebsocketMsg::MatchMsg(r) if r.data.symbol == symbol => {
+                self.last = r.data.price.parse().ok().or(self.last);
+                self.volume += r.data.size.parse().unwrap_or(0.0);
+            }
+            _ => (),
+        }
+    }
+
+    fn mid(&self) -> Option<f64> {
+        match (self.bid, self.ask) {
+            (Some(bid), Some(ask)) => Some((bid + ask) / 2.0),
+            _ => self.last,
+        }
+    }
+}
+
+/// Works a large order as a schedule of limit orders over a time window, pricing each slice
+/// from the ticker and match messages of a source such as a KucoinWebsocket subscribed to
+/// the symbol's ticker and matches. Slices trade through an OrderEntry, so an Execution runs
+/// on Kucoin or a PaperExchange alike. The previous slice is cancelled before the next one
+/// and what it left unfilled is carried over.
+pub struct Execution {
+    config: ExecutionConfig,
+    orders: Arc<dyn OrderEntry>,
+    listeners: Vec<UnboundedSender<ExecutionProgress>>,
+    progress: ExecutionProgress,
+}
+
+impl Execution {
+    pub fn new(config: ExecutionConfig, orders: Arc<dyn OrderEntry>) -> Self {
+        let progress = ExecutionProgress {
+            slices_sent: 0,
+            filled: 0.0,
+            remaining: config.size,
+            funds: 0.0,
+            avg_price: None,
+            arrival_price: None,
+            slippage_bps: None,
+            done: false,
+            completed: false,
+        };
+        Execution {
+            config,
+            orders,
+            listeners: Vec::new(),
+            progress,
+        }
+    }
+
+    /// Progress after every slice and once done.
+    pub fn progress(&mut self) -> UnboundedReceiver<ExecutionProgress> {
+        let (tx, rx) = mpsc::unbounded();
+        self.listeners.push(tx);
+        rx
+    }
+
+    /// Runs the schedule until the end of the window, returning the final progress. The
+    /// source ending early leaves the slices priced at its last messages. Fails without
+    /// trading when the window is too short to space the slices.
+    pub async fn run<S>(mut self, source: S) -> Result<ExecutionProgress, APIError>
+    where
+        S: Stream<Item = Result<KucoinWebsocketMsg, APIError>>,
+    {
+        let period = self.config.window / self.config.slices;
+        if period.is_zero() {
+            return Err(APIError::Other(format!(
+                "Execution window of {:?} is too short for {} slices",
+                self.config.window, self.config.slices
+            )));
+        }
+        pin_mut!(source);
+        let mut market = MarketState::default();
+        let mut source_done = false;
+        let mut ticks = tokio::time::interval(period);
+        let mut open: Option<String> = None;
+        let mut k = 0;
+        loop {
+            tokio::select! {
+                msg = source.next(), if !source_done => match msg {
+                    Some(Ok(msg)) => market.on_message(&self.config.symbol, &msg),
+                    Some(Err(_)) => (),
+                    None => source_done = true,
+                },
+                _ = ticks.tick() => {
+                    // Price the slice with every message already received.
+                    while !source_done {
+                        match source.next().now_or_never() {
+                            Some(Some(Ok(msg))) => market.on_message(&self.config.symbol, &msg),
+                            Some(Some(Err(_))) => (),
+                            Some(None) => source_done = true,
+                            None => break,
+                        }
+                    }
+                    if let Some(id) = open.take() {
+                        self.settle(&id).await?;
+                    }
+                    if k >= self.config.slices {
+                        break;
+                    }
+                    open = self.slice(k, &mut market).await?;
+                    k += 1;
+                    self.emit();
+                }
+            }
+        }
+        self.progress.done = true;
+        self.progress.completed =
+            self.progress.remaining < self.config.rules.base_increment.max(1e-9);
+        self.emit();
+        Ok(self.progress)
+    }
+
+    // Places slice k, returning its order id.
+    async fn slice(
+        &mut self,
+        k: u32,
+        market: &mut MarketState,
+    ) -> Result<Option<String>, APIError> {
+        if self.progress.arrival_price.is_none() {
+            self.progress.arrival_price = market.mid();
+        }
+        let size = self
+            .config
+            .slice_size(k, self.progress.filled, market.volume);
+        market.volume = 0.0;
+        let (bid, ask) = match (market.bid.or(market.last), market.ask.or(market.last)) {
+            (Some(bid), Some(ask)) => (bid, ask),
+            _ => return Ok(None),
+        };
+        if size <= 0.0 || size < self.config.rules.base_min_size {
+            return Ok(None);
+        }
+        let side = self.config.side;
+        let price = match (side, self.config.aggressive) {
+            (Side::Buy, true) | (Side::Sell, false) => ask,
+            (Side::Buy, false) | (Side::Sell, true) => bid,
+        };
+        let increment = self.config.rules.price_increment;
+        let price = match (side, self.config.limit_price) {
+            (Side::Buy, Some(limit)) => round_down(price.min(limit), increment),
+            (Side::Sell, Some(limit)) => round_up(price.max(limit), increment),
+            (Side::Buy, None) => round_down(price, increment),
+            (Side::Sell, None) => round_up(price, increment),
+        };
+        let mut order = OrderRequest::limit(
+            &format!("{}-{}", self.config.client_oid, k),
+            &self.config.symbol,
+            side,
+            &format_amount(price),
+            &format_amount(size),
+        );
+        if self.config.aggressive {
+            order.time_in_force = Some(TimeInForce::IOC);
+        }
+        let resp = self.orders.place_order(&order).await?;
+        self.progress.slices_sent += 1;
+        Ok(Some(resp.order_id))
+    }
+
+    // Cancels what is left of the order and adds its fills.
+    async fn settle(&mut self, order_id: &str) -> Result<(), APIError> {
+        // Fails for orders already done, which is expected.
+        let _ = self.orders.cancel(order_id).await;
+        let order = self.orders.order(order_id).await?;
+        let size: f64 = order.deal_size.parse().unwrap_or(0.0);
+        let funds: f64 = order.deal_funds.parse().unwrap_or(0.0);
+        let side = self.config.side;
+        let p = &mut self.progress;
+        p.filled += size;
+        p.funds += funds;
+        p.remaining = (self.config.size - p.filled).max(0.0);
+        if p.filled > 0.0 {
+            let avg = p.funds / p.filled;
+            p.avg_price = Some(avg);
+            p.slippage_bps = p.arrival_price.filter(|a| *a > 0.0).map(|a| {
+                let cost = match side {
+                    Side::Buy => avg - a,
+                    Side::Sell => a - avg,
+                };
+                cost / a * 10_000.0
+            });
+        }
+        Ok(())
+    }
+
+    fn emit(&mut self) {
+        broadcast(&mut self.listeners, &self.progress);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::kucoin::backtest::SymbolRules;
+    use crate::kucoin::exchange::Side;
+    use crate::kucoin::execution::{Execution, ExecutionAlgo, ExecutionConfig};
+    use crate::kucoin::model::market::Candle;
+    use crate::kucoin::paper::PaperExchange;
+    use crate::kucoin::utils::format_amount;
+    use crate::kucoin::websocket::parse_message;
+    use futures::{stream, StreamExt};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio_tungstenite::tungstenite::Message;
+
+    #[test]
+    fn slices_follow_schedule_and_increments() {
+        let mut config = ExecutionConfig::new("BTC-USDT", Side::Buy, 1.0, Duration::from_secs(60));
+        config.slices(3).rules(SymbolRules {
+            price_increment: 0.1,
+            base_increment: 0.01,
+            base_min_size: 0.01,
+        });
+        assert_eq!(config.slice_size(0, 0.0, 0.0), 0.33);
+        // Catches up on the first slice's unfilled size
+        assert_eq!(config.slice_size(1, 0.0, 0.0), 0.66);
+        assert_eq!(config.slice_size(2, 0.5, 0.0), 0.5);
+
+        config.algo(ExecutionAlgo::Pov { participation: 0.1 });
+        assert_eq!(config.slice_size(0, 0.0, 2.55), 0.25);
+        assert_eq!(config.slice_size(1, 0.25, 100.0), 0.75);
+        // The last slice keeps to the participation
+        assert_eq!(config.slice_size(2, 0.25, 0.0), 0.0);
+        assert_eq!(config.slice_size(2, 0.25, 5.0), 0.5);
+    }
+
+    #[test]
+    fn vwap_slices_follow_volume_profile() {
+        let mut config = ExecutionConfig::new("BTC-USDT", Side::Buy, 1.0, Duration::from_secs(60));
+        config
+            .slices(4)
+            .algo(ExecutionAlgo::Vwap)
+            .rules(SymbolRules {
+                price_increment: 0.1,
+                base_increment: 0.01,
+                base_min_size: 0.01,
+            });
+        // Evenly without a profile
+        assert_eq!(format_amount(config.slice_size(0, 0.0, 0.0)), "0.25");
+
+        // Three quarters of the volume trades in the first half of the window
+        let candle = |volume: &str| Candle {
+            time: 0,
+            open: "1".to_string(),
+            close: "1".to_string(),
+            high: "1".to_string(),
+            low: "1".to_string(),
+            volume: volume.to_string(),
+            turnover: "1".to_string(),
+        };
+        config.volume_profile(&[candle("30"), candle("10")]);
+        assert_eq!(format_amount(config.slice_size(0, 0.0, 0.0)), "0.37");
+        assert_eq!(format_amount(config.slice_size(1, 0.37, 0.0)), "0.38");
+        assert_eq!(format_amount(config.slice_size(2, 0.75, 0.0)), "0.12");
+        assert_eq!(format_amount(config.slice_size(3, 0.87, 0.0)), "0.13");
+    }
+
+    #[tokio::test]
+    async fn window_shorter_than_slices_is_rejected() {
+        let mut config = ExecutionConfig::new("BTC-USDT", Side::Buy, 1.0, Duration::from_nanos(5));
+        config.slices(10);
+        let execution = Execution::new(config, Arc::new(PaperExchange::default()));
+        assert!(execution.run(stream::empty()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn twap_fills_on_paper_and_reports_slippage() {
+        tokio::time::pause();
+        let paper = PaperExchange::default();
+        paper.deposit("USDT", 1000.0);
+        let ticker = r#"{"type":"message","topic":"/market/ticker:BTC-USDT","subject":"trade.ticker","data":{"sequence":"1","price":"100.5","size":"0.1","bestAsk":"101","bestAskSize":"10","bestBid":"100","bestBidSize":"10"}}"#;
+        let msg = parse_message(Message::Text(ticker.to_string())).unwrap();
+        paper.on_message(&msg);
+
+        let mut config =
+            ExecutionConfig::new("BTC-USDT", Side::Buy, 2.0, Duration::from_millis(80));
+        config.slices(4).limit_price(105.0);
+        let mut execution = Execution::new(config, Arc::new(paper));
+        let progress = execution.progress();
+        let report = execution.run(stream::iter(vec![Ok(msg)])).await.unwrap();
+
+        assert!(report.done && report.completed);
+        assert_eq!(report.slices_sent, 4);
+        assert_eq!(report.filled, 2.0);
+        assert_eq!(report.avg_price, Some(101.0));
+        assert_eq!(report.arrival_price, Some(100.5));
+        assert!((report.slippage_bps.unwrap() - 49.75).abs() < 0.01);
+        let updates: Vec<_> = progress.collect().await;
+        assert_eq!(updates.len(), 5);
+        assert_eq!(updates[1].filled, 0.5);
+    }
+}
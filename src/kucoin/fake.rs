@@ -0,0 +1,118 @@
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use futures::{SinkExt, StreamExt};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio_tungstenite::tungstenite::Message;
+
+use super::client::{Kucoin, KucoinEnv};
+
+// Local HTTP server standing in for the Kucoin REST API. Every request is answered with the
+// JSON body returned by respond for its method, path with query and body, and reported as
+// "METHOD path". Returns the prefix to point a client at.
+pub(crate) async fn fake_rest<F>(respond: F) -> (String, UnboundedReceiver<String>)
+where
+    F: Fn(&str, &str, &str) -> String + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let prefix = format!("http://{}", listener.local_addr().unwrap());
+    let (requests_tx, requests) = unbounded();
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            let mut req = Vec::new();
+            let mut buf = [0; 4096];
+            let head_end = loop {
+                if let Some(i) = req.windows(4).position(|w| w == b"\r\n\r\n") {
+                    break i + 4;
+                }
+                match socket.read(&mut buf).await {
+                    Ok(0) | Err(_) => break req.len(),
+                    Ok(n) => req.extend_from_slice(&buf[..n]),
+                }
+            };
+            let head = String::from_utf8_lossy(&req[..head_end]).to_string();
+            let length = head
+                .lines()
+                .filter_map(|l| l.split_once(':'))
+                .find(|(k, _)| k.eq_ignore_ascii_case("content-length"))
+                .and_then(|(_, v)| v.trim().parse::<usize>().ok())
+                .unwrap_or(0);
+            while req.len() < head_end + length {
+                match socket.read(&mut buf).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => req.extend_from_slice(&buf[..n]),
+                }
+            }
+            let body = String::from_utf8_lossy(&req[head_end..]).to_string();
+            let mut line = head.split_whitespace();
+            let method = line.next().unwrap_or_default().to_string();
+            let path = line.next().unwrap_or_default().to_string();
+            let _ = requests_tx.unbounded_send(format!("{} {}", method, path));
+            let json = respond(&method, &path, &body);
+            let resp = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                json.len(),
+                json
+            );
+            let _ = socket.write_all(resp.as_bytes()).await;
+        }
+    });
+    (prefix, requests)
+}
+
+// Local websocket server standing in for a Kucoin instance server. It welcomes the first
+// connection and acknowledges every request, reporting the requests it receives until the
+// connection closes, and pushes the frames sent to it to the client.
+pub(crate) async fn fake_websocket() -> (
+    String,
+    UnboundedReceiver<serde_json::Value>,
+    UnboundedSender<String>,
+) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}", listener.local_addr().unwrap());
+    let (requests_tx, requests) = unbounded();
+    let (frames, mut frames_rx) = unbounded::<String>();
+    tokio::spawn(async move {
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut socket = tokio_tungstenite::accept_async(socket).await.unwrap();
+        let welcome = r#"{"id":"1","type":"welcome"}"#;
+        socket
+            .send(Message::Text(welcome.to_string()))
+            .await
+            .unwrap();
+        loop {
+            tokio::select! {
+                frame = socket.next() => match frame {
+                    Some(Ok(Message::Text(text))) => {
+                        let req: serde_json::Value = serde_json::from_str(&text).unwrap();
+                        let ack = format!(r#"{{"id":{},"type":"ack"}}"#, req["id"]);
+                        let _ = requests_tx.unbounded_send(req);
+                        socket.send(Message::Text(ack)).await.unwrap();
+                    }
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => {}
+                },
+                Some(frame) = frames_rx.next() => {
+                    socket.send(Message::Text(frame)).await.unwrap();
+                }
+            }
+        }
+    });
+    (url, requests, frames)
+}
+
+// Client of a fake Kucoin whose bullets point at a fake_websocket.
+pub(crate) async fn fake_kucoin() -> (
+    Kucoin,
+    UnboundedReceiver<serde_json::Value>,
+    UnboundedSender<String>,
+) {
+    let (url, requests, frames) = fake_websocket().await;
+    let bullet = format!(
+        r#"{{"code":"200000","data":{{"token":"token","instanceServers":[{{"endpoint":"{}","protocol":"websocket","encrypt":false,"pingInterval":18000,"pingTimeout":10000}}]}}}}"#,
+        url
+    );
+    let (prefix, _) = fake_rest(move |_, _, _| bullet.clone()).await;
+    let mut api = Kucoin::new(KucoinEnv::Live, None).unwrap();
+    api.prefix = prefix;
+    (api, requests, frames)
+}
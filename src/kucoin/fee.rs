@@ -0,0 +1,314 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::client::Kucoin;
+use super::error::APIError;
+use super::exchange::{OrderKind, OrderRequest, Side, TimeInForce};
+use super::model::user::{BaseFee, TradeFee};
+
+/// Discount on fees paid in KCS.
+pub const KCS_DISCOUNT: f64 = 0.2;
+/// Symbols get_trade_fees accepts at once.
+pub const TRADE_FEES_BATCH: usize = 10;
+/// How long a FeeSchedule keeps rates by default, fee levels are updated daily.
+pub const FEE_CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Whether a fill added liquidity to the book or took it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Liquidity {
+    Maker,
+    Taker,
+}
+
+impl Liquidity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Liquidity::Maker => "maker",
+            Liquidity::Taker => "taker",
+        }
+    }
+
+    /// Expected liquidity of the order: market, IOC and FOK orders take, as do limit orders
+    /// crossing market_price, i.e. buys at or above it and sells at or below it. Other limit
+    /// orders, or any when market_price isn't positive, are assumed to rest in the book.
+    pub fn of_order(order: &OrderRequest, market_price: f64) -> Self {
+        match (&order.kind, order.time_in_force) {
+            (OrderKind::Market { .. }, _) => Liquidity::Taker,
+            (_, Some(TimeInForce::IOC)) | (_, Some(TimeInForce::FOK)) => Liquidity::Taker,
+            (OrderKind::Limit { price, .. }, _) if market_price > 0.0 => {
+                match (order.side, price.parse::<f64>()) {
+                    (Side::Buy, Ok(p)) if p >= market_price => Liquidity::Taker,
+                    (Side::Sell, Ok(p)) if p <= market_price => Liquidity::Taker,
+                    _ => Liquidity::Maker,
+                }
+            }
+            _ => Liquidity::Maker,
+        }
+    }
+}
+
+/// Maker and taker fee rates, e.g. 0.001 for 0.1%.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FeeRates {
+    pub maker: f64,
+    pub taker: f64,
+}
+
+impl FeeRates {
+    pub fn from_base_fee(fee: &BaseFee) -> Option<Self> {
+        Some(FeeRates {
+            maker: fee.maker_fee_rate.parse().ok()?,
+            taker: fee.taker_fee_rate.parse().ok()?,
+        })
+    }
+
+    pub fn from_trade_fee(fee: &TradeFee) -> Option<Self> {
+        Some(FeeRates {
+            maker: fee.maker_fee_rate.parse().ok()?,
+            taker: fee.taker_fee_rate.parse().ok()?,
+        })
+    }
+
+    pub fn rate(&self, liquidity: Liquidity) -> f64 {
+        match liquidity {
+            Liquidity::Maker => self.maker,
+            Liquidity::Taker => self.taker,
+        }
+    }
+}
+
+/// Fees paid in KCS, at KCS_DISCOUNT unless set otherwise.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KcsDeduction {
+    /// Price of KCS in the quote currency of the order
+    pub kcs_price: f64,
+    pub discount: f64,
+}
+
+impl KcsDeduction {
+    pub fn new(kcs_price: f64) -> Self {
+        KcsDeduction {
+            kcs_price,
+            discount: KCS_DISCOUNT,
+        }
+    }
+}
+
+/// Expected fee of an order if it fills completely.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeeEstimate {
+    pub liquidity: Liquidity,
+    pub size: f64,
+    /// size * price in the quote currency
+    pub funds: f64,
+    pub fee: f64,
+    /// Quote currency of the symbol, or KCS
+    pub fee_currency: String,
+    /// Quote currency spent by a buy or received by a sell, fees paid in it included
+    pub net_funds: f64,
+}
+
+/// Expected fee and net proceeds of the order. Limit orders fill at their price, market
+/// orders at `market_price`, which also tells whether a limit order takes, see
+/// Liquidity::of_order. None when the order's amounts can't be parsed.
+pub fn estimate(
+    order: &OrderRequest,
+    rates: &FeeRates,
+    market_price: f64,
+    kcs: Option<&KcsDeduction>,
+) -> Option<FeeEstimate> {
+    let (size, funds) = match &order.kind {
+        OrderKind::Limit { price, size } => {
+            let size: f64 = size.parse().ok()?;
+            (size, size * price.parse::<f64>().ok()?)
+        }
+        OrderKind::Market { size: Some(s), .. } => {
+            let size: f64 = s.parse().ok()?;
+            (size, size * market_price)
+        }
+        OrderKind::Market {
+            funds: Some(f),
+            size: None,
+        } => {
+            let funds: f64 = f.parse().ok()?;
+            if market_price <= 0.0 {
+                return None;
+            }
+            (funds / market_price, funds)
+        }
+        OrderKind::Market { .. } => return None,
+    };
+    let liquidity = Liquidity::of_order(order, market_price);
+    let quote_fee = funds * rates.rate(liquidity);
+    let (fee, fee_currency, quote_fee) = match kcs {
+        Some(kcs) if kcs.kcs_price > 0.0 => (
+            quote_fee * (1.0 - kcs.discount) / kcs.kcs_price,
+            "KCS".to_string(),
+            0.0,
+        ),
+        _ => (
+            quote_fee,
+            order.symbol.rsplit('-').next()?.to_string(),
+            quote_fee,
+        ),
+    };
+    let net_funds = match order.side {
+        Side::Buy => funds + quote_fee,
+        Side::Sell => funds - quote_fee,
+    };
+    Some(FeeEstimate {
+        liquidity,
+        size,
+        funds,
+        fee,
+        fee_currency,
+        net_funds,
+    })
+}
+
+/// Fee rates fetched from Kucoin, kept for a time to live so they can be read before every
+/// order. Share it between tasks through an Arc.
+pub struct FeeSchedule {
+    ttl: Duration,
+    base: Mutex<Option<(FeeRates, Instant)>>,
+    symbols: Mutex<HashMap<String, (FeeRates, Instant)>>,
+}
+
+impl Default for FeeSchedule {
+    fn default() -> Self {
+        FeeSchedule::new(FEE_CACHE_TTL)
+    }
+}
+
+impl FeeSchedule {
+    pub fn new(ttl: Duration) -> Self {
+        FeeSchedule {
+            ttl,
+            base: Mutex::new(None),
+            symbols: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Base rates of the user's fee level for crypto.
+    pub async fn base(&self, api: &Kucoin) -> Result<FeeRates, APIError> {
+        if let Some((rates, at)) = *self.base.lock().unwrap() {
+            if at.elapsed() < self.ttl {
+                return Ok(rates);
+            }
+        }
+        let fee = api.get_base_fee(None).await?.into_data()?;
+        let rates = FeeRates::from_base_fee(&fee)
+            .ok_or_else(|| APIError::Other(format!("Invalid base fee {:?}", fee)))?;
+        *self.base.lock().unwrap() = Some((rates, Instant::now()));
+        Ok(rates)
+    }
+
+    /// Actual rates of the symbol, fetched when not cached or expired.
+    pub async fn rates(&self, api: &Kucoin, symbol: &str) -> Result<FeeRates, APIError> {
+        if let Some(rates) = self.cached(symbol) {
+            return Ok(rates);
+        }
+        self.refresh(api, &[symbol]).await?;
+        self.cached(symbol)
+            .ok_or_else(|| APIError::Other(format!("No fee rates for {}", symbol)))
+    }
+
+    /// Fetches the rates of the symbols, TRADE_FEES_BATCH per request.
+    pub async fn refresh(&self, api: &Kucoin, symbols: &[&str]) -> Result<(), APIError> {
+        for batch in symbols.chunks(TRADE_FEES_BATCH) {
+            let fees = api.get_trade_fees(batch).await?.into_data()?;
+            self.insert(&fees);
+        }
+        Ok(())
+    }
+
+    /// Rates of the symbol if cached and not expired.
+    pub fn cached(&self, symbol: &str) -> Option<FeeRates> {
+        let symbols = self.symbols.lock().unwrap();
+        symbols
+            .get(symbol)
+            .filter(|(_, at)| at.elapsed() < self.ttl)
+            .map(|(rates, _)| *rates)
+    }
+
+    /// Drops every cached rate, e.g. after the fee level changed.
+    pub fn invalidate(&self) {
+        *self.base.lock().unwrap() = None;
+        self.symbols.lock().unwrap().clear();
+    }
+
+    fn insert(&self, fees: &[TradeFee]) {
+        let now = Instant::now();
+        let mut symbols = self.symbols.lock().unwrap();
+        for fee in fees {
+            if let Some(rates) = FeeRates::from_trade_fee(fee) {
+                symbols.insert(fee.symbol.clone(), (rates, now));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::kucoin::exchange::{OrderRequest, Side, TimeInForce};
+    use crate::kucoin::fee::{estimate, FeeRates, FeeSchedule, KcsDeduction, Liquidity};
+    use crate::kucoin::model::user::TradeFee;
+    use std::time::Duration;
+
+    #[test]
+    fn estimates_fees_and_net_proceeds() {
+        let rates = FeeRates {
+            maker: 0.001,
+            taker: 0.002,
+        };
+        let buy = OrderRequest::limit("1", "BTC-USDT", Side::Buy, "100", "2");
+        let est = estimate(&buy, &rates, 0.0, None).unwrap();
+        assert_eq!(est.liquidity, Liquidity::Maker);
+        assert_eq!(est.fee_currency, "USDT");
+        assert!((est.fee - 0.2).abs() < 1e-9);
+        assert!((est.net_funds - 200.2).abs() < 1e-9);
+
+        let mut ioc = OrderRequest::limit("2", "BTC-USDT", Side::Sell, "100", "2");
+        ioc.time_in_force = Some(TimeInForce::IOC);
+        let est = estimate(&ioc, &rates, 0.0, None).unwrap();
+        assert_eq!(est.liquidity, Liquidity::Taker);
+        assert!((est.net_funds - 199.6).abs() < 1e-9);
+
+        // A limit buy at or above the market crosses the book
+        assert_eq!(Liquidity::of_order(&buy, 99.0), Liquidity::Taker);
+        assert_eq!(Liquidity::of_order(&buy, 101.0), Liquidity::Maker);
+        let sell = OrderRequest::limit("4", "BTC-USDT", Side::Sell, "100", "2");
+        assert_eq!(Liquidity::of_order(&sell, 100.0), Liquidity::Taker);
+        let est = estimate(&sell, &rates, 99.0, None).unwrap();
+        assert_eq!(est.liquidity, Liquidity::Maker);
+
+        // 0.2 USDT of taker fee, less 20%, at 8 USDT per KCS.
+        let sell = OrderRequest::market_funds("3", "BTC-USDT", Side::Sell, "100");
+        let est = estimate(&sell, &rates, 50.0, Some(&KcsDeduction::new(8.0))).unwrap();
+        assert_eq!(est.size, 2.0);
+        assert_eq!(est.fee_currency, "KCS");
+        assert!((est.fee - 0.02).abs() < 1e-9);
+        assert_eq!(est.net_funds, 100.0);
+    }
+
+    #[test]
+    fn schedule_expires_cached_rates() {
+        let schedule = FeeSchedule::new(Duration::from_millis(20));
+        let fees: Vec<TradeFee> = serde_json::from_str(
+            r#"[{"symbol":"BTC-USDT","takerFeeRate":"0.001","makerFeeRate":"0.0008"}]"#,
+        )
+        .unwrap();
+        schedule.insert(&fees);
+        assert_eq!(
+            schedule.cached("BTC-USDT"),
+            Some(FeeRates {
+                maker: 0.0008,
+                taker: 0.001
+            })
+        );
+        assert_eq!(schedule.cached("ETH-USDT"), None);
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(schedule.cached("BTC-USDT"), None);
+    }
+}
@@ -0,0 +1,481 @@
+use reqwest::header;
+use serde_json::json;
+use std::collections::HashMap;
+
+use super::client::Kucoin;
+use super::error::APIError;
+use super::model::hf::{
+    HfBatchOrderResp, HfCancelByClientOidResp, HfCancelResp, HfDoneOrders, HfFills, HfModifyResp,
+    HfOrderInfo, HfOrderRequest, HfOrderResp, HfSyncOrderResp,
+};
+use super::model::trade::AmendResp;
+use super::model::{APIData, APIDatum, Method};
+use super::trade::{parse_order, OrderOptionals};
+use super::utils::format_query;
+
+impl Kucoin {
+    /// Places a limit order on the high-frequency (HF) trading account. Takes the same inputs as
+    /// post_limit_order, see OrderOptionals for build pattern usage to simplify generating optional params.
+    pub async fn post_hf_limit_order(
+        &self,
+        client_oid: &str,
+        symbol: &str,
+        side: &str,
+        price: &str,
+        size: &str,
+        optionals: Option<OrderOptionals<'_>>,
+    ) -> Result<APIDatum<HfOrderResp>, APIError> {
+        let endpoint = String::from("/api/v1/hf/orders");
+        let params = limit_params(client_oid, symbol, side, price, size, optionals);
+        self.post_hf(endpoint, params).await
+    }
+
+    /// Places a market order on the HF trading account. Note that size is the amount in the base currency
+    /// and funds is the amount in quote currency, only one of the two should be used.
+    pub async fn post_hf_market_order(
+        &self,
+        client_oid: &str,
+        symbol: &str,
+        side: &str,
+        size: Option<f32>,
+        funds: Option<f32>,
+        optionals: Option<OrderOptionals<'_>>,
+    ) -> Result<APIDatum<HfOrderResp>, APIError> {
+        let endpoint = String::from("/api/v1/hf/orders");
+        let params = market_params(client_oid, symbol, side, size, funds, optionals);
+        self.post_hf(endpoint, params).await
+    }
+
+    /// Places a HF limit order and waits for the matching engine result, returning the order
+    /// status and filled quantities instead of only the order id.
+    pub async fn post_hf_limit_order_sync(
+        &self,
+        client_oid: &str,
+        symbol: &str,
+        side: &str,
+        price: &str,
+        size: &str,
+        optionals: Option<OrderOptionals<'_>>,
+    ) -> Result<APIDatum<HfSyncOrderResp>, APIError> {
+        let endpoint = String::from("/api/v1/hf/orders/sync");
+        let params = limit_params(client_oid, symbol, side, price, size, optionals);
+        self.post_hf(endpoint, params).await
+    }
+
+    /// Places a HF market order and waits for the matching engine result.
+    pub async fn post_hf_market_order_sync(
+        &self,
+        client_oid: &str,
+        symbol: &str,
+        side: &str,
+        size: Option<f32>,
+        funds: Option<f32>,
+        optionals: Option<OrderOptionals<'_>>,
+    ) -> Result<APIDatum<HfSyncOrderResp>, APIError> {
+        let endpoint = String::from("/api/v1/hf/orders/sync");
+        let params = market_params(client_oid, symbol, side, size, funds, optionals);
+        self.post_hf(endpoint, params).await
+    }
+
+    /// Places up to 5 HF orders of the same symbol in a single request. Each order reports
+    /// its own success or failure in the response list.
+    pub async fn post_hf_batch_orders(
+        &self,
+        orders: Vec<HfOrderRequest>,
+    ) -> Result<APIData<HfBatchOrderResp>, APIError> {
+        let endpoint = String::from("/api/v1/hf/orders/multi");
+        let url = format!("{}{}", &self.prefix, endpoint);
+        let body = json!({ "orderList": orders });
+        let headers: header::HeaderMap = self.sign_headers_json(endpoint, &body).unwrap();
+        let resp = self
+            .post_json(url, Some(headers), body)
+            .await?
+            .json()
+            .await?;
+        Ok(resp)
+    }
+
+    /// Modifies the price and/or size of an open HF order, identified by either order id or client oid.
+    /// The exchange cancels and replaces the order atomically and returns the new order id.
+    pub async fn modify_hf_order(
+        &self,
+        symbol: &str,
+        order_id: Option<&str>,
+        client_oid: Option<&str>,
+        new_price: Option<&str>,
+        new_size: Option<&str>,
+    ) -> Result<APIDatum<HfModifyResp>, APIError> {
+        let endpoint = String::from("/api/v1/hf/orders/alter");
+        let mut params: HashMap<String, String> = HashMap::new();
+        params.insert(String::from("symbol"), symbol.to_string());
+        if let Some(o) = order_id {
+            params.insert(String::from("orderId"), o.to_string());
+        }
+        if let Some(c) = client_oid {
+            params.insert(String::from("clientOid"), c.to_string());
+        }
+        if let Some(p) = new_price {
+            params.insert(String::from("newPrice"), p.to_string());
+        }
+        if let Some(s) = new_size {
+            params.insert(String::from("newSize"), s.to_string());
+        }
+        self.post_hf(endpoint, params).await
+    }
+
+    /// Amends an open HF order through the modify endpoint, where the exchange cancels and replaces
+    /// the order atomically. Size is the total size of the amended order, quantity already filled
+    /// is deducted by the exchange. Returns the new order id and the filled size of the old order.
+    pub async fn amend_hf_order(
+        &self,
+        order_id: &str,
+        symbol: &str,
+        price: &str,
+        size: &str,
+    ) -> Result<AmendResp, APIError> {
+        let resp = self
+            .modify_hf_order(symbol, Some(order_id), None, Some(price), Some(size))
+            .await?;
+        let new_order_id = match resp.data {
+            Some(r) => r.new_order_id,
+            None => {
+                return Err(APIError::Other(format!(
+                    "Modify of HF order {} failed, code: {}, msg: {:?}",
+                    order_id, resp.code, resp.msg
+                )))
+            }
+        };
+        let old_filled_size = match self.get_hf_order(order_id, symbol).await?.data {
+            Some(o) => o.deal_size,
+            None => String::from("0"),
+        };
+        Ok(AmendResp {
+            new_order_id: Some(new_order_id),
+            old_filled_size,
+        })
+    }
+
+    pub async fn cancel_hf_order(
+        &self,
+        order_id: &str,
+        symbol: &str,
+    ) -> Result<APIDatum<HfCancelResp>, APIError> {
+        let endpoint = format!("/api/v1/hf/orders/{}", order_id);
+        self.delete_hf(endpoint, symbol).await
+    }
+
+    pub async fn cancel_hf_order_by_client_oid(
+        &self,
+        client_oid: &str,
+        symbol: &str,
+    ) -> Result<APIDatum<HfCancelByClientOidResp>, APIError> {
+        let endpoint = format!("/api/v1/hf/orders/client-order/{}", client_oid);
+        self.delete_hf(endpoint, symbol).await
+    }
+
+    /// Cancels all open HF orders of a symbol.
+    pub async fn cancel_all_hf_orders(&self, symbol: &str) -> Result<APIDatum<String>, APIError> {
+        let endpoint = String::from("/api/v1/hf/orders");
+        self.delete_hf(endpoint, symbol).await
+    }
+
+    pub async fn get_hf_active_orders(
+        &self,
+        symbol: &str,
+    ) -> Result<APIData<HfOrderInfo>, APIError> {
+        let endpoint = String::from("/api/v1/hf/orders/active");
+        let mut params: HashMap<String, String> = HashMap::new();
+        params.insert(String::from("symbol"), symbol.to_string());
+        self.get_hf(endpoint, params).await
+    }
+
+    /// Lists filled and cancelled HF orders of a symbol. Results are paged by last id,
+    /// pass the returned last_id through HfQueryOptionals to fetch the next page.
+    pub async fn get_hf_done_orders(
+        &self,
+        symbol: &str,
+        optionals: Option<HfQueryOptionals<'_>>,
+    ) -> Result<APIDatum<HfDoneOrders>, APIError> {
+        let endpoint = String::from("/api/v1/hf/orders/done");
+        let params = query_params(symbol, optionals);
+        self.get_hf(endpoint, params).await
+    }
+
+    pub async fn get_hf_order(
+        &self,
+        order_id: &str,
+        symbol: &str,
+    ) -> Result<APIDatum<HfOrderInfo>, APIError> {
+        let endpoint = format!("/api/v1/hf/orders/{}", order_id);
+        let mut params: HashMap<String, String> = HashMap::new();
+        params.insert(String::from("symbol"), symbol.to_string());
+        self.get_hf(endpoint, params).await
+    }
+
+    pub async fn get_hf_order_by_client_oid(
+        &self,
+        client_oid: &str,
+        symbol: &str,
+    ) -> Result<APIDatum<HfOrderInfo>, APIError> {
+        let endpoint = format!("/api/v1/hf/orders/client-order/{}", client_oid);
+        let mut params: HashMap<String, String> = HashMap::new();
+        params.insert(String::from("symbol"), symbol.to_string());
+        self.get_hf(endpoint, params).await
+    }
+
+    /// Lists HF fills of a symbol. Results are paged by last id like get_hf_done_orders.
+    pub async fn get_hf_fills(
+        &self,
+        symbol: &str,
+        optionals: Option<HfQueryOptionals<'_>>,
+    ) -> Result<APIDatum<HfFills>, APIError> {
+        let endpoint = String::from("/api/v1/hf/fills");
+        let params = query_params(symbol, optionals);
+        self.get_hf(endpoint, params).await
+    }
+
+    async fn post_hf<T: serde::de::DeserializeOwned>(
+        &self,
+        endpoint: String,
+        params: HashMap<String, String>,
+    ) -> Result<T, APIError> {
+        let url = format!("{}{}", &self.prefix, endpoint);
+        let headers: header::HeaderMap = self
+            .sign_headers(endpoint, Some(&params), None, Method::POST)
+            .unwrap();
+        let resp = self
+            .post(url, Some(headers), Some(params))
+            .await?
+            .json()
+            .await?;
+        Ok(resp)
+    }
+
+    async fn get_hf<T: serde::de::DeserializeOwned>(
+        &self,
+        endpoint: String,
+        params: HashMap<String, String>,
+    ) -> Result<T, APIError> {
+        let query = format_query(&params);
+        let url = format!("{}{}{}", &self.prefix, endpoint, query);
+        let headers: header::HeaderMap = self
+            .sign_headers(endpoint, None, Some(query), Method::GET)
+            .unwrap();
+        let resp = self.get(url, Some(headers)).await?.json().await?;
+        Ok(resp)
+    }
+
+    async fn delete_hf<T: serde::de::DeserializeOwned>(
+        &self,
+        endpoint: String,
+        symbol: &str,
+    ) -> Result<T, APIError> {
+        let query = format!("?symbol={}", symbol);
+        let url = format!("{}{}{}", &self.prefix, endpoint, query);
+        let headers: header::HeaderMap = self
+            .sign_headers(endpoint, None, Some(query), Method::DELETE)
+            .unwrap();
+        let resp = self.delete(url, Some(headers)).await?.json().await?;
+        Ok(resp)
+    }
+}
+
+fn limit_params(
+    client_oid: &str,
+    symbol: &str,
+    side: &str,
+    price: &str,
+    size: &str,
+    optionals: Option<OrderOptionals>,
+) -> HashMap<String, String> {
+    let mut params: HashMap<String, String> = HashMap::new();
+    params.insert(String::from("clientOid"), client_oid.to_string());
+    params.insert(String::from("symbol"), symbol.to_string());
+    params.insert(String::from("type"), String::from("limit"));
+    params.insert(String::from("side"), side.to_string());
+    params.insert(String::from("price"), price.to_string());
+    params.insert(String::from("size"), size.to_string());
+    if let Some(opt) = optionals {
+        params.extend(parse_order(opt));
+    };
+    params
+}
+
+fn market_params(
+    client_oid: &str,
+    symbol: &str,
+    side: &str,
+    size: Option<f32>,
+    funds: Option<f32>,
+    optionals: Option<OrderOptionals>,
+) -> HashMap<String, String> {
+    let mut params: HashMap<String, String> = HashMap::new();
+    params.insert(String::from("clientOid"), client_oid.to_string());
+    params.insert(String::from("symbol"), symbol.to_string());
+    params.insert(String::from("type"), String::from("market"));
+    params.insert(String::from("side"), side.to_string());
+    if let Some(s) = size {
+        params.insert(String::from("size"), s.to_string());
+    };
+    if let Some(f) = funds {
+        params.insert(String::from("funds"), f.to_string());
+    };
+    if let Some(opt) = optionals {
+        params.extend(parse_order(opt));
+    };
+    params
+}
+
+fn query_params(symbol: &str, optionals: Option<HfQueryOptionals>) -> HashMap<String, String> {
+    let mut params: HashMap<String, String> = HashMap::new();
+    params.insert(String::from("symbol"), symbol.to_string());
+    if let Some(opts) = optionals {
+        if let Some(o) = opts.order_id {
+            params.insert(String::from("orderId"), o.to_string());
+        };
+        if let Some(o) = opts.side {
+            params.insert(String::from("side"), o.to_string());
+        };
+        if let Some(o) = opts.r#type {
+            params.insert(String::from("type"), o.to_string());
+        };
+        if let Some(o) = opts.start_at {
+            params.insert(String::from("startAt"), o.to_string());
+        };
+        if let Some(o) = opts.end_at {
+            params.insert(String::from("endAt"), o.to_string());
+        };
+        if let Some(o) = opts.last_id {
+            params.insert(String::from("lastId"), o.to_string());
+        };
+        if let Some(o) = opts.limit {
+            params.insert(String::from("limit"), o.to_string());
+        };
+    };
+    params
+}
+
+/// HfQueryOptionals contains a builder pattern that can be used to more easily take advantage of optional inputs
+/// when listing HF orders and fills.
+///
+/// Example:
+/// ``` rust
+/// use kucoin_rs::kucoin::hf::HfQueryOptionals;
+///
+///     let options = HfQueryOptionals::new()
+///         .side("buy")
+///         .last_id(254062248624417)
+///         .limit(50)
+///         .build();
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct HfQueryOptionals<'a> {
+    pub order_id: Option<&'a str>,
+    pub side: Option<&'a str>,
+    pub r#type: Option<&'a str>,
+    pub start_at: Option<i64>,
+    pub end_at: Option<i64>,
+    pub last_id: Option<i64>,
+    pub limit: Option<i32>,
+}
+
+impl<'a> HfQueryOptionals<'a> {
+    pub fn new() -> Self {
+        HfQueryOptionals {
+            order_id: None,
+            side: None,
+            r#type: None,
+            start_at: None,
+            end_at: None,
+            last_id: None,
+            limit: None,
+        }
+    }
+
+    pub fn order_id(&mut self, s: &'a str) -> &mut Self {
+        self.order_id = Some(s);
+        self
+    }
+
+    pub fn side(&mut self, s: &'a str) -> &mut Self {
+        self.side = Some(s);
+        self
+    }
+
+    pub fn order_type(&mut self, s: &'a str) -> &mut Self {
+        self.r#type = Some(s);
+        self
+    }
+
+    pub fn start_at(&mut self, i: i64) -> &mut Self {
+        self.start_at = Some(i);
+        self
+    }
+
+    pub fn end_at(&mut self, i: i64) -> &mut Self {
+        self.end_at = Some(i);
+        self
+    }
+
+    pub fn last_id(&mut self, i: i64) -> &mut Self {
+        self.last_id = Some(i);
+        self
+    }
+
+    pub fn limit(&mut self, i: i32) -> &mut Self {
+        self.limit = Some(i);
+        self
+    }
+
+    /// Builds an HfQueryOptionals Type from chained optional funtions
+    /// to be used with listing HF orders and fills.
+    pub fn build(&self) -> Self {
+        HfQueryOptionals {
+            order_id: self.order_id,
+            side: self.side,
+            r#type: self.r#type,
+            start_at: self.start_at,
+            end_at: self.end_at,
+            last_id: self.last_id,
+            limit: self.limit,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::kucoin::hf::HfQueryOptionals;
+    use crate::kucoin::model::hf::HfOrderRequest;
+
+    #[test]
+    fn use_build_pattern_some_hf_query_optionals() {
+        let options = HfQueryOptionals {
+            order_id: None,
+            side: Some("buy"),
+            r#type: None,
+            start_at: Some(1_580_683_419_725),
+            end_at: None,
+            last_id: Some(254_062_248_624_417),
+            limit: Some(50),
+        };
+
+        let build_options = HfQueryOptionals::new()
+            .side("buy")
+            .start_at(1_580_683_419_725)
+            .last_id(254_062_248_624_417)
+            .limit(50)
+            .build();
+
+        assert_eq!(options, build_options)
+    }
+
+    #[test]
+    fn hf_order_request_skips_unset_fields() {
+        let order = HfOrderRequest::limit("oid-1", "BTC-USDT", "buy", "20000", "0.01");
+        let body = serde_json::to_string(&order).unwrap();
+        assert_eq!(
+            body,
+            r#"{"clientOid":"oid-1","symbol":"BTC-USDT","type":"limit","side":"buy","price":"20000","size":"0.01"}"#
+        );
+    }
+}
@@ -0,0 +1,322 @@
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures::stream::{self, BoxStream};
+use futures::{Stream, StreamExt};
+use tokio::runtime::Handle;
+use tokio::sync::{broadcast, Mutex};
+use tokio::task::JoinHandle;
+
+use super::client::Kucoin;
+use super::error::APIError;
+use super::model::websocket::{KucoinWebsocketMsg, WSTopic};
+use super::websocket::{KucoinWebsocket, WSControl};
+
+/// Messages held for each subscriber of a topic before the slowest starts missing them.
+pub const HUB_CAPACITY: usize = 1024;
+
+struct HubTopic {
+    tx: broadcast::Sender<KucoinWebsocketMsg>,
+    subscribers: usize,
+    forward: JoinHandle<()>,
+}
+
+// Subscription of a topic, None while it isn't subscribed. Each topic has its own lock so
+// subscribing or releasing a topic, which waits on Kucoin, only holds up that topic.
+type TopicSlot = Arc<Mutex<Option<HubTopic>>>;
+
+struct HubInner {
+    control: WSControl,
+    capacity: usize,
+    topics: std::sync::Mutex<HashMap<WSTopic, TopicSlot>>,
+    drain: JoinHandle<()>,
+}
+
+impl Drop for HubInner {
+    fn drop(&mut self) {
+        self.drain.abort();
+        if let Ok(topics) = self.topics.lock() {
+            for slot in topics.values() {
+                if let Ok(topic) = slot.try_lock() {
+                    if let Some(topic) = topic.as_ref() {
+                        topic.forward.abort();
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Owns the connections of a KucoinWebsocket and shares each subscribed topic with any
+/// number of tasks through broadcast channels. A topic is subscribed on Kucoin once for
+/// all of its subscribers and unsubscribed when the last HubSubscription of it is dropped.
+/// Cloning the hub is cheap and clones share the same topics.
+#[derive(Clone)]
+pub struct MarketHub {
+    inner: Arc<HubInner>,
+}
+
+impl MarketHub {
+    /// Takes over the websocket, which must be created with Kucoin::websocket so topics can
+    /// be subscribed without a url. Messages not belonging to a topic, such as welcome and
+    /// pong messages, are discarded.
+    pub fn new(ws: KucoinWebsocket) -> Self {
+        MarketHub::with_capacity(ws, HUB_CAPACITY)
+    }
+
+    pub fn with_capacity(mut ws: KucoinWebsocket, capacity: usize) -> Self {
+        let control = ws.control();
+        let drain = tokio::spawn(async move { while ws.next().await.is_some() {} });
+        MarketHub {
+            inner: Arc::new(HubInner {
+                control,
+                capacity: capacity.max(1),
+                topics: std::sync::Mutex::new(HashMap::new()),
+                drain,
+            }),
+        }
+    }
+
+    /// Subscribes to the topic, sharing the Kucoin subscription of any other subscriber of the
+    /// same WSTopic.
+    pub async fn subscribe(&self, ws_topic: WSTopic) -> Result<HubSubscription, APIError> {
+        let slot = self.inner.slot(&ws_topic);
+        let mut topic = slot.lock().await;
+        let rx = match topic.as_mut() {
+            Some(topic) => {
+                topic.subscribers += 1;
+                topic.tx.subscribe()
+            }
+            None => {
+                let subscribed = self.inner.control.subscribe_queue(ws_topic.clone()).await;
+                let mut queue = match subscribed {
+                    Ok(queue) => queue,
+                    Err(e) => {
+                        drop(topic);
+                        self.inner.prune(&ws_topic, slot);
+                        return Err(e);
+                    }
+                };
+                let (tx, rx) = broadcast::channel(self.inner.capacity);
+                let sender = tx.clone();
+                let forward = tokio::spawn(async move {
+                    while let Some(msg) = queue.next().await {
+                        if let Ok(msg) = msg {
+                            let _ = sender.send(msg);
+                        }
+                    }
+                });
+                *topic = Some(HubTopic {
+                    tx,
+                    subscribers: 1,
+                    forward,
+                });
+                rx
+            }
+        };
+        Ok(HubSubscription::new(self.inner.clone(), ws_topic, rx))
+    }
+
+    /// Topics with at least one subscriber and their number of subscribers.
+    pub async fn topics(&self) -> Vec<(WSTopic, usize)> {
+        let slots: Vec<(WSTopic, TopicSlot)> = self
+            .inner
+            .topics
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(ws_topic, slot)| (ws_topic.clone(), slot.clone()))
+            .collect();
+        let mut topics = Vec::new();
+        for (ws_topic, slot) in slots {
+            if let Some(topic) = slot.lock().await.as_ref() {
+                topics.push((ws_topic, topic.subscribers));
+            }
+        }
+        topics
+    }
+
+    /// Handle of the underlying connections, e.g. to set buffer policies or read metrics.
+    pub fn control(&self) -> WSControl {
+        self.inner.control.clone()
+    }
+}
+
+impl HubInner {
+    fn slot(&self, ws_topic: &WSTopic) -> TopicSlot {
+        let mut topics = self.topics.lock().unwrap();
+        topics.entry(ws_topic.clone()).or_default().clone()
+    }
+
+    // Forgets the slot of a topic left unsubscribed, unless another task is waiting on it.
+    fn prune(&self, ws_topic: &WSTopic, slot: TopicSlot) {
+        let mut topics = self.topics.lock().unwrap();
+        let unused = topics
+            .get(ws_topic)
+            .is_some_and(|s| Arc::ptr_eq(s, &slot) && Arc::strong_count(&slot) == 2);
+        if unused && slot.try_lock().is_ok_and(|topic| topic.is_none()) {
+            topics.remove(ws_topic);
+        }
+    }
+
+    // Only the topic's own lock is held while Kucoin acknowledges the unsubscribe, a new
+    // subscriber of the topic waits for it instead of sharing a subscription being removed.
+    async fn release(&self, ws_topic: WSTopic) -> Result<(), APIError> {
+        let slot = match self.topics.lock().unwrap().get(&ws_topic) {
+            Some(slot) => slot.clone(),
+            None => return Ok(()),
+        };
+        let mut topic = slot.lock().await;
+        let last = match topic.as_mut() {
+            Some(topic) => {
+                topic.subscribers -= 1;
+                topic.subscribers == 0
+            }
+            None => false,
+        };
+        let mut released = Ok(());
+        if last {
+            if let Some(topic) = topic.take() {
+                topic.forward.abort();
+            }
+            released = self.control.unsubscribe(ws_topic.clone()).await;
+        }
+        drop(topic);
+        self.prune(&ws_topic, slot);
+        released
+    }
+}
+
+/// Stream of the messages of one topic of a MarketHub. A subscriber falling more than the
+/// hub capacity behind gets an error with the number of messages it missed and continues
+/// from the oldest message still held.
+pub struct HubSubscription {
+    hub: Arc<HubInner>,
+    topic: WSTopic,
+    rx: BoxStream<'static, Result<KucoinWebsocketMsg, APIError>>,
+}
+
+impl HubSubscription {
+    fn new(
+        hub: Arc<HubInner>,
+        topic: WSTopic,
+        rx: broadcast::Receiver<KucoinWebsocketMsg>,
+    ) -> Self {
+        let name = topic.topic();
+        let rx = stream::unfold((rx, name), |(mut rx, name)| async move {
+            let msg = match rx.recv().await {
+                Ok(msg) => Ok(msg),
+                Err(broadcast::error::RecvError::Lagged(missed)) => Err(APIError::Other(format!(
+                    "Subscriber of {} lagged behind, missed {} messages",
+                    name, missed
+                ))),
+                Err(broadcast::error::RecvError::Closed) => return None,
+            };
+            Some((msg, (rx, name)))
+        })
+        .boxed();
+        HubSubscription { hub, topic, rx }
+    }
+
+    pub fn topic(&self) -> &WSTopic {
+        &self.topic
+    }
+}
+
+impl Stream for HubSubscription {
+    type Item = Result<KucoinWebsocketMsg, APIError>;
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_next_unpin(cx)
+    }
+}
+
+impl Drop for HubSubscription {
+    // The hub's bookkeeping is async, release the topic from a task.
+    fn drop(&mut self) {
+        if let Ok(handle) = Handle::try_current() {
+            let hub = self.hub.clone();
+            let topic = self.topic.clone();
+            handle.spawn(async move {
+                let _ = hub.release(topic).await;
+            });
+        }
+    }
+}
+
+impl Kucoin {
+    /// MarketHub over a new websocket of this client.
+    pub fn market_hub(&self) -> MarketHub {
+        MarketHub::new(self.websocket())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::kucoin::fake::fake_kucoin;
+    use crate::kucoin::model::websocket::{KucoinWebsocketMsg, WSTopic};
+    use futures::StreamExt;
+
+    const MATCH: &str = r#"{"type":"message","topic":"/market/match:BTC-USDT","subject":"trade.l3match","data":{"sequence":"1545896669145","type":"match","symbol":"BTC-USDT","side":"buy","price":"0.082","size":"0.0102","tradeId":"5c24c5da03aa673885cd67aa","takerOrderId":"5c24c5d903aa6772d55b371e","makerOrderId":"5c2187d003aa677bd09d5c93","time":"1545913818099033203"}}"#;
+
+    #[tokio::test]
+    async fn last_subscriber_releases_topic() {
+        let (api, mut requests, frames) = fake_kucoin().await;
+        let hub = api.market_hub();
+        let topic = WSTopic::Match(vec!["BTC-USDT".to_string()]);
+        let mut first = hub.subscribe(topic.clone()).await.unwrap();
+        let mut second = hub.subscribe(topic.clone()).await.unwrap();
+        assert_eq!(hub.topics().await, vec![(topic.clone(), 2)]);
+
+        frames.unbounded_send(MATCH.to_string()).unwrap();
+        for sub in [&mut first, &mut second].iter_mut() {
+            assert!(matches!(
+                sub.next().await,
+                Some(Ok(KucoinWebsocketMsg::MatchMsg(_)))
+            ));
+        }
+
+        // The remaining subscriber keeps receiving the topic
+        drop(first);
+        frames.unbounded_send(MATCH.to_string()).unwrap();
+        assert!(matches!(
+            second.next().await,
+            Some(Ok(KucoinWebsocketMsg::MatchMsg(_)))
+        ));
+        assert_eq!(hub.topics().await, vec![(topic, 1)]);
+
+        // Releasing the topic closes the connection it was the last topic of
+        drop(second);
+        let req = requests.next().await.unwrap();
+        assert_eq!(req["type"], "subscribe");
+        assert_eq!(req["topic"], "/market/match:BTC-USDT");
+        assert!(requests.next().await.is_none());
+        assert!(hub.topics().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn topics_sharing_a_topic_string_get_their_own_subjects() {
+        let (api, mut requests, frames) = fake_kucoin().await;
+        let hub = api.market_hub();
+        let mut debt = hub.subscribe(WSTopic::DebtRatio).await.unwrap();
+        let mut position = hub.subscribe(WSTopic::PositionChange).await.unwrap();
+        let req = requests.next().await.unwrap();
+        assert_eq!(req["topic"], "/margin/position");
+
+        frames
+            .unbounded_send(r#"{"type":"message","topic":"/margin/position","subject":"debt.ratio","channelType":"private","data":{"debtRatio":0.8,"totalDebt":"21.7","debtList":{"USDT":"21.7"},"timestamp":15538460812100}}"#.to_string())
+            .unwrap();
+        frames
+            .unbounded_send(r#"{"type":"message","topic":"/margin/position","subject":"position.status","channelType":"private","data":{"type":"FROZEN_FL","timestamp":15538460812100}}"#.to_string())
+            .unwrap();
+        assert!(matches!(
+            debt.next().await,
+            Some(Ok(KucoinWebsocketMsg::DebtRatioMsg(_)))
+        ));
+        assert!(matches!(
+            position.next().await,
+            Some(Ok(KucoinWebsocketMsg::PositionChangeMsg(_)))
+        ));
+    }
+}
@@ -0,0 +1,489 @@
+use std::io::Write;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use super::client::Kucoin;
+use super::error::APIError;
+use super::model::user::AccountInfo;
+use super::model::Pagination;
+
+/// Longest time range Kucoin accepts in one ledger query.
+pub const LEDGER_WINDOW: i64 = 24 * 60 * 60 * 1000;
+/// Default time between two ledger requests.
+pub const LEDGER_INTERVAL: Duration = Duration::from_millis(200);
+// Largest page Kucoin returns for ledgers.
+const LEDGER_PAGE_SIZE: i32 = 500;
+// Code of a rate limited request, retried after LEDGER_BACKOFF, doubled on every attempt up
+// to 64 times.
+const RATE_LIMITED: &str = "429000";
+const LEDGER_BACKOFF: Duration = Duration::from_secs(1);
+
+/// What a ledger entry records, normalized from Kucoin's bizType.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BizType {
+    Trade,
+    Deposit,
+    Withdrawal,
+    Transfer,
+    Fee,
+    Interest,
+    /// Rewards, airdrops and any bizType not listed above
+    Other,
+}
+
+impl BizType {
+    pub fn from_biz_type(biz_type: &str) -> Self {
+        let b = biz_type.to_lowercase();
+        if b.contains("interest") {
+            BizType::Interest
+        } else if b.contains("fee") {
+            BizType::Fee
+        } else if b.contains("deposit") {
+            BizType::Deposit
+        } else if b.contains("withdraw") {
+            BizType::Withdrawal
+        } else if b.contains("transfer") {
+            BizType::Transfer
+        } else if b.contains("exchange") || b.contains("trade") || b.contains("convert") {
+            BizType::Trade
+        } else {
+            BizType::Other
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BizType::Trade => "trade",
+            BizType::Deposit => "deposit",
+            BizType::Withdrawal => "withdrawal",
+            BizType::Transfer => "transfer",
+            BizType::Fee => "fee",
+            BizType::Interest => "interest",
+            BizType::Other => "other",
+        }
+    }
+}
+
+/// Ledger entry of one account. Amounts are kept as Kucoin's decimal strings so exports
+/// are exact.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LedgerEntry {
+    /// Ledger id, when Kucoin returns one
+    pub id: Option<String>,
+    /// Milliseconds since the epoch
+    pub time: i64,
+    pub account_id: String,
+    pub account_type: String,
+    pub currency: String,
+    pub biz_type: BizType,
+    /// bizType as returned by Kucoin
+    pub kucoin_biz_type: String,
+    /// true for credits, false for debits
+    pub incoming: bool,
+    pub amount: String,
+    pub fee: String,
+    /// Balance of the account after the entry
+    pub balance: String,
+    pub context: Option<String>,
+}
+
+impl LedgerEntry {
+    pub fn new(account_id: &str, account_type: &str, info: &AccountInfo) -> Self {
+        LedgerEntry {
+            id: info.id.clone(),
+            time: info.created_at,
+            account_id: account_id.to_string(),
+            account_type: account_type.to_string(),
+            currency: info.currency.clone(),
+            biz_type: BizType::from_biz_type(&info.biz_type),
+            kucoin_biz_type: info.biz_type.clone(),
+            incoming: info.direction == "in",
+            amount: info.amount.clone(),
+            fee: info.fee.clone(),
+            balance: info.balance.clone(),
+            context: info.context.clone(),
+        }
+    }
+
+    /// Amount with a minus sign for debits.
+    pub fn signed_amount(&self) -> String {
+        if self.incoming || self.amount.starts_with('-') {
+            self.amount.clone()
+        } else {
+            format!("-{}", self.amount)
+        }
+    }
+}
+
+/// Time range and accounts to export ledgers of. The range is queried in windows Kucoin
+/// accepts, each read page by page, with requests spaced by an interval and rate limited or
+/// failed requests retried with backoff.
+#[derive(Debug, Clone)]
+pub struct LedgerExport {
+    start_at: i64,
+    end_at: i64,
+    window: i64,
+    currency: Option<String>,
+    account_type: Option<String>,
+    interval: Duration,
+    retries: u32,
+}
+
+impl LedgerExport {
+    /// Entries from start_at up to end_at, in milliseconds.
+    pub fn new(start_at: i64, end_at: i64) -> Self {
+        LedgerExport {
+            start_at,
+            end_at,
+            window: LEDGER_WINDOW,
+            currency: None,
+            account_type: None,
+            interval: LEDGER_INTERVAL,
+            retries: 5,
+        }
+    }
+
+    /// Only accounts of the currency.
+    pub fn currency(&mut self, currency: &str) -> &mut Self {
+        self.currency = Some(currency.to_string());
+        self
+    }
+
+    /// Only accounts of the type, such as main or trade.
+    pub fn account_type(&mut self, account_type: &str) -> &mut Self {
+        self.account_type = Some(account_type.to_string());
+        self
+    }
+
+    /// Length of each query in milliseconds, capped at LEDGER_WINDOW.
+    pub fn window(&mut self, window: i64) -> &mut Self {
+        self.window = window.clamp(1, LEDGER_WINDOW);
+        self
+    }
+
+    /// Time between two requests, defaults to LEDGER_INTERVAL.
+    pub fn interval(&mut self, interval: Duration) -> &mut Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Times a rate limited or failed request is retried before the export fails, defaults
+    /// to 5.
+    pub fn retries(&mut self, retries: u32) -> &mut Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Every entry of the matching accounts, oldest first.
+    #[allow(clippy::result_large_err)]
+    pub async fn fetch(&self, api: &Kucoin) -> Result<Vec<LedgerEntry>, APIError> {
+        let mut entries = Vec::new();
+        self.fetch_each(api, |e| {
+            entries.push(e);
+            Ok(())
+        })
+        .await?;
+        entries.sort_by(|a, b| (a.time, &a.account_id).cmp(&(b.time, &b.account_id)));
+        Ok(entries)
+    }
+
+    /// Hands every entry to on_entry as it is fetched, account by account and oldest first
+    /// within an account, returning how many there were. Entries handed on before a failure
+    /// are kept by on_entry, so a failed export can resume after the last one.
+    pub async fn fetch_each<F>(&self, api: &Kucoin, mut on_entry: F) -> Result<usize, APIError>
+    where
+        F: FnMut(LedgerEntry) -> Result<(), APIError>,
+    {
+        let accounts = api
+            .get_accounts_list(self.currency.as_deref(), self.account_type.as_deref())
+            .await?
+            .into_data()?;
+        let mut next = Instant::now();
+        let mut count = 0;
+        for account in accounts.iter() {
+            let mut boundary = Vec::new();
+            for (start, end) in windows(self.start_at, self.end_at, self.window) {
+                let mut window = Vec::new();
+                let mut page = 1;
+                loop {
+                    let items = self
+                        .page(api, &account.id, start, end, page, &mut next)
+                        .await?;
+                    window.extend(
+                        items
+                            .items
+                            .iter()
+                            .map(|i| LedgerEntry::new(&account.id, &account.r#type, i)),
+                    );
+                    if items.items.is_empty() || page >= items.total_page {
+                        break;
+                    }
+                    page += 1;
+                }
+                for entry in dedupe(&mut boundary, window, end) {
+                    on_entry(entry)?;
+                    count += 1;
+                }
+            }
+        }
+        Ok(count)
+    }
+
+    /// Fetches the entries and writes them as CSV as they arrive, see fetch_each and
+    /// write_csv.
+    #[allow(clippy::result_large_err)]
+    pub async fn export_csv<W: Write>(&self, api: &Kucoin, mut out: W) -> Result<usize, APIError> {
+        csv_header(&mut out)?;
+        let count = self.fetch_each(api, |e| csv_row(&mut out, &e)).await?;
+        out.flush().map_err(csv_error)?;
+        Ok(count)
+    }
+
+    /// Fetches the entries and writes them as JSON lines as they arrive, see fetch_each.
+    #[allow(clippy::result_large_err)]
+    pub async fn export_jsonl<W: Write>(
+        &self,
+        api: &Kucoin,
+        mut out: W,
+    ) -> Result<usize, APIError> {
+        let count = self.fetch_each(api, |e| jsonl_row(&mut out, &e)).await?;
+        out.flush().map_err(jsonl_error)?;
+        Ok(count)
+    }
+
+    // Reads a page no sooner than `next`, retrying rate limited and failed requests with
+    // backoff.
+    async fn page(
+        &self,
+        api: &Kucoin,
+        account_id: &str,
+        start: i64,
+        end: i64,
+        page: i32,
+        next: &mut Instant,
+    ) -> Result<Pagination<AccountInfo>, APIError> {
+        let mut attempt = 0;
+        loop {
+            tokio::time::sleep_until(*next).await;
+            *next = Instant::now() + self.interval;
+            let resp = api
+                .get_account_ledgers(
+                    account_id,
+                    Some(start),
+                    Some(end),
+                    Some(page),
+                    Some(LEDGER_PAGE_SIZE),
+                )
+                .await;
+            let retry = match &resp {
+                Ok(r) => r.code == RATE_LIMITED,
+                Err(APIError::HTTP(_)) => true,
+                Err(_) => false,
+            };
+            if !retry || attempt >= self.retries {
+                return resp?.into_data();
+            }
+            *next = Instant::now() + (LEDGER_BACKOFF * 2u32.pow(attempt.min(6))).max(self.interval);
+            attempt += 1;
+        }
+    }
+}
+
+// Sorts the entries of a window oldest first, dropping those already handed on with the
+// previous window in case both include the time they share. Keeps the entries at the end
+// of this window for the next one.
+fn dedupe(
+    boundary: &mut Vec<LedgerEntry>,
+    mut window: Vec<LedgerEntry>,
+    end: i64,
+) -> Vec<LedgerEntry> {
+    window.sort_by_key(|e| e.time);
+    window.retain(|e| !boundary.contains(e));
+    *boundary = window.iter().filter(|e| e.time >= end).cloned().collect();
+    window
+}
+
+// Splits [start, end) into consecutive ranges of at most len.
+fn windows(start: i64, end: i64, len: i64) -> Vec<(i64, i64)> {
+    let mut ranges = Vec::new();
+    let mut from = start;
+    while from < end {
+        let to = end.min(from.saturating_add(len));
+        ranges.push((from, to));
+        from = to;
+    }
+    ranges
+}
+
+// ISO 8601 UTC time of milliseconds since the epoch.
+fn format_utc(ms: i64) -> String {
+    let secs = ms.div_euclid(1000);
+    let days = secs.div_euclid(86400);
+    let rem = secs.rem_euclid(86400);
+    // Civil date from days since 1970-01-01, proleptic Gregorian calendar
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60,
+        ms.rem_euclid(1000)
+    )
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn csv_error(e: std::io::Error) -> APIError {
+    APIError::Other(format!("Failed writing ledger CSV: {}", e))
+}
+
+fn jsonl_error(e: std::io::Error) -> APIError {
+    APIError::Other(format!("Failed writing ledger JSON lines: {}", e))
+}
+
+#[allow(clippy::result_large_err)]
+fn csv_header<W: Write>(out: &mut W) -> Result<(), APIError> {
+    writeln!(
+        out,
+        "date,time,account_id,account_type,currency,type,kucoin_type,amount,fee,balance,context"
+    )
+    .map_err(csv_error)
+}
+
+#[allow(clippy::result_large_err)]
+fn csv_row<W: Write>(out: &mut W, e: &LedgerEntry) -> Result<(), APIError> {
+    let row = [
+        format_utc(e.time),
+        e.time.to_string(),
+        csv_field(&e.account_id),
+        csv_field(&e.account_type),
+        csv_field(&e.currency),
+        e.biz_type.as_str().to_string(),
+        csv_field(&e.kucoin_biz_type),
+        e.signed_amount(),
+        e.fee.clone(),
+        e.balance.clone(),
+        csv_field(e.context.as_deref().unwrap_or_default()),
+    ];
+    writeln!(out, "{}", row.join(",")).map_err(csv_error)
+}
+
+#[allow(clippy::result_large_err)]
+fn jsonl_row<W: Write>(out: &mut W, e: &LedgerEntry) -> Result<(), APIError> {
+    serde_json::to_writer(&mut *out, e)?;
+    out.write_all(b"\n").map_err(jsonl_error)
+}
+
+/// Writes the entries as CSV with a header row. Debits have a negative amount.
+#[allow(clippy::result_large_err)]
+pub fn write_csv<W: Write>(entries: &[LedgerEntry], mut out: W) -> Result<(), APIError> {
+    csv_header(&mut out)?;
+    for e in entries {
+        csv_row(&mut out, e)?;
+    }
+    out.flush().map_err(csv_error)
+}
+
+/// Writes each entry as a JSON object on its own line.
+#[allow(clippy::result_large_err)]
+pub fn write_jsonl<W: Write>(entries: &[LedgerEntry], mut out: W) -> Result<(), APIError> {
+    for e in entries {
+        jsonl_row(&mut out, e)?;
+    }
+    out.flush().map_err(jsonl_error)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::kucoin::ledger::{
+        dedupe, format_utc, windows, write_csv, write_jsonl, BizType, LedgerEntry,
+    };
+    use crate::kucoin::model::user::AccountInfo;
+
+    #[test]
+    fn windows_cover_range_and_dates_format() {
+        assert_eq!(windows(0, 25, 10), vec![(0, 10), (10, 20), (20, 25)]);
+        assert!(windows(5, 5, 10).is_empty());
+        assert_eq!(format_utc(0), "1970-01-01T00:00:00.000Z");
+        assert_eq!(format_utc(1_709_251_199_999), "2024-02-29T23:59:59.999Z");
+    }
+
+    #[test]
+    fn entries_export_to_csv_and_jsonl() {
+        let infos: Vec<AccountInfo> = serde_json::from_str(
+            r#"[
+            {"currency":"BTC","amount":"0.5","fee":"0","balance":"0.5","bizType":"Deposit","direction":"in","createdAt":1600000000000,"context":null},
+            {"currency":"BTC","amount":"0.1","fee":"0.0001","balance":"0.4","bizType":"Exchange","direction":"out","createdAt":1600000001000,"context":"{\"symbol\":\"BTC-USDT\",\"orderId\":\"1\"}"}
+            ]"#,
+        )
+        .unwrap();
+        let entries: Vec<LedgerEntry> = infos
+            .iter()
+            .map(|i| LedgerEntry::new("a1", "trade", i))
+            .collect();
+        assert_eq!(entries[0].biz_type, BizType::Deposit);
+        assert_eq!(entries[1].biz_type, BizType::Trade);
+        assert_eq!(BizType::from_biz_type("Margin Interest"), BizType::Interest);
+        assert_eq!(BizType::from_biz_type("KCS Pay Fees"), BizType::Fee);
+
+        let mut csv = Vec::new();
+        write_csv(&entries, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[2],
+            r#"2020-09-13T12:26:41.000Z,1600000001000,a1,trade,BTC,trade,Exchange,-0.1,0.0001,0.4,"{""symbol"":""BTC-USDT"",""orderId"":""1""}""#
+        );
+
+        let mut jsonl = Vec::new();
+        write_jsonl(&entries, &mut jsonl).unwrap();
+        let first: serde_json::Value =
+            serde_json::from_str(String::from_utf8(jsonl).unwrap().lines().next().unwrap())
+                .unwrap();
+        assert_eq!(first["bizType"], "deposit");
+        assert_eq!(first["amount"], "0.5");
+    }
+
+    #[test]
+    fn entries_on_window_boundaries_are_handed_on_once() {
+        let infos: Vec<AccountInfo> = serde_json::from_str(
+            r#"[
+            {"id":"3","currency":"BTC","amount":"0.1","fee":"0","balance":"0.6","bizType":"Deposit","direction":"in","createdAt":20,"context":null},
+            {"id":"2","currency":"BTC","amount":"0.1","fee":"0","balance":"0.5","bizType":"Deposit","direction":"in","createdAt":10,"context":null},
+            {"id":"1","currency":"BTC","amount":"0.4","fee":"0","balance":"0.4","bizType":"Deposit","direction":"in","createdAt":5,"context":null}
+            ]"#,
+        )
+        .unwrap();
+        let entry = |i: usize| LedgerEntry::new("a1", "main", &infos[i]);
+        let mut boundary = Vec::new();
+        let first = dedupe(&mut boundary, vec![entry(1), entry(2)], 10);
+        assert_eq!(
+            first.iter().map(|e| e.time).collect::<Vec<_>>(),
+            vec![5, 10]
+        );
+        // The next window starts at 10 and returns its entry again
+        let second = dedupe(&mut boundary, vec![entry(0), entry(1)], 20);
+        assert_eq!(second, vec![entry(0)]);
+    }
+}
@@ -67,16 +67,16 @@ impl Kucoin {
             OrderBookType::L20 | OrderBookType::L100 => {
                 let url = format!("{}{}", &self.prefix, endpoint);
                 let resp: APIDatum<OrderBook> = self.get(url, None).await?.json().await?;
-                return Ok(resp)
-            },
+                Ok(resp)
+            }
             OrderBookType::Full => {
                 let url = format!("{}{}", &self.prefix, endpoint);
                 let headers: header::HeaderMap = self
                     .sign_headers(endpoint, None, None, Method::GET)
                     .unwrap();
                 let resp = self.get(url, Some(headers)).await?.json().await?;
-                return Ok(resp)
-            },
+                Ok(resp)
+            }
         }
     }
 
@@ -128,10 +128,10 @@ impl Kucoin {
         }
         endpoint.push_str(&format!("&symbol={}", symbol));
         if let Some(t) = start_at {
-            endpoint.push_str(&format!("&startAt={}", t.to_string()));
+            endpoint.push_str(&format!("&startAt={}", t));
         }
         if let Some(t) = end_at {
-            endpoint.push_str(&format!("&endAt={}", t.to_string()));
+            endpoint.push_str(&format!("&endAt={}", t));
         }
         let url = format!("{}{}", &self.prefix, endpoint);
         let resp = self.get(url, None).await?.json().await?;
@@ -170,19 +170,18 @@ impl Kucoin {
     ) -> Result<APIDatum<HashMap<String, String>>, APIError> {
         let endpoint = String::from("/api/v1/prices");
         let mut params: HashMap<String, String> = HashMap::new();
-        let url: String;
         if let Some(b) = base {
             params.insert(String::from("base"), b.to_string());
         }
         if let Some(c) = currencies {
             params.insert(String::from("currencies"), c.to_string());
         }
-        if !params.is_empty() {
+        let url = if !params.is_empty() {
             let query = format_query(&params);
-            url = format!("{}{}{}", &self.prefix, endpoint, query);
+            format!("{}{}{}", &self.prefix, endpoint, query)
         } else {
-            url = format!("{}{}", &self.prefix, endpoint);
-        }
+            format!("{}{}", &self.prefix, endpoint)
+        };
         let resp = self.get(url, None).await?.json().await?;
         Ok(resp)
     }
@@ -1,10 +1,38 @@
+/// Backtesting strategies over historical market data
+pub mod backtest;
+/// Bounded buffering of websocket messages
+pub mod buffer;
 /// Main Kucoin API Client w/ All Endpoints
 pub mod client;
 pub mod error;
+/// Exchange traits implemented by Kucoin
+pub mod exchange;
+/// TWAP, VWAP and percent of volume execution of large orders
+pub mod execution;
+// Local stand-ins for the Kucoin servers in tests
+#[cfg(test)]
+mod fake;
+/// Fee rates and fee-aware order calculations
+pub mod fee;
+pub mod hf;
+/// Shared market data subscriptions
+pub mod hub;
+/// Account ledger export to CSV and JSON lines
+pub mod ledger;
 pub mod margin;
 pub mod market;
 /// API Response Strucs
 pub mod model;
+/// Simulated exchange for paper trading
+pub mod paper;
+/// Realized and unrealized PnL from fills
+pub mod pnl;
+/// Balances kept current from REST and websocket pushes
+pub mod portfolio;
+/// Recording and replay of raw websocket frames
+pub mod recorder;
+/// Strategy trait and runtime for live, paper and replayed trading
+pub mod strategy;
 pub mod trade;
 pub mod user;
 /// Utility Functions
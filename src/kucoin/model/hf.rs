@@ -0,0 +1,183 @@
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HfOrderResp {
+    pub order_id: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HfSyncOrderResp {
+    pub order_id: String,
+    pub order_time: i64,
+    pub origin_size: String,
+    pub deal_size: String,
+    pub remain_size: String,
+    pub canceled_size: String,
+    pub status: String,
+    pub match_time: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HfModifyResp {
+    pub new_order_id: String,
+    pub client_oid: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HfCancelResp {
+    pub order_id: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HfCancelByClientOidResp {
+    pub client_oid: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HfBatchOrderResp {
+    pub order_id: Option<String>,
+    pub success: bool,
+    pub fail_msg: Option<String>,
+}
+
+/// A single order of a HF batch placement. Limit orders require price and size,
+/// market orders one of size or funds.
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HfOrderRequest {
+    pub client_oid: String,
+    pub symbol: String,
+    pub r#type: String,
+    pub side: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub price: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub funds: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stp: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remark: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_in_force: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cancel_after: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub post_only: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hidden: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iceberg: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub visible_size: Option<String>,
+}
+
+impl HfOrderRequest {
+    pub fn limit(client_oid: &str, symbol: &str, side: &str, price: &str, size: &str) -> Self {
+        HfOrderRequest {
+            client_oid: client_oid.to_string(),
+            symbol: symbol.to_string(),
+            r#type: String::from("limit"),
+            side: side.to_string(),
+            price: Some(price.to_string()),
+            size: Some(size.to_string()),
+            ..Default::default()
+        }
+    }
+
+    pub fn market(
+        client_oid: &str,
+        symbol: &str,
+        side: &str,
+        size: Option<&str>,
+        funds: Option<&str>,
+    ) -> Self {
+        HfOrderRequest {
+            client_oid: client_oid.to_string(),
+            symbol: symbol.to_string(),
+            r#type: String::from("market"),
+            side: side.to_string(),
+            size: size.map(|s| s.to_string()),
+            funds: funds.map(|f| f.to_string()),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HfOrderInfo {
+    pub id: String,
+    pub symbol: String,
+    pub op_type: String,
+    pub r#type: String,
+    pub side: String,
+    pub price: String,
+    pub size: String,
+    pub funds: String,
+    pub deal_size: String,
+    pub deal_funds: String,
+    pub fee: String,
+    pub fee_currency: String,
+    pub stp: Option<String>,
+    pub time_in_force: String,
+    pub post_only: bool,
+    pub hidden: bool,
+    pub iceberg: bool,
+    pub visible_size: String,
+    pub cancel_after: i64,
+    pub channel: String,
+    pub client_oid: String,
+    pub remark: Option<String>,
+    pub tags: Option<String>,
+    pub active: bool,
+    pub in_order_book: bool,
+    pub cancel_exist: bool,
+    pub created_at: i64,
+    pub last_updated_at: i64,
+    pub trade_type: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HfDoneOrders {
+    pub last_id: i64,
+    pub items: Vec<HfOrderInfo>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HfFillsInfo {
+    pub id: i64,
+    pub symbol: String,
+    pub trade_id: i64,
+    pub order_id: String,
+    pub counter_order_id: String,
+    pub side: String,
+    pub liquidity: String,
+    pub force_taker: bool,
+    pub price: String,
+    pub size: String,
+    pub funds: String,
+    pub fee: String,
+    pub fee_rate: String,
+    pub fee_currency: String,
+    pub stop: String,
+    pub trade_type: String,
+    pub r#type: String,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HfFills {
+    pub last_id: i64,
+    pub items: Vec<HfFillsInfo>,
+}
@@ -1,4 +1,4 @@
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SymbolList {
     pub symbol: String,
@@ -16,7 +16,7 @@ pub struct SymbolList {
     pub is_margin_enabled: bool,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Ticker {
     pub sequence: String,
@@ -67,7 +67,7 @@ pub struct DailyStats {
     pub last: String,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct OrderBook {
     pub sequence: String,
@@ -85,13 +85,14 @@ pub struct AtomicOrderBook {
     pub asks: Vec<(String, String, String, i64)>,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum OrderBookType {
     L20,
     L100,
     Full,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct TradeHistories {
     pub sequence: String,
@@ -101,6 +102,38 @@ pub struct TradeHistories {
     pub time: i64,
 }
 
+/// A kline row of get_klines, prices and amounts are decimal strings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candle {
+    /// Start of the candle in seconds since the epoch
+    pub time: i64,
+    pub open: String,
+    pub close: String,
+    pub high: String,
+    pub low: String,
+    pub volume: String,
+    pub turnover: String,
+}
+
+impl Candle {
+    /// Reads a row ordered as Kucoin returns them: time, open, close, high, low, volume, turnover.
+    pub fn from_row(row: &[String]) -> Option<Self> {
+        match row {
+            [time, open, close, high, low, volume, turnover, ..] => Some(Candle {
+                time: time.parse().ok()?,
+                open: open.clone(),
+                close: close.clone(),
+                high: high.clone(),
+                low: low.clone(),
+                volume: volume.clone(),
+                turnover: turnover.clone(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Klines {
     K1min,
     K3min,
@@ -1,10 +1,13 @@
 //! All Kucoin API endpoint response objects
+pub mod hf;
 pub mod margin;
 pub mod market;
 pub mod trade;
 pub mod user;
 pub mod websocket;
 
+use super::error::APIError;
+
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct APIData<T> {
@@ -13,6 +16,17 @@ pub struct APIData<T> {
     pub msg: Option<String>,
 }
 
+impl<T> APIData<T> {
+    /// The response data, or an error with Kucoin's code and message when there is none.
+    #[allow(clippy::result_large_err)]
+    pub fn into_data(self) -> Result<Vec<T>, APIError> {
+        match self.data {
+            Some(d) => Ok(d),
+            None => Err(no_data(&self.code, &self.msg)),
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct APIDatum<T> {
@@ -21,6 +35,24 @@ pub struct APIDatum<T> {
     pub msg: Option<String>,
 }
 
+impl<T> APIDatum<T> {
+    /// The response data, or an error with Kucoin's code and message when there is none.
+    #[allow(clippy::result_large_err)]
+    pub fn into_data(self) -> Result<T, APIError> {
+        match self.data {
+            Some(d) => Ok(d),
+            None => Err(no_data(&self.code, &self.msg)),
+        }
+    }
+}
+
+fn no_data(code: &str, msg: &Option<String>) -> APIError {
+    APIError::Other(format!(
+        "Kucoin returned no data, code: {}, msg: {:?}",
+        code, msg
+    ))
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub enum Method {
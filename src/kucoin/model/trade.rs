@@ -1,23 +1,48 @@
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct OrderResp {
     pub order_id: String,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct CancelResp {
     pub cancelled_order_ids: Vec<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct CancelByClientOidResp {
     pub cancelled_order_id: String,
     pub client_oid: String,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+/// Result of amending an order. new_order_id is None when the old order had already
+/// filled the requested size, in which case nothing was resubmitted.
+#[derive(Debug)]
+pub struct AmendResp {
+    pub new_order_id: Option<String>,
+    pub old_filled_size: String,
+}
+
+/// Outcome for a single order of a filtered bulk cancel.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CancelReport {
+    pub order_id: String,
+    pub client_oid: String,
+    pub status: CancelStatus,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub enum CancelStatus {
+    Cancelled,
+    /// The order filled or was cancelled elsewhere before the request reached it.
+    AlreadyDone,
+    Failed(String),
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct OrderInfo {
     pub id: String,
@@ -64,7 +89,7 @@ pub struct HistoricalOrder {
     created_at: i64,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct FillsInfo {
     pub symbol: String,
@@ -85,3 +110,36 @@ pub struct FillsInfo {
     pub created_at: i64,
     pub trade_type: String,
 }
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OcoOrder {
+    pub order_id: String,
+    pub symbol: String,
+    pub client_oid: String,
+    pub order_time: i64,
+    pub status: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OcoOrderDetails {
+    pub order_id: String,
+    pub symbol: String,
+    pub client_oid: String,
+    pub order_time: i64,
+    pub status: String,
+    pub orders: Vec<OcoLegOrder>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OcoLegOrder {
+    pub id: String,
+    pub symbol: String,
+    pub side: String,
+    pub price: String,
+    pub stop_price: String,
+    pub size: String,
+    pub status: String,
+}
@@ -16,9 +16,10 @@ pub enum AccountType {
     Main,
     Trade,
     Margin,
+    TradeHf,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Accounts {
     pub id: String,
@@ -41,6 +42,7 @@ pub struct SingleAccount {
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AccountInfo {
+    pub id: Option<String>,
     pub currency: String,
     pub amount: String,
     pub fee: String,
@@ -187,3 +189,18 @@ pub struct WithdrawalQuotas {
 pub struct WithdrawalId {
     withdrawal_id: String,
 }
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BaseFee {
+    pub taker_fee_rate: String,
+    pub maker_fee_rate: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TradeFee {
+    pub symbol: String,
+    pub taker_fee_rate: String,
+    pub maker_fee_rate: String,
+}
@@ -38,17 +38,29 @@ pub enum WSTopic {
     PositionChange,
     MarginTradeOrder(String),
     TradeOrders,
+    TradeOrdersV2,
+    FuturesTicker(Vec<String>),
+    FuturesOrderBook(Vec<String>),
+    FuturesExecution(Vec<String>),
+    FuturesInstrument(Vec<String>),
+    FuturesPosition(String),
+    FuturesTradeOrders,
+    FuturesBalances,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum WSType {
     Public,
     Private,
+    FuturesPublic,
+    FuturesPrivate,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[allow(clippy::large_enum_variant)]
 pub enum KucoinWebsocketMsg {
     WelcomeMsg(DefaultMsg),
+    AckMsg(DefaultMsg),
     SubscribeMsg(Subscribe),
     PingMsg(DefaultMsg),
     PongMsg(DefaultMsg),
@@ -86,8 +98,234 @@ pub enum KucoinWebsocketMsg {
     TradeFilledMsg(WSResp<TradeFilled>),
     TradeCanceledMsg(WSResp<TradeCanceled>),
     TradeUpdateMsg(WSResp<TradeUpdate>),
-    Error(String),
-}
+    HfTradeReceivedMsg(WSResp<HfTradeReceived>),
+    HfTradeOpenMsg(WSResp<TradeOpen>),
+    HfTradeMatchMsg(WSResp<TradeMatch>),
+    HfTradeFilledMsg(WSResp<TradeFilled>),
+    HfTradeCanceledMsg(WSResp<TradeCanceled>),
+    HfTradeUpdateMsg(WSResp<TradeUpdate>),
+    FuturesTickerMsg(WSResp<FuturesTicker>),
+    FuturesOrderBookMsg(WSResp<FuturesLevel2>),
+    FuturesExecutionMsg(WSResp<FuturesExecution>),
+    FuturesMarkIndexPriceMsg(WSResp<FuturesMarkIndexPrice>),
+    FuturesFundingRateMsg(WSResp<FuturesFundingRate>),
+    FuturesPositionChangeMsg(WSResp<FuturesPositionChange>),
+    FuturesOrderMsg(WSResp<FuturesOrder>),
+    FuturesOrderMarginMsg(WSResp<FuturesOrderMargin>),
+    FuturesAvailableBalanceMsg(WSResp<FuturesAvailableBalance>),
+    Error(WSError),
+    /// Frame of an unknown type or topic, or whose data did not parse, kept as JSON in
+    /// ParseMode::Lenient.
+    Unknown(serde_json::Value),
+}
+
+/// How frames that do not parse into a known KucoinWebsocketMsg are handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ParseMode {
+    /// Yield an APIError::Parse carrying the raw frame.
+    #[default]
+    Strict,
+    /// Yield the frame as KucoinWebsocketMsg::Unknown when it is valid JSON.
+    Lenient,
+}
+
+macro_rules! msg_envelope {
+    ($msg:expr, $($variant:ident),+) => {
+        match $msg {
+            $(KucoinWebsocketMsg::$variant(r) => Some((r.topic.as_str(), r.subject.as_str())),)+
+            _ => None,
+        }
+    };
+}
+
+impl KucoinWebsocketMsg {
+    /// Topic the message was published on, None for control messages such as Welcome or Pong.
+    pub fn topic(&self) -> Option<&str> {
+        self.envelope().map(|(topic, _)| topic)
+    }
+
+    /// Subject of the message, e.g. trade.l3match, or the symbol for /market/ticker:all.
+    pub fn subject(&self) -> Option<&str> {
+        self.envelope().map(|(_, subject)| subject)
+    }
+
+    /// Exchange time of the message in milliseconds since the epoch, when its data carries one.
+    pub fn timestamp(&self) -> Option<u64> {
+        use KucoinWebsocketMsg::*;
+        let time = match self {
+            TickerMsg(r) | AllTickerMsg(r) => r.data.time?,
+            SnapshotMsg(r) => r.data.data.datetime,
+            OrderBookMsg(r) => r.data.time?,
+            OrderBookDepthMsg(r) => r.data.timestamp as i64,
+            MatchMsg(r) => r.data.time.parse().ok()?,
+            Level3ReceivedMsg(r) => r.data.time.parse().ok()?,
+            Level3OpenMsg(r) => r.data.time.parse().ok()?,
+            Level3MatchMsg(r) => r.data.time.parse().ok()?,
+            Level3DoneMsg(r) => r.data.time.parse().ok()?,
+            Level3ChangeMsg(r) => r.data.time.parse().ok()?,
+            FullMatchReceivedMsg(r) => r.data.ts,
+            FullMatchOpenMsg(r) => r.data.ts,
+            FullMatchDoneMsg(r) => r.data.ts,
+            FullMatchMatchMsg(r) => r.data.ts,
+            FullMatchChangeMsg(r) => r.data.ts,
+            IndexPriceMsg(r) => r.data.timestamp,
+            MarketPriceMsg(r) => r.data.timestamp,
+            OrderBookChangeMsg(r) => r.data.ts,
+            StopOrderMsg(r) => r.data.time.parse().ok()?,
+            BalancesMsg(r) => r.data.time.parse().ok()?,
+            DebtRatioMsg(r) => r.data.timestamp,
+            PositionChangeMsg(r) => r.data.timestamp,
+            MarginTradeOpenMsg(r) => r.data.ts,
+            MarginTradeUpdateMsg(r) => r.data.ts,
+            MarginTradeDoneMsg(r) => r.data.ts,
+            TradeOpenMsg(r) | HfTradeOpenMsg(r) => r.data.ts,
+            TradeMatchMsg(r) | HfTradeMatchMsg(r) => r.data.ts,
+            TradeFilledMsg(r) | HfTradeFilledMsg(r) => r.data.ts,
+            TradeCanceledMsg(r) | HfTradeCanceledMsg(r) => r.data.ts,
+            TradeUpdateMsg(r) | HfTradeUpdateMsg(r) => r.data.ts,
+            HfTradeReceivedMsg(r) => r.data.ts,
+            FuturesTickerMsg(r) => r.data.ts,
+            FuturesOrderBookMsg(r) => r.data.timestamp,
+            FuturesExecutionMsg(r) => r.data.ts,
+            FuturesMarkIndexPriceMsg(r) => r.data.timestamp,
+            FuturesFundingRateMsg(r) => r.data.timestamp,
+            FuturesPositionChangeMsg(r) => r.data.current_timestamp?,
+            FuturesOrderMsg(r) => r.data.ts,
+            FuturesOrderMarginMsg(r) => r.data.timestamp,
+            FuturesAvailableBalanceMsg(r) => r.data.timestamp,
+            _ => return None,
+        };
+        epoch_millis(time)
+    }
+
+    fn envelope(&self) -> Option<(&str, &str)> {
+        if let KucoinWebsocketMsg::Unknown(frame) = self {
+            let field = |key| frame.get(key).and_then(|v: &serde_json::Value| v.as_str());
+            return Some((field("topic")?, field("subject").unwrap_or_default()));
+        }
+        msg_envelope!(
+            self,
+            TickerMsg,
+            AllTickerMsg,
+            SnapshotMsg,
+            OrderBookMsg,
+            MatchMsg,
+            Level3ReceivedMsg,
+            Level3OpenMsg,
+            Level3MatchMsg,
+            Level3DoneMsg,
+            Level3ChangeMsg,
+            OrderBookDepthMsg,
+            FullMatchReceivedMsg,
+            FullMatchOpenMsg,
+            FullMatchDoneMsg,
+            FullMatchMatchMsg,
+            FullMatchChangeMsg,
+            IndexPriceMsg,
+            MarketPriceMsg,
+            OrderBookChangeMsg,
+            StopOrderMsg,
+            BalancesMsg,
+            DebtRatioMsg,
+            PositionChangeMsg,
+            MarginTradeOpenMsg,
+            MarginTradeUpdateMsg,
+            MarginTradeDoneMsg,
+            TradeOpenMsg,
+            TradeMatchMsg,
+            TradeFilledMsg,
+            TradeCanceledMsg,
+            TradeUpdateMsg,
+            HfTradeReceivedMsg,
+            HfTradeOpenMsg,
+            HfTradeMatchMsg,
+            HfTradeFilledMsg,
+            HfTradeCanceledMsg,
+            HfTradeUpdateMsg,
+            FuturesTickerMsg,
+            FuturesOrderBookMsg,
+            FuturesExecutionMsg,
+            FuturesMarkIndexPriceMsg,
+            FuturesFundingRateMsg,
+            FuturesPositionChangeMsg,
+            FuturesOrderMsg,
+            FuturesOrderMarginMsg,
+            FuturesAvailableBalanceMsg
+        )
+    }
+}
+
+// Kucoin timestamps are in seconds, milliseconds, microseconds or nanoseconds depending on
+// the feed, told apart by their magnitude.
+fn epoch_millis(time: i64) -> Option<u64> {
+    match time {
+        t if t <= 0 => None,
+        t if t >= 100_000_000_000_000_000 => Some(t as u64 / 1_000_000),
+        t if t >= 100_000_000_000_000 => Some(t as u64 / 1_000),
+        t if t >= 100_000_000_000 => Some(t as u64),
+        t => Some(t as u64 * 1_000),
+    }
+}
+
+/// Message data that can be taken out of a KucoinWebsocketMsg, used to type the streams
+/// returned by KucoinWebsocket::subscribe_typed.
+pub trait WSData: Sized {
+    fn from_msg(msg: KucoinWebsocketMsg) -> Option<WSResp<Self>>;
+}
+
+macro_rules! ws_data {
+    ($data:ty => $($variant:ident),+) => {
+        impl WSData for $data {
+            fn from_msg(msg: KucoinWebsocketMsg) -> Option<WSResp<Self>> {
+                match msg {
+                    $(KucoinWebsocketMsg::$variant(r) => Some(r),)+
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+ws_data!(SymbolTicker => TickerMsg, AllTickerMsg);
+ws_data!(Snapshot => SnapshotMsg);
+ws_data!(Level2 => OrderBookMsg);
+ws_data!(Match => MatchMsg);
+ws_data!(Level3Received => Level3ReceivedMsg);
+ws_data!(Level3Open => Level3OpenMsg);
+ws_data!(Level3Match => Level3MatchMsg);
+ws_data!(Level3Done => Level3DoneMsg);
+ws_data!(Level3Change => Level3ChangeMsg);
+ws_data!(Level2Depth => OrderBookDepthMsg);
+ws_data!(FullMatchReceived => FullMatchReceivedMsg);
+ws_data!(FullMatchOpen => FullMatchOpenMsg);
+ws_data!(FullMatchDone => FullMatchDoneMsg);
+ws_data!(FullMatchMatch => FullMatchMatchMsg);
+ws_data!(FullMatchChange => FullMatchChangeMsg);
+ws_data!(IndexPrice => IndexPriceMsg);
+ws_data!(MarketPrice => MarketPriceMsg);
+ws_data!(BookChange => OrderBookChangeMsg);
+ws_data!(StopOrder => StopOrderMsg);
+ws_data!(Balances => BalancesMsg);
+ws_data!(DebtRatio => DebtRatioMsg);
+ws_data!(PositionChange => PositionChangeMsg);
+ws_data!(MarginTradeOpen => MarginTradeOpenMsg);
+ws_data!(MarginTradeUpdate => MarginTradeUpdateMsg);
+ws_data!(MarginTradeDone => MarginTradeDoneMsg);
+ws_data!(TradeOpen => TradeOpenMsg, HfTradeOpenMsg);
+ws_data!(TradeMatch => TradeMatchMsg, HfTradeMatchMsg);
+ws_data!(TradeFilled => TradeFilledMsg, HfTradeFilledMsg);
+ws_data!(TradeCanceled => TradeCanceledMsg, HfTradeCanceledMsg);
+ws_data!(TradeUpdate => TradeUpdateMsg, HfTradeUpdateMsg);
+ws_data!(HfTradeReceived => HfTradeReceivedMsg);
+ws_data!(FuturesTicker => FuturesTickerMsg);
+ws_data!(FuturesLevel2 => FuturesOrderBookMsg);
+ws_data!(FuturesExecution => FuturesExecutionMsg);
+ws_data!(FuturesMarkIndexPrice => FuturesMarkIndexPriceMsg);
+ws_data!(FuturesFundingRate => FuturesFundingRateMsg);
+ws_data!(FuturesPositionChange => FuturesPositionChangeMsg);
+ws_data!(FuturesOrder => FuturesOrderMsg);
+ws_data!(FuturesOrderMargin => FuturesOrderMarginMsg);
+ws_data!(FuturesAvailableBalance => FuturesAvailableBalanceMsg);
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -105,6 +343,18 @@ pub struct DefaultMsg {
     pub r#type: String,
 }
 
+/// Error message Kucoin replies with when a connection or request is rejected,
+/// id is the id of the rejected request.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WSError {
+    #[serde(default)]
+    pub id: String,
+    pub code: i64,
+    #[serde(default)]
+    pub data: String,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Subscribe {
@@ -119,6 +369,8 @@ pub struct Subscribe {
 #[serde(rename_all = "camelCase")]
 pub struct SymbolTicker {
     pub sequence: String,
+    #[serde(default)]
+    pub time: Option<i64>,
     pub best_ask: String,
     pub size: String,
     pub best_bid_size: String,
@@ -165,6 +417,8 @@ pub struct Level2 {
     pub sequence_end: i64,
     pub symbol: String,
     pub changes: Level2Changes,
+    #[serde(default)]
+    pub time: Option<i64>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -538,3 +792,143 @@ pub struct TradeUpdate {
     pub status: String,
     pub ts: i64,
 }
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HfTradeReceived {
+    pub symbol: String,
+    pub order_type: String,
+    pub side: String,
+    pub r#type: String,
+    pub order_id: String,
+    pub order_time: i64,
+    pub size: Option<String>,
+    pub funds: Option<String>,
+    #[serde(default)]
+    pub price: String,
+    #[serde(default)]
+    pub client_oid: String,
+    pub status: String,
+    pub ts: i64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FuturesTicker {
+    pub symbol: String,
+    pub sequence: i64,
+    pub best_bid_size: i64,
+    pub best_bid_price: String,
+    pub best_ask_price: String,
+    pub best_ask_size: i64,
+    pub ts: i64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FuturesLevel2 {
+    pub sequence: i64,
+    pub change: String,
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FuturesExecution {
+    pub symbol: String,
+    pub sequence: i64,
+    pub side: String,
+    pub size: i64,
+    pub price: String,
+    pub taker_order_id: String,
+    pub maker_order_id: String,
+    pub trade_id: String,
+    pub ts: i64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FuturesMarkIndexPrice {
+    pub granularity: i32,
+    pub index_price: f64,
+    pub mark_price: f64,
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FuturesFundingRate {
+    pub granularity: i32,
+    pub funding_rate: f64,
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FuturesPositionChange {
+    pub symbol: Option<String>,
+    pub change_reason: Option<String>,
+    pub current_qty: Option<i64>,
+    pub avg_entry_price: Option<f64>,
+    pub mark_price: Option<f64>,
+    pub mark_value: Option<f64>,
+    pub liquidation_price: Option<f64>,
+    pub bankrupt_price: Option<f64>,
+    pub real_leverage: Option<f64>,
+    pub cross_mode: Option<bool>,
+    pub is_open: Option<bool>,
+    pub pos_margin: Option<f64>,
+    pub pos_cost: Option<f64>,
+    pub maint_margin: Option<f64>,
+    pub realised_pnl: Option<f64>,
+    pub realised_gross_pnl: Option<f64>,
+    pub unrealised_pnl: Option<f64>,
+    pub unrealised_pnl_pcnt: Option<f64>,
+    pub unrealised_roe_pcnt: Option<f64>,
+    pub delev_percentage: Option<f64>,
+    pub opening_timestamp: Option<i64>,
+    pub current_timestamp: Option<i64>,
+    pub settle_currency: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FuturesOrder {
+    pub order_id: String,
+    pub symbol: String,
+    pub r#type: String,
+    pub status: String,
+    pub order_type: Option<String>,
+    pub side: String,
+    pub price: Option<String>,
+    pub size: String,
+    pub remain_size: String,
+    pub filled_size: String,
+    pub canceled_size: Option<String>,
+    pub match_size: Option<String>,
+    pub match_price: Option<String>,
+    pub trade_id: Option<String>,
+    pub liquidity: Option<String>,
+    pub old_size: Option<String>,
+    #[serde(default)]
+    pub client_oid: String,
+    pub order_time: i64,
+    pub ts: i64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FuturesOrderMargin {
+    pub order_margin: f64,
+    pub currency: String,
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FuturesAvailableBalance {
+    pub available_balance: f64,
+    pub hold_balance: f64,
+    pub currency: String,
+    pub timestamp: i64,
+}
@@ -0,0 +1,1282 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::Stream;
+
+use super::error::APIError;
+use super::exchange::{Account, OrderEntry, OrderKind, OrderRequest, Side, TimeInForce};
+use super::fee::Liquidity;
+use super::model::trade::{CancelByClientOidResp, CancelResp, FillsInfo, OrderInfo, OrderResp};
+use super::model::user::Accounts;
+use super::model::websocket::{
+    KucoinWebsocketMsg, TradeCanceled, TradeFilled, TradeMatch, TradeOpen, WSResp,
+};
+use super::model::{APIData, APIDatum, Pagination};
+use super::trade::{FillsOptionals, OrderInfoOptionals, OrderOptionals};
+use super::utils::{broadcast, feed, format_amount, get_time};
+
+const SUCCESS: &str = "200000";
+const BALANCE_INSUFFICIENT: &str = "200004";
+const INVALID_REQUEST: &str = "400100";
+
+// Amounts below this are treated as zero.
+const EPSILON: f64 = 1e-12;
+
+/// Fees and latency of a PaperExchange, see PaperConfig::new for the defaults.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaperConfig {
+    /// Fee rate of fills against orders resting on the book, e.g. 0.001 for 0.1%
+    pub maker_fee: f64,
+    /// Fee rate of fills taking liquidity from the book
+    pub taker_fee: f64,
+    /// Delay before an order, cancel or query reaches the simulated exchange
+    pub latency: Duration,
+}
+
+impl Default for PaperConfig {
+    fn default() -> Self {
+        PaperConfig::new()
+    }
+}
+
+impl PaperConfig {
+    /// Kucoin's base fee rates of 0.1% and no latency.
+    pub fn new() -> Self {
+        PaperConfig {
+            maker_fee: 0.001,
+            taker_fee: 0.001,
+            latency: Duration::from_millis(0),
+        }
+    }
+
+    pub fn maker_fee(&mut self, f: f64) -> &mut Self {
+        self.maker_fee = f;
+        self
+    }
+
+    pub fn taker_fee(&mut self, f: f64) -> &mut Self {
+        self.taker_fee = f;
+        self
+    }
+
+    pub fn latency(&mut self, l: Duration) -> &mut Self {
+        self.latency = l;
+        self
+    }
+
+    pub fn build(&self) -> Self {
+        self.clone()
+    }
+}
+
+// Price levels of a symbol, best first.
+#[derive(Debug, Default)]
+struct Book {
+    bids: Vec<(f64, f64)>,
+    asks: Vec<(f64, f64)>,
+}
+
+impl Book {
+    fn levels(&mut self, side: Side) -> &mut Vec<(f64, f64)> {
+        match side {
+            Side::Buy => &mut self.bids,
+            Side::Sell => &mut self.asks,
+        }
+    }
+
+    fn replace(&mut self, bids: &[Vec<String>], asks: &[Vec<String>]) {
+        self.bids = levels_from_rows(bids);
+        self.asks = levels_from_rows(asks);
+    }
+
+    // Sets the size of a level, removing it when the size is zero.
+    fn update(&mut self, side: Side, price: f64, size: f64) {
+        let levels = self.levels(side);
+        let pos = levels.iter().position(|(p, _)| !better(side, *p, price));
+        match pos {
+            Some(i) if levels[i].0 == price => {
+                if size > EPSILON {
+                    levels[i].1 = size;
+                } else {
+                    levels.remove(i);
+                }
+            }
+            Some(i) if size > EPSILON => levels.insert(i, (price, size)),
+            None if size > EPSILON => levels.push((price, size)),
+            _ => (),
+        }
+    }
+
+    // Makes the price the best level of the side, e.g. from a ticker.
+    fn set_best(&mut self, side: Side, price: f64, size: f64) {
+        self.levels(side).retain(|(p, _)| !better(side, *p, price));
+        self.update(side, price, size);
+    }
+}
+
+// Whether price a ranks before price b on the side of the book.
+fn better(side: Side, a: f64, b: f64) -> bool {
+    match side {
+        Side::Buy => a > b,
+        Side::Sell => a < b,
+    }
+}
+
+fn levels_from_rows(rows: &[Vec<String>]) -> Vec<(f64, f64)> {
+    rows.iter()
+        .filter_map(|row| match row.as_slice() {
+            [price, size, ..] => Some((price.parse().ok()?, size.parse().ok()?)),
+            _ => None,
+        })
+        .filter(|(_, size): &(f64, f64)| *size > EPSILON)
+        .collect()
+}
+
+// Fills a taker order would get from the levels of the opposite side, without consuming them.
+fn plan(
+    levels: &[(f64, f64)],
+    side: Side,
+    limit: Option<f64>,
+    size: Option<f64>,
+    funds: Option<f64>,
+) -> Vec<(f64, f64)> {
+    let mut fills = Vec::new();
+    let (mut size, mut funds) = (size, funds);
+    for &(price, available) in levels {
+        if limit.is_some_and(|limit| better(side, price, limit)) {
+            break;
+        }
+        let mut qty = available;
+        if let Some(s) = size {
+            qty = qty.min(s);
+        }
+        if let Some(f) = funds {
+            qty = qty.min(f / price);
+        }
+        if qty <= EPSILON {
+            break;
+        }
+        fills.push((price, qty));
+        size = size.map(|s| s - qty);
+        funds = funds.map(|f| f - qty * price);
+    }
+    fills
+}
+
+fn consume(levels: &mut Vec<(f64, f64)>, fills: &[(f64, f64)]) {
+    for (level, (_, qty)) in levels.iter_mut().zip(fills) {
+        level.1 -= qty;
+    }
+    levels.retain(|(_, size)| *size > EPSILON);
+}
+
+fn amount(s: &str) -> Option<f64> {
+    s.parse::<f64>().ok().filter(|a| *a > 0.0 && a.is_finite())
+}
+
+fn split_symbol(symbol: &str) -> Option<(String, String)> {
+    let mut parts = symbol.splitn(2, '-');
+    match (parts.next(), parts.next()) {
+        (Some(base), Some(quote)) if !base.is_empty() && !quote.is_empty() => {
+            Some((base.to_string(), quote.to_string()))
+        }
+        _ => None,
+    }
+}
+
+fn ok<T>(data: T) -> APIDatum<T> {
+    APIDatum {
+        code: SUCCESS.to_string(),
+        data: Some(data),
+        msg: None,
+    }
+}
+
+fn rejected<T>(code: &str, msg: &str) -> APIDatum<T> {
+    APIDatum {
+        code: code.to_string(),
+        data: None,
+        msg: Some(msg.to_string()),
+    }
+}
+
+fn page<T>(items: Vec<T>, current_page: Option<i32>, page_size: Option<i32>) -> Pagination<T> {
+    let current_page = current_page.unwrap_or(1).max(1);
+    let page_size = page_size.unwrap_or(50).max(1);
+    let total_num = items.len() as i32;
+    let skip = (current_page as usize - 1).saturating_mul(page_size as usize);
+    let items = items
+        .into_iter()
+        .skip(skip)
+        .take(page_size as usize)
+        .collect();
+    Pagination {
+        current_page,
+        page_size,
+        total_num,
+        total_page: if total_num == 0 {
+            0
+        } else {
+            (total_num - 1) / page_size + 1
+        },
+        items,
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Balance {
+    balance: f64,
+    holds: f64,
+}
+
+struct PaperOrder {
+    info: OrderInfo,
+    side: Side,
+    base: String,
+    quote: String,
+    price: Option<f64>,
+    size: Option<f64>,
+    funds: Option<f64>,
+    filled: f64,
+    deal_funds: f64,
+    fee: f64,
+    // Amount still held, in the quote currency for buys and the base currency for sells
+    hold: f64,
+    active: bool,
+}
+
+impl PaperOrder {
+    fn remaining(&self) -> f64 {
+        match (self.size, self.funds) {
+            (Some(size), _) => size - self.filled,
+            (None, Some(funds)) => funds - self.deal_funds,
+            (None, None) => 0.0,
+        }
+    }
+
+    fn hold_currency(&self) -> &str {
+        match self.side {
+            Side::Buy => &self.quote,
+            Side::Sell => &self.base,
+        }
+    }
+
+    fn sync(&mut self) {
+        self.info.deal_size = format_amount(self.filled);
+        self.info.deal_funds = format_amount(self.deal_funds);
+        self.info.fee = format_amount(self.fee);
+        self.info.is_active = Some(self.active);
+    }
+}
+
+struct State {
+    config: PaperConfig,
+    books: HashMap<String, Book>,
+    balances: HashMap<String, Balance>,
+    orders: Vec<PaperOrder>,
+    fills: Vec<FillsInfo>,
+    listeners: Vec<UnboundedSender<KucoinWebsocketMsg>>,
+    next_id: u64,
+    clock: i64,
+}
+
+impl State {
+    // Time of the latest market data, so replays run on the recording's clock.
+    fn now(&self) -> i64 {
+        if self.clock > 0 {
+            self.clock
+        } else {
+            get_time() as i64
+        }
+    }
+
+    fn next_id(&mut self) -> String {
+        self.next_id += 1;
+        format!("{:024x}", self.next_id)
+    }
+
+    fn available(&self, currency: &str) -> f64 {
+        self.balances
+            .get(currency)
+            .map_or(0.0, |b| b.balance - b.holds)
+    }
+
+    fn emit(&mut self, msg: KucoinWebsocketMsg) {
+        broadcast(&mut self.listeners, &msg);
+    }
+
+    fn order_event<T>(&self, data: T) -> WSResp<T> {
+        WSResp {
+            r#type: "message".to_string(),
+            topic: "/spotMarket/tradeOrders".to_string(),
+            subject: "orderChange".to_string(),
+            data,
+        }
+    }
+
+    fn find(&self, order_id: &str) -> Option<usize> {
+        self.orders.iter().position(|o| o.info.id == order_id)
+    }
+
+    fn place(&mut self, order: &OrderRequest) -> APIDatum<OrderResp> {
+        let (base, quote) = match split_symbol(&order.symbol) {
+            Some(pair) => pair,
+            None => return rejected(INVALID_REQUEST, "Invalid symbol"),
+        };
+        let (price, size, funds) = match &order.kind {
+            OrderKind::Limit { price, size } => match (amount(price), amount(size)) {
+                (Some(p), Some(s)) => (Some(p), Some(s), None),
+                _ => {
+                    return rejected(
+                        INVALID_REQUEST,
+                        "Limit orders need a positive price and size",
+                    )
+                }
+            },
+            OrderKind::Market { size, funds } => {
+                match (size.as_deref().map(amount), funds.as_deref().map(amount)) {
+                    (Some(Some(s)), None) => (None, Some(s), None),
+                    (None, Some(Some(f))) => (None, None, Some(f)),
+                    _ => {
+                        return rejected(
+                            INVALID_REQUEST,
+                            "Market orders need either a positive size or funds",
+                        )
+                    }
+                }
+            }
+        };
+        let tif = order.time_in_force.unwrap_or(TimeInForce::GTC);
+        let taker_fee = self.config.taker_fee;
+        let book = self.books.entry(order.symbol.clone()).or_default();
+        let fills = plan(
+            book.levels(order.side.opposite()),
+            order.side,
+            price,
+            size,
+            funds,
+        );
+        let planned_size: f64 = fills.iter().map(|(_, q)| q).sum();
+        let planned_funds: f64 = fills.iter().map(|(p, q)| p * q).sum();
+        if price.is_none() && fills.is_empty() {
+            return rejected(INVALID_REQUEST, "No liquidity in the paper book");
+        }
+
+        // Limit orders hold their full amount, market orders need what they will fill
+        let (hold, required) = match (order.side, price, size) {
+            (Side::Buy, Some(p), Some(s)) => (p * s * (1.0 + taker_fee), p * s * (1.0 + taker_fee)),
+            (Side::Sell, Some(_), Some(s)) => (s, s),
+            (Side::Buy, _, _) => (0.0, planned_funds * (1.0 + taker_fee)),
+            (Side::Sell, _, _) => (0.0, planned_size),
+        };
+        let currency = match order.side {
+            Side::Buy => &quote,
+            Side::Sell => &base,
+        };
+        if self.available(currency) < required - EPSILON {
+            return rejected(BALANCE_INSUFFICIENT, "Balance insufficient!");
+        }
+        self.balances.entry(currency.clone()).or_default().holds += hold;
+
+        let id = self.next_id();
+        let now = self.now();
+        let info = OrderInfo {
+            id: id.clone(),
+            symbol: order.symbol.clone(),
+            op_type: "DEAL".to_string(),
+            r#type: if price.is_some() { "limit" } else { "market" }.to_string(),
+            side: order.side.as_str().to_string(),
+            price: price.map_or("0".to_string(), format_amount),
+            size: size.map_or("0".to_string(), format_amount),
+            funds: funds.map_or("0".to_string(), format_amount),
+            deal_funds: "0".to_string(),
+            deal_size: "0".to_string(),
+            fee: "0".to_string(),
+            fee_currency: quote.clone(),
+            stp: String::new(),
+            stop: String::new(),
+            stop_triggered: false,
+            stop_price: "0".to_string(),
+            time_in_force: tif.as_str().to_string(),
+            post_only: order.post_only,
+            hidden: false,
+            iceberg: false,
+            visible_size: "0".to_string(),
+            cancel_after: 0,
+            channel: "API".to_string(),
+            client_oid: order.client_oid.clone(),
+            remark: order.remark.clone(),
+            tags: None,
+            is_active: Some(true),
+            cancel_exist: false,
+            created_at: now,
+            trade_type: "TRADE".to_string(),
+        };
+        self.orders.push(PaperOrder {
+            info,
+            side: order.side,
+            base,
+            quote,
+            price,
+            size,
+            funds,
+            filled: 0.0,
+            deal_funds: 0.0,
+            fee: 0.0,
+            hold,
+            active: true,
+        });
+        let idx = self.orders.len() - 1;
+
+        let would_take = order.post_only && !fills.is_empty();
+        let unfillable = tif == TimeInForce::FOK && planned_size < size.unwrap_or(0.0) - EPSILON;
+        if would_take || unfillable {
+            self.finish(idx, true);
+            return ok(OrderResp { order_id: id });
+        }
+        if let Some(book) = self.books.get_mut(&order.symbol) {
+            consume(book.levels(order.side.opposite()), &fills);
+        }
+        for (p, q) in fills {
+            self.fill(idx, p, q, Liquidity::Taker);
+        }
+        if self.orders[idx].remaining() <= EPSILON {
+            self.finish(idx, false);
+        } else if price.is_none() || tif == TimeInForce::IOC {
+            self.finish(idx, true);
+        } else {
+            let event = self.open_event(idx);
+            self.emit(KucoinWebsocketMsg::TradeOpenMsg(event));
+        }
+        ok(OrderResp { order_id: id })
+    }
+
+    fn fill(&mut self, idx: usize, price: f64, qty: f64, liquidity: Liquidity) {
+        let fee_rate = match liquidity {
+            Liquidity::Maker => self.config.maker_fee,
+            Liquidity::Taker => self.config.taker_fee,
+        };
+        let taker_fee = self.config.taker_fee;
+        let funds = price * qty;
+        let fee = funds * fee_rate;
+        let order = &mut self.orders[idx];
+        order.filled += qty;
+        order.deal_funds += funds;
+        order.fee += fee;
+        let release = match (order.side, order.price) {
+            (Side::Buy, Some(p)) => p * qty * (1.0 + taker_fee),
+            (Side::Sell, Some(_)) => qty,
+            (_, None) => 0.0,
+        }
+        .min(order.hold);
+        order.hold -= release;
+        order.sync();
+        let (side, base, quote) = (order.side, order.base.clone(), order.quote.clone());
+
+        match side {
+            Side::Buy => {
+                let q = self.balances.entry(quote.clone()).or_default();
+                q.balance -= funds + fee;
+                q.holds -= release;
+                self.balances.entry(base).or_default().balance += qty;
+            }
+            Side::Sell => {
+                let b = self.balances.entry(base).or_default();
+                b.balance -= qty;
+                b.holds -= release;
+                self.balances.entry(quote.clone()).or_default().balance += funds - fee;
+            }
+        }
+
+        let trade_id = self.next_id();
+        let now = self.now();
+        let order = &self.orders[idx];
+        self.fills.push(FillsInfo {
+            symbol: order.info.symbol.clone(),
+            trade_id: trade_id.clone(),
+            order_id: order.info.id.clone(),
+            counter_order_id: String::new(),
+            side: order.info.side.clone(),
+            liquidity: liquidity.as_str().to_string(),
+            force_taker: false,
+            price: format_amount(price),
+            size: format_amount(qty),
+            funds: format_amount(funds),
+            fee: format_amount(fee),
+            fee_rate: format_amount(fee_rate),
+            fee_currency: quote,
+            stop: String::new(),
+            r#type: order.info.r#type.clone(),
+            created_at: now,
+            trade_type: "TRADE".to_string(),
+        });
+        let event = TradeMatch {
+            symbol: order.info.symbol.clone(),
+            order_type: order.info.r#type.clone(),
+            side: order.info.side.clone(),
+            liquidity: liquidity.as_str().to_string(),
+            r#type: "match".to_string(),
+            order_id: order.info.id.clone(),
+            order_time: order.info.created_at * 1_000_000,
+            size: order.info.size.clone(),
+            filled_size: order.info.deal_size.clone(),
+            price: order.info.price.clone(),
+            match_price: format_amount(price),
+            match_size: format_amount(qty),
+            trade_id,
+            client_oid: order.info.client_oid.clone(),
+            remain_size: format_amount(order.remaining().max(0.0)),
+            status: "match".to_string(),
+            ts: now * 1_000_000,
+        };
+        let event = self.order_event(event);
+        self.emit(KucoinWebsocketMsg::TradeMatchMsg(event));
+    }
+
+    // Completes the order as filled or canceled, releasing what is still held.
+    fn finish(&mut self, idx: usize, canceled: bool) {
+        let order = &mut self.orders[idx];
+        let hold = order.hold;
+        order.hold = 0.0;
+        order.active = false;
+        order.info.cancel_exist = canceled;
+        order.sync();
+        let currency = order.hold_currency().to_string();
+        if let Some(b) = self.balances.get_mut(&currency) {
+            b.holds = (b.holds - hold).max(0.0);
+        }
+        let TradeOpen {
+            symbol,
+            order_type,
+            side,
+            order_id,
+            order_time,
+            size,
+            filled_size,
+            price,
+            client_oid,
+            remain_size,
+            ts,
+            ..
+        } = self.open_event(idx).data;
+        let msg = if canceled {
+            KucoinWebsocketMsg::TradeCanceledMsg(self.order_event(TradeCanceled {
+                symbol,
+                order_type,
+                side,
+                r#type: "canceled".to_string(),
+                order_id,
+                order_time,
+                size,
+                filled_size,
+                price,
+                client_oid,
+                remain_size,
+                status: "done".to_string(),
+                ts,
+            }))
+        } else {
+            KucoinWebsocketMsg::TradeFilledMsg(self.order_event(TradeFilled {
+                symbol,
+                order_type,
+                side,
+                r#type: "filled".to_string(),
+                order_id,
+                order_time,
+                size,
+                filled_size,
+                price,
+                client_oid,
+                remain_size: "0".to_string(),
+                status: "done".to_string(),
+                ts,
+            }))
+        };
+        self.emit(msg);
+    }
+
+    fn open_event(&self, idx: usize) -> WSResp<TradeOpen> {
+        let order = &self.orders[idx];
+        self.order_event(TradeOpen {
+            symbol: order.info.symbol.clone(),
+            order_type: order.info.r#type.clone(),
+            side: order.info.side.clone(),
+            r#type: "open".to_string(),
+            order_id: order.info.id.clone(),
+            order_time: order.info.created_at * 1_000_000,
+            size: order.info.size.clone(),
+            filled_size: order.info.deal_size.clone(),
+            price: order.info.price.clone(),
+            client_oid: order.info.client_oid.clone(),
+            remain_size: format_amount(order.remaining().max(0.0)),
+            status: "open".to_string(),
+            ts: self.now() * 1_000_000,
+        })
+    }
+
+    fn cancel(&mut self, idx: usize) -> bool {
+        if !self.orders[idx].active {
+            return false;
+        }
+        self.finish(idx, true);
+        true
+    }
+
+    // Resting orders of the symbol, oldest first.
+    fn resting(&self, symbol: &str, side: Side) -> Vec<usize> {
+        (0..self.orders.len())
+            .filter(|i| {
+                let o = &self.orders[*i];
+                o.active && o.side == side && o.info.symbol == symbol
+            })
+            .collect()
+    }
+
+    // Fills resting orders the book has moved through, at their own price.
+    fn match_book(&mut self, symbol: &str) {
+        for side in [Side::Buy, Side::Sell].iter() {
+            for idx in self.resting(symbol, *side) {
+                let (price, remaining) = (self.orders[idx].price, self.orders[idx].remaining());
+                let book = match self.books.get_mut(symbol) {
+                    Some(book) => book,
+                    None => return,
+                };
+                let levels = book.levels(side.opposite());
+                let fills = plan(levels, *side, price, Some(remaining), None);
+                if fills.is_empty() {
+                    continue;
+                }
+                consume(levels, &fills);
+                let qty = fills.iter().map(|(_, q)| q).sum();
+                self.fill(idx, price.unwrap_or_default(), qty, Liquidity::Maker);
+                if self.orders[idx].remaining() <= EPSILON {
+                    self.finish(idx, false);
+                }
+            }
+        }
+    }
+
+    // Fills resting orders at or through the price of a public trade, up to its size. Orders
+    // are assumed to be first in the queue of their price level.
+    fn match_trade(&mut self, symbol: &str, taker: Side, price: f64, size: f64) {
+        let mut left = size;
+        let maker = taker.opposite();
+        for idx in self.resting(symbol, maker) {
+            let order = &self.orders[idx];
+            let order_price = order.price.unwrap_or_default();
+            if better(maker, price, order_price) || left <= EPSILON {
+                continue;
+            }
+            let qty = order.remaining().min(left);
+            left -= qty;
+            self.fill(idx, order_price, qty, Liquidity::Maker);
+            if self.orders[idx].remaining() <= EPSILON {
+                self.finish(idx, false);
+            }
+        }
+    }
+
+    fn on_message(&mut self, msg: &KucoinWebsocketMsg) {
+        if let Some(ts) = msg.timestamp() {
+            self.clock = self.clock.max(ts as i64);
+        }
+        let topic_symbol = msg
+            .topic()
+            .and_then(|t| t.rsplit(':').next())
+            .unwrap_or_default()
+            .to_string();
+        let symbol = match msg {
+            KucoinWebsocketMsg::TickerMsg(r) | KucoinWebsocketMsg::AllTickerMsg(r) => {
+                // /market/ticker:all carries the symbol as its subject
+                let symbol = match topic_symbol.as_str() {
+                    "all" => r.subject.clone(),
+                    _ => topic_symbol,
+                };
+                let book = self.books.entry(symbol.clone()).or_default();
+                let bid = (r.data.best_bid.parse(), r.data.best_bid_size.parse());
+                if let (Ok(price), Ok(size)) = bid {
+                    book.set_best(Side::Buy, price, size);
+                }
+                let ask = (r.data.best_ask.parse(), r.data.best_ask_size.parse());
+                if let (Ok(price), Ok(size)) = ask {
+                    book.set_best(Side::Sell, price, size);
+                }
+                symbol
+            }
+            KucoinWebsocketMsg::OrderBookDepthMsg(r) => {
+                let book = self.books.entry(topic_symbol.clone()).or_default();
+                book.replace(&r.data.bids, &r.data.asks);
+                topic_symbol
+            }
+            KucoinWebsocketMsg::OrderBookMsg(r) => {
+                let book = self.books.entry(r.data.symbol.clone()).or_default();
+                let changes = [
+                    (Side::Buy, &r.data.changes.bids),
+                    (Side::Sell, &r.data.changes.asks),
+                ];
+                for (side, rows) in changes.iter() {
+                    for row in rows.iter() {
+                        if let [price, size, ..] = row.as_slice() {
+                            if let (Ok(price), Ok(size)) = (price.parse(), size.parse()) {
+                                book.update(*side, price, size);
+                            }
+                        }
+                    }
+                }
+                r.data.symbol.clone()
+            }
+            KucoinWebsocketMsg::MatchMsg(r) => {
+                let trade = (
+                    Side::parse(&r.data.side),
+                    r.data.price.parse(),
+                    r.data.size.parse(),
+                );
+                if let (Some(taker), Ok(price), Ok(size)) = trade {
+                    self.match_trade(&r.data.symbol, taker, price, size);
+                }
+                return;
+            }
+            _ => return,
+        };
+        self.match_book(&symbol);
+    }
+}
+
+/// Simulated spot exchange for running strategies without risking funds. Orders are matched
+/// against a book fed from KucoinWebsocket (or Replay) messages: ticker, level2 and depth
+/// messages update the book, and public matches fill resting orders they trade through.
+/// Balances start empty, fund them with deposit. Order and fill calls mirror those of trade.rs
+/// and rejections are returned like Kucoin's, with a code and msg but no data. Cloning the
+/// exchange is cheap and clones share the same state.
+#[derive(Clone)]
+pub struct PaperExchange {
+    state: Arc<Mutex<State>>,
+    latency: Duration,
+}
+
+impl Default for PaperExchange {
+    fn default() -> Self {
+        PaperExchange::new(PaperConfig::new())
+    }
+}
+
+impl PaperExchange {
+    pub fn new(config: PaperConfig) -> Self {
+        PaperExchange {
+            latency: config.latency,
+            state: Arc::new(Mutex::new(State {
+                config,
+                books: HashMap::new(),
+                balances: HashMap::new(),
+                orders: Vec::new(),
+                fills: Vec::new(),
+                listeners: Vec::new(),
+                next_id: 0,
+                clock: 0,
+            })),
+        }
+    }
+
+    /// Credits the trade account of the currency.
+    pub fn deposit(&self, currency: &str, amount: f64) {
+        let mut state = self.state.lock().unwrap();
+        state
+            .balances
+            .entry(currency.to_string())
+            .or_default()
+            .balance += amount;
+    }
+
+    /// Updates the book with a market data message and fills resting orders it reaches.
+    pub fn on_message(&self, msg: &KucoinWebsocketMsg) {
+        self.state.lock().unwrap().on_message(msg);
+    }
+
+    /// Matches resting orders against the market data of a stream such as a KucoinWebsocket
+    /// or a Replay, see on_message. Returns when the stream ends.
+    pub async fn feed<S>(&self, stream: S)
+    where
+        S: Stream<Item = Result<KucoinWebsocketMsg, APIError>>,
+    {
+        feed(stream, |msg| self.on_message(msg)).await
+    }
+
+    /// Order changes as /spotMarket/tradeOrders would publish them: TradeOpenMsg,
+    /// TradeMatchMsg, TradeFilledMsg and TradeCanceledMsg.
+    pub fn events(&self) -> UnboundedReceiver<KucoinWebsocketMsg> {
+        let (tx, rx) = mpsc::unbounded();
+        self.state.lock().unwrap().listeners.push(tx);
+        rx
+    }
+
+    async fn delay(&self) {
+        if self.latency > Duration::from_millis(0) {
+            tokio::time::sleep(self.latency).await;
+        }
+    }
+
+    async fn submit(&self, order: &OrderRequest) -> APIDatum<OrderResp> {
+        self.delay().await;
+        self.state.lock().unwrap().place(order)
+    }
+
+    pub async fn post_limit_order(
+        &self,
+        client_oid: &str,
+        symbol: &str,
+        side: &str,
+        price: &str,
+        size: &str,
+        optionals: Option<OrderOptionals<'_>>,
+    ) -> Result<APIDatum<OrderResp>, APIError> {
+        let side = match Side::parse(side) {
+            Some(s) => s,
+            None => return Ok(rejected(INVALID_REQUEST, "Invalid side")),
+        };
+        let order = OrderRequest::limit(client_oid, symbol, side, price, size);
+        match with_optionals(order, optionals) {
+            Ok(order) => Ok(self.submit(&order).await),
+            Err(resp) => Ok(resp),
+        }
+    }
+
+    pub async fn post_market_order(
+        &self,
+        client_oid: &str,
+        symbol: &str,
+        side: &str,
+        size: Option<f32>,
+        funds: Option<f32>,
+        optionals: Option<OrderOptionals<'_>>,
+    ) -> Result<APIDatum<OrderResp>, APIError> {
+        let side = match Side::parse(side) {
+            Some(s) => s,
+            None => return Ok(rejected(INVALID_REQUEST, "Invalid side")),
+        };
+        let mut order = OrderRequest::market(client_oid, symbol, side, "0");
+        order.kind = OrderKind::Market {
+            size: size.map(|s| s.to_string()),
+            funds: funds.map(|f| f.to_string()),
+        };
+        match with_optionals(order, optionals) {
+            Ok(order) => Ok(self.submit(&order).await),
+            Err(resp) => Ok(resp),
+        }
+    }
+
+    pub async fn cancel_order(&self, order_id: &str) -> Result<APIDatum<CancelResp>, APIError> {
+        self.delay().await;
+        let mut state = self.state.lock().unwrap();
+        match state.find(order_id) {
+            Some(idx) if state.cancel(idx) => Ok(ok(CancelResp {
+                cancelled_order_ids: vec![order_id.to_string()],
+            })),
+            _ => Ok(rejected(
+                INVALID_REQUEST,
+                "order_not_exist_or_not_allow_to_cancel",
+            )),
+        }
+    }
+
+    pub async fn cancel_order_by_client_oid(
+        &self,
+        client_oid: &str,
+    ) -> Result<APIDatum<CancelByClientOidResp>, APIError> {
+        self.delay().await;
+        let mut state = self.state.lock().unwrap();
+        let idx = state
+            .orders
+            .iter()
+            .position(|o| o.active && o.info.client_oid == client_oid);
+        match idx {
+            Some(idx) if state.cancel(idx) => Ok(ok(CancelByClientOidResp {
+                cancelled_order_id: state.orders[idx].info.id.clone(),
+                client_oid: client_oid.to_string(),
+            })),
+            _ => Ok(rejected(
+                INVALID_REQUEST,
+                "order_not_exist_or_not_allow_to_cancel",
+            )),
+        }
+    }
+
+    /// Cancels the active orders of the symbol, or all of them. Only the TRADE trade type is
+    /// simulated, other trade types have no orders.
+    pub async fn cancel_all_orders(
+        &self,
+        symbol: Option<&str>,
+        trade_type: Option<&str>,
+    ) -> Result<APIDatum<CancelResp>, APIError> {
+        self.delay().await;
+        let mut state = self.state.lock().unwrap();
+        let mut cancelled_order_ids = Vec::new();
+        if trade_type.map_or(true, |t| t == "TRADE") {
+            for idx in 0..state.orders.len() {
+                let order = &state.orders[idx];
+                if symbol.map_or(true, |s| s == order.info.symbol) && state.cancel(idx) {
+                    cancelled_order_ids.push(state.orders[idx].info.id.clone());
+                }
+            }
+        }
+        Ok(ok(CancelResp {
+            cancelled_order_ids,
+        }))
+    }
+
+    /// Orders matching the optionals, newest first.
+    pub async fn get_orders(
+        &self,
+        optionals: Option<OrderInfoOptionals<'_>>,
+    ) -> Result<APIDatum<Pagination<OrderInfo>>, APIError> {
+        self.delay().await;
+        let opts = optionals.unwrap_or_default();
+        let state = self.state.lock().unwrap();
+        let orders = state
+            .orders
+            .iter()
+            .rev()
+            .filter(|o| match opts.status {
+                Some("active") => o.active,
+                Some("done") => !o.active,
+                _ => true,
+            })
+            .map(|o| &o.info)
+            .filter(|o| {
+                opts.symbol.map_or(true, |s| s == o.symbol)
+                    && opts.side.map_or(true, |s| s == o.side)
+                    && opts.r#type.map_or(true, |t| t == o.r#type)
+                    && opts.trade_type.map_or(true, |t| t == o.trade_type)
+                    && opts.start_at.map_or(true, |t| o.created_at >= t)
+                    && opts.end_at.map_or(true, |t| o.created_at < t)
+            })
+            .cloned()
+            .collect();
+        Ok(ok(page(orders, opts.current_page, opts.page_size)))
+    }
+
+    pub async fn get_order(&self, order_id: &str) -> Result<APIDatum<OrderInfo>, APIError> {
+        self.delay().await;
+        let state = self.state.lock().unwrap();
+        match state.find(order_id) {
+            Some(idx) => Ok(ok(state.orders[idx].info.clone())),
+            None => Ok(rejected(INVALID_REQUEST, "order_not_exist")),
+        }
+    }
+
+    /// Fills matching the optionals, newest first.
+    pub async fn get_fills(
+        &self,
+        optionals: Option<FillsOptionals<'_>>,
+    ) -> Result<APIDatum<Pagination<FillsInfo>>, APIError> {
+        self.delay().await;
+        let opts = optionals.unwrap_or_default();
+        let state = self.state.lock().unwrap();
+        let fills = state
+            .fills
+            .iter()
+            .rev()
+            .filter(|f| {
+                opts.order_id.map_or(true, |o| o == f.order_id)
+                    && opts.symbol.map_or(true, |s| s == f.symbol)
+                    && opts.side.map_or(true, |s| s == f.side)
+                    && opts.r#type.map_or(true, |t| t == f.r#type)
+                    && opts.trade_type.map_or(true, |t| t == f.trade_type)
+                    && opts.start_at.map_or(true, |t| f.created_at >= t)
+                    && opts.end_at.map_or(true, |t| f.created_at < t)
+            })
+            .cloned()
+            .collect();
+        Ok(ok(page(fills, opts.current_page, opts.page_size)))
+    }
+
+    /// Simulated trade accounts, the only account type of the paper exchange.
+    pub async fn get_accounts_list(
+        &self,
+        currency: Option<&str>,
+        acct_type: Option<&str>,
+    ) -> Result<APIData<Accounts>, APIError> {
+        self.delay().await;
+        let state = self.state.lock().unwrap();
+        let mut accounts: Vec<Accounts> = state
+            .balances
+            .iter()
+            .filter(|(c, _)| currency.map_or(true, |currency| currency == c.as_str()))
+            .filter(|_| acct_type.map_or(true, |t| t == "trade"))
+            .map(|(c, b)| Accounts {
+                id: format!("paper-{}", c),
+                currency: c.clone(),
+                r#type: "trade".to_string(),
+                balance: format_amount(b.balance),
+                available: format_amount(b.balance - b.holds),
+                holds: format_amount(b.holds),
+            })
+            .collect();
+        accounts.sort_by(|a, b| a.currency.cmp(&b.currency));
+        Ok(APIData {
+            code: SUCCESS.to_string(),
+            data: Some(accounts),
+            msg: None,
+        })
+    }
+}
+
+fn with_optionals(
+    mut order: OrderRequest,
+    optionals: Option<OrderOptionals>,
+) -> Result<OrderRequest, APIDatum<OrderResp>> {
+    if let Some(opts) = optionals {
+        if opts.stop.is_some() {
+            return Err(rejected(INVALID_REQUEST, "Stop orders are not simulated"));
+        }
+        if let Some(t) = opts.time_in_force {
+            match TimeInForce::parse(t) {
+                Some(t) => order.time_in_force = Some(t),
+                None => return Err(rejected(INVALID_REQUEST, "Invalid timeInForce")),
+            }
+        }
+        order.post_only = opts.post_only.unwrap_or(false);
+        order.remark = opts.remark.map(String::from);
+    }
+    Ok(order)
+}
+
+#[async_trait]
+impl OrderEntry for PaperExchange {
+    async fn place_order(&self, order: &OrderRequest) -> Result<OrderResp, APIError> {
+        self.submit(order).await.into_data()
+    }
+
+    async fn cancel(&self, order_id: &str) -> Result<CancelResp, APIError> {
+        self.cancel_order(order_id).await?.into_data()
+    }
+
+    async fn cancel_by_client_oid(
+        &self,
+        client_oid: &str,
+    ) -> Result<CancelByClientOidResp, APIError> {
+        self.cancel_order_by_client_oid(client_oid)
+            .await?
+            .into_data()
+    }
+
+    async fn cancel_all(&self, symbol: Option<&str>) -> Result<CancelResp, APIError> {
+        self.cancel_all_orders(symbol, None).await?.into_data()
+    }
+
+    async fn order(&self, order_id: &str) -> Result<OrderInfo, APIError> {
+        self.get_order(order_id).await?.into_data()
+    }
+
+    async fn open_orders(&self, symbol: Option<&str>) -> Result<Vec<OrderInfo>, APIError> {
+        let mut opts = OrderInfoOptionals::new();
+        opts.status("active").page_size(i32::MAX);
+        if let Some(s) = symbol {
+            opts.symbol(s);
+        }
+        Ok(self
+            .get_orders(Some(opts.build()))
+            .await?
+            .into_data()?
+            .items)
+    }
+
+    async fn fills(
+        &self,
+        symbol: Option<&str>,
+        order_id: Option<&str>,
+    ) -> Result<Vec<FillsInfo>, APIError> {
+        let mut opts = FillsOptionals::new();
+        opts.page_size(500);
+        if let Some(s) = symbol {
+            opts.symbol(s);
+        }
+        if let Some(o) = order_id {
+            opts.order_id(o);
+        }
+        Ok(self.get_fills(Some(opts.build())).await?.into_data()?.items)
+    }
+}
+
+#[async_trait]
+impl Account for PaperExchange {
+    async fn accounts(
+        &self,
+        currency: Option<&str>,
+        acct_type: Option<&str>,
+    ) -> Result<Vec<Accounts>, APIError> {
+        self.get_accounts_list(currency, acct_type)
+            .await?
+            .into_data()
+    }
+
+    async fn server_time(&self) -> Result<i64, APIError> {
+        Ok(self.state.lock().unwrap().now())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::kucoin::exchange::{Account, OrderEntry, OrderRequest, Side, TimeInForce};
+    use crate::kucoin::model::websocket::KucoinWebsocketMsg;
+    use crate::kucoin::paper::{PaperConfig, PaperExchange};
+    use crate::kucoin::trade::{FillsOptionals, OrderInfoOptionals, OrderOptionals};
+    use crate::kucoin::websocket::parse_message;
+    use futures::StreamExt;
+    use tokio_tungstenite::tungstenite::Message;
+
+    fn msg(frame: &str) -> KucoinWebsocketMsg {
+        parse_message(Message::Text(frame.to_string())).unwrap()
+    }
+
+    fn depth() -> KucoinWebsocketMsg {
+        msg(
+            r#"{"type":"message","topic":"/spotMarket/level2Depth5:BTC-USDT","subject":"level2","data":{"asks":[["101","1"],["102","2"]],"bids":[["99","1"],["98","2"]],"timestamp":1586948108193}}"#,
+        )
+    }
+
+    async fn balance(paper: &PaperExchange, currency: &str) -> (String, String) {
+        let account = paper.trade_balance(currency).await.unwrap().unwrap();
+        (account.balance, account.holds)
+    }
+
+    #[tokio::test]
+    async fn orders_match_against_market_data() {
+        let paper =
+            PaperExchange::new(PaperConfig::new().maker_fee(0.001).taker_fee(0.002).build());
+        let mut events = paper.events();
+        paper.deposit("USDT", 1000.0);
+        paper.on_message(&depth());
+
+        let resp = paper
+            .post_limit_order("1", "BTC-USDT", "buy", "100", "2", None)
+            .await
+            .unwrap();
+        let order_id = resp.data.unwrap().order_id;
+        assert!(matches!(
+            events.next().await,
+            Some(KucoinWebsocketMsg::TradeOpenMsg(_))
+        ));
+        assert_eq!(
+            balance(&paper, "USDT").await,
+            ("1000".to_string(), "200.4".to_string())
+        );
+
+        // A public sell through the order's price fills it as maker
+        paper.on_message(&msg(
+            r#"{"type":"message","topic":"/market/match:BTC-USDT","subject":"trade.l3match","data":{"sequence":"1","type":"match","symbol":"BTC-USDT","side":"sell","price":"99.5","size":"0.5","tradeId":"1","takerOrderId":"2","makerOrderId":"3","time":"1586948109000000000"}}"#,
+        ));
+        match events.next().await {
+            Some(KucoinWebsocketMsg::TradeMatchMsg(m)) => {
+                assert_eq!(m.data.match_price, "100");
+                assert_eq!(m.data.liquidity, "maker");
+                assert_eq!(m.data.remain_size, "1.5");
+            }
+            m => panic!("Unexpected event {:?}", m),
+        }
+        assert_eq!(
+            balance(&paper, "USDT").await,
+            ("949.95".to_string(), "150.3".to_string())
+        );
+        assert_eq!(balance(&paper, "BTC").await.0, "0.5");
+
+        paper.cancel(&order_id).await.unwrap();
+        assert!(matches!(
+            events.next().await,
+            Some(KucoinWebsocketMsg::TradeCanceledMsg(_))
+        ));
+        assert_eq!(balance(&paper, "USDT").await.1, "0");
+        assert!(paper.cancel(&order_id).await.is_err());
+
+        // Market orders walk the book as taker
+        paper
+            .post_market_order("2", "BTC-USDT", "buy", Some(1.5), None, None)
+            .await
+            .unwrap();
+        assert_eq!(balance(&paper, "USDT").await.0, "797.646");
+        assert_eq!(balance(&paper, "BTC").await.0, "2");
+        let fills = paper.fills(Some("BTC-USDT"), None).await.unwrap();
+        assert_eq!(fills.len(), 3);
+        assert_eq!(
+            (fills[0].price.as_str(), fills[0].size.as_str()),
+            ("102", "0.5")
+        );
+        let mut opts = OrderInfoOptionals::new();
+        opts.status("done");
+        let done = paper.get_orders(Some(opts.build())).await.unwrap();
+        assert_eq!(done.data.unwrap().total_num, 2);
+
+        let order = OrderRequest::market("3", "BTC-USDT", Side::Sell, "1");
+        paper.place_order(&order).await.unwrap();
+        assert_eq!(balance(&paper, "USDT").await.0, "896.448");
+        let mut opts = FillsOptionals::new();
+        opts.side("buy");
+        let fills = paper.get_fills(Some(opts.build())).await.unwrap();
+        assert_eq!(fills.data.unwrap().total_num, 3);
+    }
+
+    #[tokio::test]
+    async fn queries_wait_out_the_latency() {
+        tokio::time::pause();
+        let latency = std::time::Duration::from_millis(100);
+        let paper = PaperExchange::new(PaperConfig::new().latency(latency).build());
+        paper.deposit("USDT", 100.0);
+
+        let start = tokio::time::Instant::now();
+        assert_eq!(paper.get_order("1").await.unwrap().code, "400100");
+        paper.get_orders(None).await.unwrap();
+        paper.get_fills(None).await.unwrap();
+        paper.get_accounts_list(None, None).await.unwrap();
+        assert!(start.elapsed() >= latency * 4);
+    }
+
+    #[tokio::test]
+    async fn orders_rejected_or_cancelled_like_kucoin() {
+        let paper = PaperExchange::default();
+        paper.deposit("BTC", 3.0);
+        paper.on_message(&depth());
+
+        let resp = paper
+            .post_limit_order("1", "BTC-USDT", "buy", "100", "1", None)
+            .await
+            .unwrap();
+        assert_eq!(resp.code, "200004");
+        assert!(resp.data.is_none());
+
+        let mut stop = OrderOptionals::new();
+        stop.stop("loss").stop_price("90");
+        let resp = paper
+            .post_limit_order("2", "BTC-USDT", "sell", "90", "1", Some(stop.build()))
+            .await
+            .unwrap();
+        assert_eq!(resp.code, "400100");
+
+        // Post only orders that would take and FOK orders that cannot fill are cancelled
+        let mut post_only = OrderRequest::limit("3", "BTC-USDT", Side::Sell, "99", "0.5");
+        post_only.post_only = true;
+        let mut fok = OrderRequest::limit("4", "BTC-USDT", Side::Sell, "99.5", "1");
+        fok.time_in_force = Some(TimeInForce::FOK);
+        for order in [post_only, fok].iter() {
+            let id = paper.place_order(order).await.unwrap().order_id;
+            let info = paper.order(&id).await.unwrap();
+            assert!(info.cancel_exist);
+            assert_eq!(info.deal_size, "0");
+        }
+
+        // IOC orders cancel what they could not fill
+        let mut ioc = OrderRequest::limit("5", "BTC-USDT", Side::Sell, "99", "3");
+        ioc.time_in_force = Some(TimeInForce::IOC);
+        let id = paper.place_order(&ioc).await.unwrap().order_id;
+        let info = paper.order(&id).await.unwrap();
+        assert_eq!(info.deal_size, "1");
+        assert_eq!(info.deal_funds, "99");
+        assert!(paper.open_orders(None).await.unwrap().is_empty());
+        let account = paper.trade_balance("BTC").await.unwrap().unwrap();
+        assert_eq!(
+            (account.balance.as_str(), account.holds.as_str()),
+            ("2", "0")
+        );
+    }
+}
@@ -0,0 +1,502 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use super::exchange::Side;
+use super::model::trade::FillsInfo;
+use super::model::websocket::{KucoinWebsocketMsg, TradeMatch};
+
+// Lots smaller than this are considered closed.
+const EPSILON: f64 = 1e-12;
+
+/// Which open lots a closing fill is matched against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CostMethod {
+    /// Oldest lots first
+    Fifo,
+    /// Newest lots first
+    Lifo,
+    /// A single lot at the size-weighted average price
+    Average,
+}
+
+/// Fill as the engine sees it, from get_fills or a TradeMatch event.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fill {
+    pub symbol: String,
+    pub trade_id: String,
+    pub side: Side,
+    pub price: f64,
+    pub size: f64,
+    pub fee: f64,
+    pub fee_currency: String,
+    pub time: i64,
+}
+
+impl Fill {
+    pub fn from_fills_info(fill: &FillsInfo) -> Option<Self> {
+        Some(Fill {
+            symbol: fill.symbol.clone(),
+            trade_id: fill.trade_id.clone(),
+            side: Side::parse(&fill.side)?,
+            price: fill.price.parse().ok()?,
+            size: fill.size.parse().ok()?,
+            fee: fill.fee.parse().ok()?,
+            fee_currency: fill.fee_currency.clone(),
+            time: fill.created_at,
+        })
+    }
+
+    /// Match events carry no fee, it is estimated at fee_rate of the funds, in the quote
+    /// currency. None for events other than matches.
+    pub fn from_trade_match(event: &TradeMatch, fee_rate: f64) -> Option<Self> {
+        if event.r#type != "match" {
+            return None;
+        }
+        let price: f64 = event.match_price.parse().ok()?;
+        let size: f64 = event.match_size.parse().ok()?;
+        Some(Fill {
+            symbol: event.symbol.clone(),
+            trade_id: event.trade_id.clone(),
+            side: Side::parse(&event.side)?,
+            price,
+            size,
+            fee: price * size * fee_rate,
+            fee_currency: quote_of(&event.symbol).to_string(),
+            time: event.ts / 1_000_000,
+        })
+    }
+}
+
+fn quote_of(symbol: &str) -> &str {
+    symbol.rsplit('-').next().unwrap_or_default()
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Lot {
+    // Positive when long, negative when short
+    size: f64,
+    price: f64,
+}
+
+struct SymbolBook {
+    method: CostMethod,
+    lots: VecDeque<Lot>,
+    realized: f64,
+    fees: HashMap<String, f64>,
+}
+
+impl SymbolBook {
+    fn position(&self) -> f64 {
+        self.lots.iter().map(|l| l.size).sum()
+    }
+
+    fn avg_cost(&self) -> Option<f64> {
+        let position = self.position();
+        if position.abs() < EPSILON {
+            return None;
+        }
+        Some(self.lots.iter().map(|l| l.size * l.price).sum::<f64>() / position)
+    }
+
+    // Replaces the estimated fee of a fill with the fee Kucoin charged.
+    fn correct_fee(&mut self, estimated: &Fill, fill: &Fill) {
+        if let Some(fee) = self.fees.get_mut(&estimated.fee_currency) {
+            *fee -= estimated.fee;
+        }
+        *self.fees.entry(fill.fee_currency.clone()).or_default() += fill.fee;
+    }
+
+    fn apply(&mut self, fill: &Fill) {
+        *self.fees.entry(fill.fee_currency.clone()).or_default() += fill.fee;
+        let mut qty = match fill.side {
+            Side::Buy => fill.size,
+            Side::Sell => -fill.size,
+        };
+        while qty.abs() > EPSILON {
+            let lot = match self.method {
+                CostMethod::Lifo => self.lots.back_mut(),
+                _ => self.lots.front_mut(),
+            };
+            let lot = match lot {
+                Some(lot) if lot.size.signum() != qty.signum() => lot,
+                _ => break,
+            };
+            let closed = qty.abs().min(lot.size.abs());
+            self.realized += closed * (fill.price - lot.price) * lot.size.signum();
+            lot.size -= closed * lot.size.signum();
+            qty -= closed * qty.signum();
+            if lot.size.abs() < EPSILON {
+                match self.method {
+                    CostMethod::Lifo => self.lots.pop_back(),
+                    _ => self.lots.pop_front(),
+                };
+            }
+        }
+        if qty.abs() > EPSILON {
+            self.lots.push_back(Lot {
+                size: qty,
+                price: fill.price,
+            });
+            if self.method == CostMethod::Average && self.lots.len() > 1 {
+                let price = self.avg_cost().unwrap_or(fill.price);
+                let size = self.position();
+                self.lots.clear();
+                self.lots.push_back(Lot { size, price });
+            }
+        }
+    }
+}
+
+/// PnL of one symbol, in its quote currency except for fees.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SymbolPnl {
+    pub symbol: String,
+    pub quote: String,
+    pub method: CostMethod,
+    /// Negative when short
+    pub position: f64,
+    /// Average price of the open lots
+    pub avg_cost: Option<f64>,
+    pub mark: Option<f64>,
+    pub realized: f64,
+    /// None while the position is open without a mark price
+    pub unrealized: Option<f64>,
+    /// Fees paid by fee currency
+    pub fees: HashMap<String, f64>,
+}
+
+/// PnL of every symbol converted to the reporting currency at the latest rates.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PnlReport {
+    pub currency: String,
+    pub symbols: Vec<SymbolPnl>,
+    pub realized: f64,
+    pub unrealized: f64,
+    pub fees: f64,
+    /// realized + unrealized - fees
+    pub net: f64,
+    /// Currencies without a rate to the reporting currency and open symbols without a mark,
+    /// left out of the totals
+    pub unconverted: Vec<String>,
+}
+
+/// Computes realized and unrealized PnL from fills with a CostMethod per symbol. Amounts in
+/// other currencies, such as the quote of ETH-BTC or fees paid in KCS, are converted to the
+/// reporting currency with rates set by hand or taken from the marks of their symbols against it.
+pub struct PnlEngine {
+    currency: String,
+    method: CostMethod,
+    methods: HashMap<String, CostMethod>,
+    fee_rate: f64,
+    books: HashMap<String, SymbolBook>,
+    marks: HashMap<String, f64>,
+    rates: HashMap<String, f64>,
+    // Trades added so far, with the fill of those whose fee is only estimated
+    seen: HashMap<(String, String), Option<Fill>>,
+}
+
+impl PnlEngine {
+    pub fn new(currency: &str) -> Self {
+        PnlEngine {
+            currency: currency.to_string(),
+            method: CostMethod::Fifo,
+            methods: HashMap::new(),
+            fee_rate: 0.001,
+            books: HashMap::new(),
+            marks: HashMap::new(),
+            rates: HashMap::new(),
+            seen: HashMap::new(),
+        }
+    }
+
+    /// Method of the symbols without one of their own, FIFO by default. Only applies to
+    /// symbols without fills yet.
+    pub fn method(&mut self, method: CostMethod) -> &mut Self {
+        self.method = method;
+        self
+    }
+
+    pub fn symbol_method(&mut self, symbol: &str, method: CostMethod) -> &mut Self {
+        self.methods.insert(symbol.to_string(), method);
+        self
+    }
+
+    /// Rate used to estimate the fees of TradeMatch events, 0.1% by default.
+    pub fn fee_rate(&mut self, fee_rate: f64) -> &mut Self {
+        self.fee_rate = fee_rate;
+        self
+    }
+
+    /// Value of one unit of the currency in the reporting currency, overriding marks.
+    pub fn rate(&mut self, currency: &str, rate: f64) -> &mut Self {
+        self.rates.insert(currency.to_string(), rate);
+        self
+    }
+
+    /// Adds the fill, returning false for a trade already added. A trade first added from a
+    /// TradeMatch event by on_message has its estimated fee replaced by the fill's.
+    pub fn on_fill(&mut self, fill: &Fill) -> bool {
+        self.add(fill, false)
+    }
+
+    fn add(&mut self, fill: &Fill, estimated_fee: bool) -> bool {
+        let key = (fill.symbol.clone(), fill.trade_id.clone());
+        if let Some(seen) = self.seen.get_mut(&key) {
+            if !estimated_fee {
+                if let (Some(estimated), Some(book)) =
+                    (seen.take(), self.books.get_mut(&fill.symbol))
+                {
+                    book.correct_fee(&estimated, fill);
+                }
+            }
+            return false;
+        }
+        self.seen.insert(key, estimated_fee.then(|| fill.clone()));
+        let method = *self.methods.get(&fill.symbol).unwrap_or(&self.method);
+        self.books
+            .entry(fill.symbol.clone())
+            .or_insert_with(|| SymbolBook {
+                method,
+                lots: VecDeque::new(),
+                realized: 0.0,
+                fees: HashMap::new(),
+            })
+            .apply(fill);
+        true
+    }
+
+    /// Adds fills from get_fills oldest first, as Kucoin lists them newest first. Returns how
+    /// many were new.
+    pub fn on_fills(&mut self, fills: &[FillsInfo]) -> usize {
+        // Reversed before the stable sort so fills of the same millisecond stay oldest first
+        let mut fills: Vec<Fill> = fills
+            .iter()
+            .rev()
+            .filter_map(Fill::from_fills_info)
+            .collect();
+        fills.sort_by_key(|f| f.time);
+        fills.iter().filter(|f| self.on_fill(f)).count()
+    }
+
+    /// Marks symbols with ticker messages and adds fills from TradeMatch events.
+    pub fn on_message(&mut self, msg: &KucoinWebsocketMsg) {
+        match msg {
+            KucoinWebsocketMsg::TickerMsg(r) => {
+                if let (Some(symbol), Ok(price)) =
+                    (r.topic.rsplit(':').next(), r.data.price.parse())
+                {
+                    self.mark(symbol, price);
+                }
+            }
+            KucoinWebsocketMsg::AllTickerMsg(r) => {
+                if let Ok(price) = r.data.price.parse() {
+                    self.mark(&r.subject, price);
+                }
+            }
+            KucoinWebsocketMsg::TradeMatchMsg(r) | KucoinWebsocketMsg::HfTradeMatchMsg(r) => {
+                if let Some(fill) = Fill::from_trade_match(&r.data, self.fee_rate) {
+                    self.add(&fill, true);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Latest price of the symbol, for unrealized PnL and conversions.
+    pub fn mark(&mut self, symbol: &str, price: f64) {
+        self.marks.insert(symbol.to_string(), price);
+    }
+
+    /// Value of one unit of the currency in the reporting currency.
+    pub fn conversion(&self, currency: &str) -> Option<f64> {
+        if currency == self.currency {
+            return Some(1.0);
+        }
+        if let Some(rate) = self.rates.get(currency) {
+            return Some(*rate);
+        }
+        if let Some(price) = self.marks.get(&format!("{}-{}", currency, self.currency)) {
+            return Some(*price);
+        }
+        self.marks
+            .get(&format!("{}-{}", self.currency, currency))
+            .filter(|p| **p > 0.0)
+            .map(|p| 1.0 / p)
+    }
+
+    pub fn symbol(&self, symbol: &str) -> Option<SymbolPnl> {
+        let book = self.books.get(symbol)?;
+        let position = book.position();
+        let avg_cost = book.avg_cost();
+        let mark = self.marks.get(symbol).copied();
+        let unrealized = match (avg_cost, mark) {
+            (None, _) => Some(0.0),
+            (Some(cost), Some(mark)) => Some((mark - cost) * position),
+            (Some(_), None) => None,
+        };
+        Some(SymbolPnl {
+            symbol: symbol.to_string(),
+            quote: quote_of(symbol).to_string(),
+            method: book.method,
+            position,
+            avg_cost,
+            mark,
+            realized: book.realized,
+            unrealized,
+            fees: book.fees.clone(),
+        })
+    }
+
+    pub fn report(&self) -> PnlReport {
+        let mut names: Vec<&String> = self.books.keys().collect();
+        names.sort();
+        let mut report = PnlReport {
+            currency: self.currency.clone(),
+            symbols: Vec::new(),
+            realized: 0.0,
+            unrealized: 0.0,
+            fees: 0.0,
+            net: 0.0,
+            unconverted: Vec::new(),
+        };
+        let mut unconverted = HashSet::new();
+        for pnl in names.into_iter().filter_map(|s| self.symbol(s)) {
+            match self.conversion(&pnl.quote) {
+                Some(rate) => {
+                    report.realized += pnl.realized * rate;
+                    report.unrealized += pnl.unrealized.unwrap_or(0.0) * rate;
+                }
+                None => {
+                    unconverted.insert(pnl.quote.clone());
+                }
+            }
+            if pnl.unrealized.is_none() {
+                unconverted.insert(pnl.symbol.clone());
+            }
+            for (currency, fee) in pnl.fees.iter() {
+                match self.conversion(currency) {
+                    Some(rate) => report.fees += fee * rate,
+                    None => {
+                        unconverted.insert(currency.clone());
+                    }
+                }
+            }
+            report.symbols.push(pnl);
+        }
+        report.net = report.realized + report.unrealized - report.fees;
+        report.unconverted = unconverted.into_iter().collect();
+        report.unconverted.sort();
+        report
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::kucoin::exchange::Side;
+    use crate::kucoin::model::trade::FillsInfo;
+    use crate::kucoin::pnl::{CostMethod, Fill, PnlEngine};
+    use crate::kucoin::websocket::parse_message;
+    use tokio_tungstenite::tungstenite::Message;
+
+    fn fill(id: &str, side: Side, price: f64, size: f64) -> Fill {
+        Fill {
+            symbol: "BTC-USDT".to_string(),
+            trade_id: id.to_string(),
+            side,
+            price,
+            size,
+            fee: 0.0,
+            fee_currency: "USDT".to_string(),
+            time: 0,
+        }
+    }
+
+    #[test]
+    fn cost_methods_split_realized_and_unrealized() {
+        for (method, realized, unrealized) in [
+            (CostMethod::Fifo, 150.0, 100.0),
+            (CostMethod::Lifo, 50.0, 200.0),
+            (CostMethod::Average, 100.0, 150.0),
+        ]
+        .iter()
+        {
+            let mut engine = PnlEngine::new("USDT");
+            engine.method(*method);
+            engine.on_fill(&fill("1", Side::Buy, 100.0, 1.0));
+            engine.on_fill(&fill("2", Side::Buy, 200.0, 1.0));
+            engine.on_fill(&fill("3", Side::Sell, 250.0, 1.0));
+            assert!(!engine.on_fill(&fill("3", Side::Sell, 250.0, 1.0)));
+            engine.mark("BTC-USDT", 300.0);
+            let pnl = engine.symbol("BTC-USDT").unwrap();
+            assert_eq!(pnl.position, 1.0);
+            assert_eq!(pnl.realized, *realized);
+            assert_eq!(pnl.unrealized, Some(*unrealized));
+        }
+
+        // Selling through the position opens a short.
+        let mut engine = PnlEngine::new("USDT");
+        engine.on_fill(&fill("1", Side::Buy, 100.0, 1.0));
+        engine.on_fill(&fill("2", Side::Sell, 120.0, 3.0));
+        engine.mark("BTC-USDT", 110.0);
+        let pnl = engine.symbol("BTC-USDT").unwrap();
+        assert_eq!(pnl.position, -2.0);
+        assert_eq!(pnl.realized, 20.0);
+        assert_eq!(pnl.unrealized, Some(20.0));
+    }
+
+    #[test]
+    fn report_converts_quotes_and_fees() {
+        let fills: Vec<FillsInfo> = serde_json::from_str(
+            r#"[
+            {"symbol":"ETH-BTC","tradeId":"2","orderId":"o2","counterOrderId":"c","side":"sell","liquidity":"taker","forceTaker":false,"price":"0.06","size":"1","funds":"0.06","fee":"0.5","feeRate":"0.001","feeCurrency":"KCS","stop":"","type":"limit","createdAt":2,"tradeType":"TRADE"},
+            {"symbol":"ETH-BTC","tradeId":"1","orderId":"o1","counterOrderId":"c","side":"buy","liquidity":"taker","forceTaker":false,"price":"0.05","size":"2","funds":"0.1","fee":"0.0001","feeRate":"0.001","feeCurrency":"BTC","stop":"","type":"limit","createdAt":1,"tradeType":"TRADE"}
+            ]"#,
+        )
+        .unwrap();
+        let mut engine = PnlEngine::new("USDT");
+        assert_eq!(engine.on_fills(&fills), 2);
+        engine.rate("KCS", 10.0);
+        let ticker = r#"{"type":"message","topic":"/market/ticker:BTC-USDT","subject":"trade.ticker","data":{"sequence":"1","price":"20000","size":"0.1","bestAsk":"20001","bestAskSize":"1","bestBid":"19999","bestBidSize":"1"}}"#;
+        engine.on_message(&parse_message(Message::Text(ticker.to_string())).unwrap());
+
+        let report = engine.report();
+        assert_eq!(report.unconverted, vec!["ETH-BTC".to_string()]);
+        assert!((report.realized - 200.0).abs() < 1e-6);
+        assert!((report.fees - 7.0).abs() < 1e-6);
+
+        engine.mark("ETH-BTC", 0.07);
+        let report = engine.report();
+        assert!(report.unconverted.is_empty());
+        assert!((report.unrealized - 400.0).abs() < 1e-6);
+        assert!((report.net - 593.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn fills_replace_estimated_fees_and_keep_listed_order() {
+        let info = |id: &str, side: &str, price: &str, fee: &str| -> FillsInfo {
+            let fill = format!(
+                r#"{{"symbol":"BTC-USDT","tradeId":"{}","orderId":"o{}","counterOrderId":"c","side":"{}","liquidity":"maker","forceTaker":false,"price":"{}","size":"1","funds":"{}","fee":"{}","feeRate":"0.0008","feeCurrency":"USDT","stop":"","type":"limit","createdAt":1,"tradeType":"TRADE"}}"#,
+                id, id, side, price, price, fee
+            );
+            serde_json::from_str(&fill).unwrap()
+        };
+
+        let mut engine = PnlEngine::new("USDT");
+        let event = r#"{"type":"message","topic":"/spotMarket/tradeOrdersV2","subject":"orderChange","channelType":"private","data":{"symbol":"BTC-USDT","orderType":"limit","side":"buy","liquidity":"maker","orderId":"o1","type":"match","orderTime":1,"size":"1","filledSize":"1","price":"100","matchPrice":"100","matchSize":"1","tradeId":"1","clientOid":"c","remainSize":"0","status":"match","ts":1000000}}"#;
+        engine.on_message(&parse_message(Message::Text(event.to_string())).unwrap());
+        assert!((engine.symbol("BTC-USDT").unwrap().fees["USDT"] - 0.1).abs() < 1e-9);
+        assert_eq!(engine.on_fills(&[info("1", "buy", "100", "0.08")]), 0);
+        assert!((engine.symbol("BTC-USDT").unwrap().fees["USDT"] - 0.08).abs() < 1e-9);
+
+        // Newest first, all in the same millisecond
+        let mut engine = PnlEngine::new("USDT");
+        let fills = vec![
+            info("3", "sell", "150", "0"),
+            info("2", "buy", "200", "0"),
+            info("1", "buy", "100", "0"),
+        ];
+        assert_eq!(engine.on_fills(&fills), 3);
+        let pnl = engine.symbol("BTC-USDT").unwrap();
+        assert_eq!(pnl.position, 1.0);
+        assert_eq!(pnl.realized, 50.0);
+    }
+}
@@ -0,0 +1,413 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::Stream;
+use tokio::task::JoinHandle;
+
+use super::error::APIError;
+use super::exchange::Account;
+use super::model::user::Accounts;
+use super::model::websocket::{Balances, KucoinWebsocketMsg};
+use super::utils::{broadcast, feed};
+
+// Differences below this are rounding, not drift.
+const DRIFT_TOLERANCE: f64 = 1e-9;
+
+/// Balance of one account, i.e. one currency of one account type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Balance {
+    pub account_id: String,
+    /// main, trade, margin..., empty for accounts only known from a push so far
+    pub account_type: String,
+    pub currency: String,
+    pub available: f64,
+    pub hold: f64,
+    pub total: f64,
+    /// Server time in milliseconds of the push or request the balance was last updated from
+    pub time: i64,
+}
+
+impl Balance {
+    fn from_account(account: &Accounts, time: i64) -> Option<Self> {
+        Some(Balance {
+            account_id: account.id.clone(),
+            account_type: account.r#type.clone(),
+            currency: account.currency.clone(),
+            available: account.available.parse().ok()?,
+            hold: account.holds.parse().ok()?,
+            total: account.balance.parse().ok()?,
+            time,
+        })
+    }
+
+    // The balance emptied, for an account Kucoin no longer lists.
+    fn emptied(&self, time: i64) -> Self {
+        Balance {
+            available: 0.0,
+            hold: 0.0,
+            total: 0.0,
+            time,
+            ..self.clone()
+        }
+    }
+
+    fn drifted(&self, other: &Balance) -> bool {
+        (self.available - other.available).abs() > DRIFT_TOLERANCE
+            || (self.hold - other.hold).abs() > DRIFT_TOLERANCE
+            || (self.total - other.total).abs() > DRIFT_TOLERANCE
+    }
+}
+
+/// Why a balance changed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChangeReason {
+    /// Loaded by Portfolio::seed
+    Seed,
+    /// /account/balance push, with its relationEvent such as trade.hold or main.deposit
+    Push {
+        relation_event: String,
+        relation_event_id: String,
+    },
+    /// Corrected by Portfolio::reconcile after drifting from Kucoin's balance, or emptied and
+    /// no longer tracked when Kucoin no longer lists the account
+    Reconcile,
+}
+
+/// Change of one balance, `before` is None for accounts not tracked until then.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BalanceChange {
+    pub before: Option<Balance>,
+    pub after: Balance,
+    pub reason: ChangeReason,
+}
+
+/// Consistent copy of every tracked balance. Version increases with every change.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PortfolioSnapshot {
+    pub version: u64,
+    pub balances: Vec<Balance>,
+}
+
+impl PortfolioSnapshot {
+    pub fn balance(&self, account_type: &str, currency: &str) -> Option<&Balance> {
+        self.balances
+            .iter()
+            .find(|b| b.account_type == account_type && b.currency == currency)
+    }
+
+    /// Total of the currency over every account type.
+    pub fn total(&self, currency: &str) -> f64 {
+        self.balances
+            .iter()
+            .filter(|b| b.currency == currency)
+            .map(|b| b.total)
+            .sum()
+    }
+}
+
+struct State {
+    balances: HashMap<String, Balance>,
+    listeners: Vec<UnboundedSender<BalanceChange>>,
+    version: u64,
+}
+
+impl State {
+    fn set(&mut self, after: Balance, reason: ChangeReason) {
+        let before = self
+            .balances
+            .insert(after.account_id.clone(), after.clone());
+        self.notify(before, after, reason);
+    }
+
+    fn remove(&mut self, account_id: &str, time: i64, reason: ChangeReason) {
+        if let Some(before) = self.balances.remove(account_id) {
+            let after = before.emptied(time);
+            self.notify(Some(before), after, reason);
+        }
+    }
+
+    fn notify(&mut self, before: Option<Balance>, after: Balance, reason: ChangeReason) {
+        self.version += 1;
+        let change = BalanceChange {
+            before,
+            after,
+            reason,
+        };
+        broadcast(&mut self.listeners, &change);
+    }
+
+    fn on_balances(&mut self, push: &Balances) -> bool {
+        let time: i64 = push.time.parse().unwrap_or(0);
+        let known = self.balances.get(&push.account_id);
+        // Pushes sent before the balance was last requested are already part of it.
+        if known.is_some_and(|b| time < b.time) {
+            return false;
+        }
+        let (available, hold, total) = match (
+            push.available.parse(),
+            push.hold.parse(),
+            push.total.parse(),
+        ) {
+            (Ok(a), Ok(h), Ok(t)) => (a, h, t),
+            _ => return false,
+        };
+        let after = Balance {
+            account_id: push.account_id.clone(),
+            account_type: known.map(|b| b.account_type.clone()).unwrap_or_default(),
+            currency: push.currency.clone(),
+            available,
+            hold,
+            total,
+            time,
+        };
+        let reason = ChangeReason::Push {
+            relation_event: push.relation_event.clone(),
+            relation_event_id: push.relation_event_id.clone(),
+        };
+        self.set(after, reason);
+        true
+    }
+
+    // Applies every account's balance requested at `time` (server time), returning how many
+    // changed. Accounts not listed are removed unless pushed since.
+    fn on_accounts(&mut self, accounts: &[Accounts], time: i64, reason: ChangeReason) -> usize {
+        let mut drifted = 0;
+        let vanished: Vec<String> = self
+            .balances
+            .values()
+            .filter(|b| b.time <= time && !accounts.iter().any(|a| a.id == b.account_id))
+            .map(|b| b.account_id.clone())
+            .collect();
+        for account_id in vanished {
+            drifted += 1;
+            self.remove(&account_id, time, reason.clone());
+        }
+        for account in accounts {
+            let remote = match Balance::from_account(account, time) {
+                Some(remote) => remote,
+                None => continue,
+            };
+            let update = match self.balances.get(&remote.account_id) {
+                // Updated by a push while the request was in flight
+                Some(local) if local.time > time => false,
+                Some(local) => local.drifted(&remote) || local.account_type != remote.account_type,
+                None => true,
+            };
+            if update {
+                drifted += 1;
+                self.set(remote, reason.clone());
+            } else if let Some(local) = self.balances.get_mut(&remote.account_id) {
+                local.time = local.time.max(time);
+            }
+        }
+        drifted
+    }
+}
+
+/// Balances of every account, seeded from get_accounts_list and kept current with the
+/// /account/balance pushes. Periodic reconciliation against REST corrects any drift, e.g.
+/// from pushes missed while the websocket reconnected. Clones share the same balances.
+#[derive(Clone)]
+pub struct Portfolio {
+    state: Arc<Mutex<State>>,
+}
+
+impl Default for Portfolio {
+    fn default() -> Self {
+        Portfolio::new()
+    }
+}
+
+impl Portfolio {
+    pub fn new() -> Self {
+        Portfolio {
+            state: Arc::new(Mutex::new(State {
+                balances: HashMap::new(),
+                listeners: Vec::new(),
+                version: 0,
+            })),
+        }
+    }
+
+    /// Loads every account's balance, e.g. from Kucoin or a PaperExchange. Balances are stamped
+    /// with the account's server time so pushes can be ordered against them.
+    pub async fn seed(&self, account: &dyn Account) -> Result<(), APIError> {
+        let time = account.server_time().await?;
+        let accounts = account.accounts(None, None).await?;
+        self.state
+            .lock()
+            .unwrap()
+            .on_accounts(&accounts, time, ChangeReason::Seed);
+        Ok(())
+    }
+
+    /// Compares every balance with the account's and corrects those that drifted, returning
+    /// how many did. Balances pushed since the request was sent are kept, and those of
+    /// accounts no longer listed are removed.
+    pub async fn reconcile(&self, account: &dyn Account) -> Result<usize, APIError> {
+        let time = account.server_time().await?;
+        let accounts = account.accounts(None, None).await?;
+        Ok(self
+            .state
+            .lock()
+            .unwrap()
+            .on_accounts(&accounts, time, ChangeReason::Reconcile))
+    }
+
+    /// Reconciles at the interval until the task is aborted. Failed requests are retried at
+    /// the next interval.
+    pub fn spawn_reconcile(&self, account: Arc<dyn Account>, interval: Duration) -> JoinHandle<()> {
+        let portfolio = self.clone();
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            ticks.tick().await;
+            loop {
+                ticks.tick().await;
+                let _ = portfolio.reconcile(account.as_ref()).await;
+            }
+        })
+    }
+
+    /// Applies a BalancesMsg, returning whether it changed a balance. Other messages and
+    /// pushes older than the tracked balance are ignored.
+    pub fn on_message(&self, msg: &KucoinWebsocketMsg) -> bool {
+        match msg {
+            KucoinWebsocketMsg::BalancesMsg(r) => self.state.lock().unwrap().on_balances(&r.data),
+            _ => false,
+        }
+    }
+
+    /// Applies the balance pushes of a stream subscribed to WSTopic::Balances until it ends.
+    pub async fn feed<S>(&self, stream: S)
+    where
+        S: Stream<Item = Result<KucoinWebsocketMsg, APIError>>,
+    {
+        feed(stream, |msg| {
+            self.on_message(msg);
+        })
+        .await
+    }
+
+    pub fn snapshot(&self) -> PortfolioSnapshot {
+        let state = self.state.lock().unwrap();
+        let mut balances: Vec<Balance> = state.balances.values().cloned().collect();
+        balances.sort_by(|a, b| {
+            (&a.account_type, &a.currency, &a.account_id).cmp(&(
+                &b.account_type,
+                &b.currency,
+                &b.account_id,
+            ))
+        });
+        PortfolioSnapshot {
+            version: state.version,
+            balances,
+        }
+    }
+
+    pub fn balance(&self, account_type: &str, currency: &str) -> Option<Balance> {
+        let state = self.state.lock().unwrap();
+        state
+            .balances
+            .values()
+            .find(|b| b.account_type == account_type && b.currency == currency)
+            .cloned()
+    }
+
+    /// Every balance change from now on.
+    pub fn changes(&self) -> UnboundedReceiver<BalanceChange> {
+        let (tx, rx) = mpsc::unbounded();
+        self.state.lock().unwrap().listeners.push(tx);
+        rx
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::kucoin::error::APIError;
+    use crate::kucoin::exchange::Account;
+    use crate::kucoin::model::user::Accounts;
+    use crate::kucoin::model::websocket::KucoinWebsocketMsg;
+    use crate::kucoin::portfolio::{BalanceChange, ChangeReason, Portfolio};
+    use crate::kucoin::websocket::parse_message;
+    use async_trait::async_trait;
+    use futures::StreamExt;
+    use std::sync::Mutex;
+    use tokio_tungstenite::tungstenite::Message;
+
+    struct Remote(Mutex<Vec<Accounts>>);
+
+    #[async_trait]
+    impl Account for Remote {
+        async fn accounts(
+            &self,
+            _: Option<&str>,
+            _: Option<&str>,
+        ) -> Result<Vec<Accounts>, APIError> {
+            Ok(self.0.lock().unwrap().clone())
+        }
+    }
+
+    fn account(id: &str, currency: &str, balance: &str, holds: &str) -> Accounts {
+        let total: f64 = balance.parse().unwrap();
+        let holds_f: f64 = holds.parse().unwrap();
+        Accounts {
+            id: id.to_string(),
+            currency: currency.to_string(),
+            r#type: "trade".to_string(),
+            balance: balance.to_string(),
+            available: (total - holds_f).to_string(),
+            holds: holds.to_string(),
+        }
+    }
+
+    fn push(time: i64, total: &str, available: &str, hold: &str) -> KucoinWebsocketMsg {
+        let frame = format!(
+            r#"{{"type":"message","topic":"/account/balance","subject":"account.balance","data":{{"total":"{}","available":"{}","availableChange":"0","currency":"USDT","hold":"{}","holdChange":"0","relationEvent":"trade.hold","relationEventId":"1","time":"{}","accountId":"a1"}}}}"#,
+            total, available, hold, time
+        );
+        parse_message(Message::Text(frame)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn pushes_update_seeded_balances_and_reconcile_fixes_drift() {
+        let remote = Remote(Mutex::new(vec![
+            account("a1", "USDT", "100", "0"),
+            account("a2", "BTC", "1", "0"),
+        ]));
+        let portfolio = Portfolio::new();
+        let mut changes = portfolio.changes();
+        portfolio.seed(&remote).await.unwrap();
+        let seeded = portfolio.snapshot();
+        assert_eq!(seeded.balances.len(), 2);
+        assert_eq!(seeded.total("USDT"), 100.0);
+
+        // Older than the seed, already part of it.
+        assert!(!portfolio.on_message(&push(1, "90", "90", "0")));
+        let now = seeded.balance("trade", "USDT").unwrap().time;
+        assert!(portfolio.on_message(&push(now + 1, "100", "60", "40")));
+        let usdt = portfolio.balance("trade", "USDT").unwrap();
+        assert_eq!((usdt.available, usdt.hold, usdt.total), (60.0, 40.0, 100.0));
+
+        // Still matches Kucoin after the push was sent.
+        *remote.0.lock().unwrap() = vec![
+            account("a1", "USDT", "100", "40"),
+            account("a2", "BTC", "1.5", "0"),
+        ];
+        assert_eq!(portfolio.reconcile(&remote).await.unwrap(), 1);
+        assert_eq!(portfolio.snapshot().total("BTC"), 1.5);
+
+        *remote.0.lock().unwrap() = vec![account("a1", "USDT", "100", "40")];
+        assert_eq!(portfolio.reconcile(&remote).await.unwrap(), 1);
+        assert_eq!(portfolio.snapshot().balances.len(), 1);
+
+        let changes: Vec<BalanceChange> = changes.by_ref().take(5).collect().await;
+        assert_eq!(changes[0].reason, ChangeReason::Seed);
+        assert!(matches!(changes[2].reason, ChangeReason::Push { .. }));
+        assert_eq!(changes[3].reason, ChangeReason::Reconcile);
+        assert_eq!(changes[4].before.as_ref().unwrap().total, 1.5);
+        assert_eq!(changes[4].after.total, 0.0);
+        assert_eq!(portfolio.snapshot().version, 5);
+    }
+}
@@ -0,0 +1,286 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures::channel::mpsc::{channel, Receiver};
+use futures::executor::block_on;
+use futures::{ready, SinkExt, Stream, StreamExt};
+use tokio::time::{self, Duration, Instant, Sleep};
+use tokio_tungstenite::tungstenite::Message;
+
+use super::error::APIError;
+use super::model::websocket::{KucoinWebsocketMsg, ParseMode};
+use super::websocket::parse_message_with;
+
+/// A raw text frame as received from Kucoin, one JSON line of a recording.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct RecordedFrame {
+    /// Receive time in milliseconds since the epoch
+    pub ts: u64,
+    pub frame: String,
+}
+
+pub(crate) struct Tap {
+    id: usize,
+    tx: mpsc::Sender<RecordedFrame>,
+}
+
+pub(crate) type Taps = Arc<std::sync::Mutex<Vec<Tap>>>;
+
+static NEXT_TAP: AtomicUsize = AtomicUsize::new(0);
+
+// Hands a text frame to every active recorder with the time the reader received it.
+pub(crate) fn record(taps: &std::sync::Mutex<Vec<Tap>>, frame: &str, received: u64) {
+    let taps = taps.lock().unwrap();
+    for tap in taps.iter() {
+        let _ = tap.tx.send(RecordedFrame {
+            ts: received,
+            frame: frame.to_string(),
+        });
+    }
+}
+
+/// Writes every raw frame received by a KucoinWebsocket, with its receive time, to a gzip
+/// compressed file of JSON lines. Created by KucoinWebsocket::record, recording stops when
+/// the Recorder is finished or dropped, either of which waits for the file to be completed.
+pub struct Recorder {
+    id: usize,
+    taps: Taps,
+    writer: Option<thread::JoinHandle<Result<u64, APIError>>>,
+}
+
+impl Recorder {
+    #[allow(clippy::result_large_err)]
+    pub(crate) fn start<P: AsRef<Path>>(path: P, taps: &Taps) -> Result<Self, APIError> {
+        let file = File::create(path)
+            .map_err(|e| APIError::Other(format!("Failed creating recording: {}", e)))?;
+        let (tx, rx) = mpsc::channel();
+        let writer = thread::spawn(move || {
+            write_frames(file, rx)
+                .map_err(|e| APIError::Other(format!("Failed writing recording: {}", e)))
+        });
+        let id = NEXT_TAP.fetch_add(1, Ordering::Relaxed);
+        taps.lock().unwrap().push(Tap { id, tx });
+        Ok(Recorder {
+            id,
+            taps: taps.clone(),
+            writer: Some(writer),
+        })
+    }
+
+    /// Stops recording and completes the file, returning the number of frames written.
+    #[allow(clippy::result_large_err)]
+    pub fn finish(mut self) -> Result<u64, APIError> {
+        self.stop()
+    }
+
+    // Closes the writer's channel and waits for it to complete the file.
+    #[allow(clippy::result_large_err)]
+    fn stop(&mut self) -> Result<u64, APIError> {
+        self.taps.lock().unwrap().retain(|t| t.id != self.id);
+        match self.writer.take() {
+            Some(writer) => writer
+                .join()
+                .map_err(|_| APIError::Other("Recording writer panicked".to_string()))?,
+            None => Ok(0),
+        }
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+fn write_frames(file: File, rx: mpsc::Receiver<RecordedFrame>) -> std::io::Result<u64> {
+    let mut out = GzEncoder::new(BufWriter::new(file), Compression::default());
+    let mut written = 0;
+    for frame in rx {
+        serde_json::to_writer(&mut out, &frame)?;
+        out.write_all(b"\n")?;
+        written += 1;
+    }
+    out.finish()?.flush()?;
+    Ok(written)
+}
+
+/// Pace of a Replay.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaySpeed {
+    /// Frames are yielded with the gaps they were received with.
+    Original,
+    /// Gaps between frames are divided by the factor, e.g. 10.0 replays ten times faster.
+    Accelerated(f64),
+    /// Frames are yielded as soon as they are read.
+    AsFastAsPossible,
+}
+
+/// Stream of the messages of a recording made with a Recorder, yielded like a KucoinWebsocket
+/// would have at the chosen speed. Must be polled within a tokio runtime unless replayed
+/// as fast as possible.
+pub struct Replay {
+    rx: Receiver<Result<RecordedFrame, APIError>>,
+    speed: ReplaySpeed,
+    mode: ParseMode,
+    start: Option<(Instant, u64)>,
+    pending: Option<RecordedFrame>,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl Replay {
+    #[allow(clippy::result_large_err)]
+    pub fn open<P: AsRef<Path>>(path: P, speed: ReplaySpeed) -> Result<Self, APIError> {
+        let file = File::open(path)
+            .map_err(|e| APIError::Other(format!("Failed opening recording: {}", e)))?;
+        if let ReplaySpeed::Accelerated(factor) = speed {
+            if !(factor > 0.0 && factor.is_finite()) {
+                return Err(APIError::Other(format!(
+                    "Replay acceleration must be positive, got {}",
+                    factor
+                )));
+            }
+        }
+        let (mut tx, rx) = channel(1024);
+        thread::spawn(move || {
+            for line in BufReader::new(GzDecoder::new(file)).lines() {
+                let frame = match line {
+                    Ok(line) if line.is_empty() => continue,
+                    Ok(line) => serde_json::from_str(&line).map_err(APIError::Serde),
+                    Err(e) => Err(APIError::Other(format!("Failed reading recording: {}", e))),
+                };
+                let failed = frame.is_err();
+                if block_on(tx.send(frame)).is_err() || failed {
+                    break;
+                }
+            }
+        });
+        Ok(Replay {
+            rx,
+            speed,
+            mode: ParseMode::default(),
+            start: None,
+            pending: None,
+            sleep: None,
+        })
+    }
+
+    /// Parses the recorded frames with the mode, defaults to ParseMode::Strict.
+    pub fn parse_mode(mut self, mode: ParseMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    // When the frame is due relative to the first frame of the replay.
+    fn due(&mut self, frame: &RecordedFrame) -> Option<Instant> {
+        let factor = match self.speed {
+            ReplaySpeed::Original => 1.0,
+            ReplaySpeed::Accelerated(factor) => factor,
+            ReplaySpeed::AsFastAsPossible => return None,
+        };
+        let (start, first) = *self.start.get_or_insert((Instant::now(), frame.ts));
+        let gap = frame.ts.saturating_sub(first) as f64 / factor;
+        Some(start + Duration::from_secs_f64(gap / 1000.0))
+    }
+}
+
+impl Stream for Replay {
+    type Item = Result<KucoinWebsocketMsg, APIError>;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(sleep) = this.sleep.as_mut() {
+                ready!(sleep.as_mut().poll(cx));
+                this.sleep = None;
+            }
+            if let Some(frame) = this.pending.take() {
+                let msg = parse_message_with(Message::Text(frame.frame), this.mode);
+                return Poll::Ready(Some(msg));
+            }
+            match ready!(this.rx.poll_next_unpin(cx)) {
+                Some(Ok(frame)) => {
+                    if let Some(due) = this.due(&frame) {
+                        if due > Instant::now() {
+                            this.sleep = Some(Box::pin(time::sleep_until(due)));
+                        }
+                    }
+                    this.pending = Some(frame);
+                }
+                Some(Err(e)) => return Poll::Ready(Some(Err(e))),
+                None => return Poll::Ready(None),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::kucoin::model::websocket::KucoinWebsocketMsg;
+    use crate::kucoin::recorder::{record, Recorder, Replay, ReplaySpeed, Taps};
+    use futures::StreamExt;
+    use std::time::Instant;
+
+    #[tokio::test]
+    async fn replays_recorded_frames() {
+        let path =
+            std::env::temp_dir().join(format!("kucoin_rs_replay_{}.jsonl.gz", std::process::id()));
+        let taps = Taps::default();
+        let recorder = Recorder::start(&path, &taps).unwrap();
+        record(&taps, r#"{"id":"hQvf8jkno","type":"welcome"}"#, 1000);
+        record(
+            &taps,
+            r#"{"type":"message","topic":"/market/match:BTC-USDT","subject":"trade.l3match","data":{"sequence":"1545896669145","type":"match","symbol":"BTC-USDT","side":"buy","price":"0.082","size":"0.0102","tradeId":"5c24c5da03aa673885cd67aa","takerOrderId":"5c24c5d903aa6772d55b371e","makerOrderId":"5c2187d003aa677bd09d5c93","time":"1545913818099033203"}}"#,
+            1001,
+        );
+        assert_eq!(recorder.finish().unwrap(), 2);
+        // Nothing is recorded once finished
+        record(&taps, r#"{"id":"2","type":"pong"}"#, 1002);
+
+        for speed in &[
+            ReplaySpeed::AsFastAsPossible,
+            ReplaySpeed::Accelerated(100.0),
+        ] {
+            let msgs: Vec<_> = Replay::open(&path, *speed).unwrap().collect().await;
+            assert_eq!(msgs.len(), 2);
+            assert!(matches!(msgs[0], Ok(KucoinWebsocketMsg::WelcomeMsg(_))));
+            match &msgs[1] {
+                Ok(KucoinWebsocketMsg::MatchMsg(m)) => assert_eq!(m.data.price, "0.082"),
+                m => panic!("Unexpected message {:?}", m),
+            }
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn original_speed_keeps_gaps() {
+        let path =
+            std::env::temp_dir().join(format!("kucoin_rs_gaps_{}.jsonl.gz", std::process::id()));
+        let taps = Taps::default();
+        // Dropping the recorder completes the file as finishing it does
+        {
+            let _recorder = Recorder::start(&path, &taps).unwrap();
+            record(&taps, r#"{"id":"1","type":"pong"}"#, 1000);
+            record(&taps, r#"{"id":"2","type":"pong"}"#, 1050);
+        }
+
+        let started = Instant::now();
+        let msgs: Vec<_> = Replay::open(&path, ReplaySpeed::Original)
+            .unwrap()
+            .collect()
+            .await;
+        assert_eq!(msgs.len(), 2);
+        assert!(started.elapsed().as_millis() >= 45);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
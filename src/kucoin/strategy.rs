@@ -0,0 +1,490 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::channel::mpsc::UnboundedReceiver;
+use futures::channel::oneshot;
+use futures::future;
+use futures::stream::BoxStream;
+use futures::{Stream, StreamExt};
+use tokio::task::JoinHandle;
+
+use super::client::Kucoin;
+use super::error::APIError;
+use super::exchange::{Account, OrderEntry};
+use super::model::websocket::{
+    KucoinWebsocketMsg, Level2, Level2Depth, Match, SymbolTicker, TradeCanceled, TradeFilled,
+    TradeMatch, TradeOpen, TradeUpdate, WSTopic,
+};
+use super::paper::PaperExchange;
+use super::recorder::Replay;
+
+/// Order book message passed to Strategy::on_book.
+#[derive(Debug, Clone, Copy)]
+pub enum BookUpdate<'a> {
+    /// Incremental changes of /market/level2
+    Changes(&'a Level2),
+    /// Snapshot of /spotMarket/level2Depth5 or level2Depth50
+    Depth {
+        symbol: &'a str,
+        depth: &'a Level2Depth,
+    },
+}
+
+/// Change of one of the account's orders, from /spotMarket/tradeOrders or a PaperExchange.
+#[derive(Debug, Clone)]
+pub enum OrderUpdate {
+    Open(TradeOpen),
+    Match(TradeMatch),
+    Filled(TradeFilled),
+    Canceled(TradeCanceled),
+    Update(TradeUpdate),
+}
+
+impl OrderUpdate {
+    pub fn from_message(msg: &KucoinWebsocketMsg) -> Option<Self> {
+        use KucoinWebsocketMsg::*;
+        match msg {
+            TradeOpenMsg(r) | HfTradeOpenMsg(r) => Some(OrderUpdate::Open(r.data.clone())),
+            TradeMatchMsg(r) | HfTradeMatchMsg(r) => Some(OrderUpdate::Match(r.data.clone())),
+            TradeFilledMsg(r) | HfTradeFilledMsg(r) => Some(OrderUpdate::Filled(r.data.clone())),
+            TradeCanceledMsg(r) | HfTradeCanceledMsg(r) => {
+                Some(OrderUpdate::Canceled(r.data.clone()))
+            }
+            TradeUpdateMsg(r) | HfTradeUpdateMsg(r) => Some(OrderUpdate::Update(r.data.clone())),
+            _ => None,
+        }
+    }
+
+    pub fn order_id(&self) -> &str {
+        match self {
+            OrderUpdate::Open(o) => &o.order_id,
+            OrderUpdate::Match(o) => &o.order_id,
+            OrderUpdate::Filled(o) => &o.order_id,
+            OrderUpdate::Canceled(o) => &o.order_id,
+            OrderUpdate::Update(o) => &o.order_id,
+        }
+    }
+}
+
+/// What a Strategy trades through, shared by every hook.
+#[derive(Clone)]
+pub struct StrategyContext {
+    orders: Arc<dyn OrderEntry>,
+    account: Arc<dyn Account>,
+    stopping: Arc<AtomicBool>,
+}
+
+impl StrategyContext {
+    pub fn new(orders: Arc<dyn OrderEntry>, account: Arc<dyn Account>) -> Self {
+        StrategyContext {
+            orders,
+            account,
+            stopping: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Kucoin when trading live, the PaperExchange otherwise.
+    pub fn orders(&self) -> &dyn OrderEntry {
+        self.orders.as_ref()
+    }
+
+    pub fn account(&self) -> &dyn Account {
+        self.account.as_ref()
+    }
+
+    /// Stops the runtime once the current hook returns.
+    pub fn stop(&self) {
+        self.stopping.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_stopping(&self) -> bool {
+        self.stopping.load(Ordering::SeqCst)
+    }
+}
+
+/// Trading logic run by a StrategyRuntime. Every hook defaults to doing nothing, and an error
+/// returned by a hook stops the runtime.
+#[async_trait]
+pub trait Strategy: Send {
+    /// Market data topics the strategy needs, subscribed by the live and paper runtimes.
+    fn topics(&self) -> Vec<WSTopic>;
+
+    async fn on_start(&mut self, _ctx: &StrategyContext) -> Result<(), APIError> {
+        Ok(())
+    }
+
+    async fn on_ticker(
+        &mut self,
+        _ctx: &StrategyContext,
+        _symbol: &str,
+        _ticker: &SymbolTicker,
+    ) -> Result<(), APIError> {
+        Ok(())
+    }
+
+    async fn on_trade(&mut self, _ctx: &StrategyContext, _trade: &Match) -> Result<(), APIError> {
+        Ok(())
+    }
+
+    async fn on_book(
+        &mut self,
+        _ctx: &StrategyContext,
+        _book: BookUpdate<'_>,
+    ) -> Result<(), APIError> {
+        Ok(())
+    }
+
+    async fn on_order_update(
+        &mut self,
+        _ctx: &StrategyContext,
+        _update: &OrderUpdate,
+    ) -> Result<(), APIError> {
+        Ok(())
+    }
+
+    /// Called at the interval set with StrategyRuntime::timer.
+    async fn on_timer(&mut self, _ctx: &StrategyContext) -> Result<(), APIError> {
+        Ok(())
+    }
+
+    /// Called once the runtime stops, for whatever reason, e.g. to cancel open orders.
+    async fn on_stop(&mut self, _ctx: &StrategyContext) -> Result<(), APIError> {
+        Ok(())
+    }
+}
+
+type MsgStream = BoxStream<'static, Result<KucoinWebsocketMsg, APIError>>;
+
+/// Runs a Strategy against live Kucoin, a PaperExchange fed with live data, or a replayed
+/// recording, dispatching each message of its source to the matching hook. Frames the source
+/// could not parse are skipped, while a websocket error or APIError::Disconnected stops the
+/// runtime with that error, as the strategy would otherwise miss data unknowingly.
+pub struct StrategyRuntime<S> {
+    strategy: S,
+    source: MsgStream,
+    ctx: StrategyContext,
+    paper: Option<(PaperExchange, UnboundedReceiver<KucoinWebsocketMsg>)>,
+    timer: Option<Duration>,
+}
+
+impl<S: Strategy + 'static> StrategyRuntime<S> {
+    /// Runs the strategy with any source of messages, trading through orders and account.
+    pub fn new<St>(
+        strategy: S,
+        source: St,
+        orders: Arc<dyn OrderEntry>,
+        account: Arc<dyn Account>,
+    ) -> Self
+    where
+        St: Stream<Item = Result<KucoinWebsocketMsg, APIError>> + Send + 'static,
+    {
+        StrategyRuntime {
+            strategy,
+            source: source.boxed(),
+            ctx: StrategyContext::new(orders, account),
+            paper: None,
+            timer: None,
+        }
+    }
+
+    /// Trades on Kucoin, subscribing the strategy's topics and the account's order changes.
+    pub async fn live(api: &Kucoin, strategy: S) -> Result<Self, APIError> {
+        let mut ws = api.websocket();
+        let mut topics = strategy.topics();
+        topics.push(WSTopic::TradeOrders);
+        ws.subscribe_topics(topics).await?;
+        let api = Arc::new(api.clone());
+        Ok(StrategyRuntime::new(strategy, ws, api.clone(), api))
+    }
+
+    /// Trades on the PaperExchange, fed with the strategy's topics subscribed on Kucoin.
+    pub async fn paper(api: &Kucoin, paper: PaperExchange, strategy: S) -> Result<Self, APIError> {
+        let mut ws = api.websocket();
+        ws.subscribe_topics(strategy.topics()).await?;
+        Ok(StrategyRuntime::with_paper(strategy, ws, paper))
+    }
+
+    /// Trades on the PaperExchange, fed with the recording. The runtime stops at its end.
+    pub fn replay(replay: Replay, paper: PaperExchange, strategy: S) -> Self {
+        StrategyRuntime::with_paper(strategy, replay, paper)
+    }
+
+    /// Trades on the PaperExchange, which is fed each message of the source before the
+    /// strategy sees it. Its order changes are passed to on_order_update.
+    pub fn with_paper<St>(strategy: S, source: St, paper: PaperExchange) -> Self
+    where
+        St: Stream<Item = Result<KucoinWebsocketMsg, APIError>> + Send + 'static,
+    {
+        let events = paper.events();
+        let exchange = Arc::new(paper.clone());
+        let mut runtime = StrategyRuntime::new(strategy, source, exchange.clone(), exchange);
+        runtime.paper = Some((paper, events));
+        runtime
+    }
+
+    /// Calls on_timer at the interval.
+    pub fn timer(mut self, interval: Duration) -> Self {
+        self.timer = Some(interval);
+        self
+    }
+
+    /// Runs the strategy on a task until it stops, see RuntimeHandle.
+    pub fn start(self) -> RuntimeHandle<S> {
+        let (stop_tx, stop_rx) = oneshot::channel();
+        let task = tokio::spawn(self.run_until(stop_rx));
+        RuntimeHandle {
+            stop: Some(stop_tx),
+            task,
+        }
+    }
+
+    /// Runs the strategy until its source ends or it calls StrategyContext::stop, returning it
+    /// after on_stop.
+    pub async fn run(self) -> Result<S, APIError> {
+        let (_stop_tx, stop_rx) = oneshot::channel();
+        self.run_until(stop_rx).await
+    }
+
+    async fn run_until(mut self, mut stop_rx: oneshot::Receiver<()>) -> Result<S, APIError> {
+        let ctx = self.ctx.clone();
+        let mut result = self.strategy.on_start(&ctx).await;
+        let mut timer = self.timer.map(|interval| {
+            let start = tokio::time::Instant::now() + interval;
+            tokio::time::interval_at(start, interval)
+        });
+        while result.is_ok() && !ctx.is_stopping() {
+            let tick = async {
+                match timer.as_mut() {
+                    Some(timer) => {
+                        timer.tick().await;
+                    }
+                    None => future::pending::<()>().await,
+                }
+            };
+            result = tokio::select! {
+                msg = self.source.next() => match msg {
+                    Some(Ok(msg)) => self.on_message(&msg).await,
+                    Some(Err(e @ APIError::Websocket(_)))
+                    | Some(Err(e @ APIError::Disconnected(_))) => Err(e),
+                    Some(Err(_)) => Ok(()),
+                    None => break,
+                },
+                _ = tick => self.strategy.on_timer(&ctx).await,
+                _ = &mut stop_rx => break,
+            };
+            if result.is_ok() {
+                result = self.on_paper_events().await;
+            }
+        }
+        let stopped = self.strategy.on_stop(&ctx).await;
+        result.and(stopped).map(|_| self.strategy)
+    }
+
+    async fn on_message(&mut self, msg: &KucoinWebsocketMsg) -> Result<(), APIError> {
+        if let Some((paper, _)) = &self.paper {
+            paper.on_message(msg);
+        }
+        let ctx = &self.ctx;
+        match msg {
+            KucoinWebsocketMsg::TickerMsg(r) => {
+                let symbol = r.topic.rsplit(':').next().unwrap_or_default();
+                self.strategy.on_ticker(ctx, symbol, &r.data).await
+            }
+            KucoinWebsocketMsg::AllTickerMsg(r) => {
+                self.strategy.on_ticker(ctx, &r.subject, &r.data).await
+            }
+            KucoinWebsocketMsg::MatchMsg(r) => self.strategy.on_trade(ctx, &r.data).await,
+            KucoinWebsocketMsg::OrderBookMsg(r) => {
+                let book = BookUpdate::Changes(&r.data);
+                self.strategy.on_book(ctx, book).await
+            }
+            KucoinWebsocketMsg::OrderBookDepthMsg(r) => {
+                let book = BookUpdate::Depth {
+                    symbol: r.topic.rsplit(':').next().unwrap_or_default(),
+                    depth: &r.data,
+                };
+                self.strategy.on_book(ctx, book).await
+            }
+            msg => match OrderUpdate::from_message(msg) {
+                Some(update) => self.strategy.on_order_update(ctx, &update).await,
+                None => Ok(()),
+            },
+        }
+    }
+
+    // Passes on the order changes the paper exchange made while handling the last message.
+    async fn on_paper_events(&mut self) -> Result<(), APIError> {
+        loop {
+            let msg = match &mut self.paper {
+                Some((_, events)) => match events.try_next() {
+                    Ok(Some(msg)) => msg,
+                    _ => return Ok(()),
+                },
+                None => return Ok(()),
+            };
+            if let Some(update) = OrderUpdate::from_message(&msg) {
+                self.strategy.on_order_update(&self.ctx, &update).await?;
+            }
+        }
+    }
+}
+
+/// Handle of a StrategyRuntime started on a task.
+pub struct RuntimeHandle<S> {
+    stop: Option<oneshot::Sender<()>>,
+    task: JoinHandle<Result<S, APIError>>,
+}
+
+impl<S> RuntimeHandle<S> {
+    /// Stops the runtime once the hook in progress returns, calls on_stop and returns the
+    /// strategy, or the error that stopped it.
+    pub async fn stop(mut self) -> Result<S, APIError> {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+        self.join().await
+    }
+
+    /// Waits for the runtime to stop on its own.
+    pub async fn join(self) -> Result<S, APIError> {
+        self.task
+            .await
+            .map_err(|e| APIError::Other(format!("Strategy runtime failed: {}", e)))?
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::kucoin::error::APIError;
+    use crate::kucoin::exchange::{OrderRequest, Side};
+    use crate::kucoin::model::websocket::{KucoinWebsocketMsg, Match, SymbolTicker, WSTopic};
+    use crate::kucoin::paper::PaperExchange;
+    use crate::kucoin::strategy::{OrderUpdate, Strategy, StrategyContext, StrategyRuntime};
+    use crate::kucoin::websocket::parse_message;
+    use async_trait::async_trait;
+    use futures::stream;
+    use std::time::Duration;
+    use tokio_tungstenite::tungstenite::Message;
+
+    #[derive(Default)]
+    struct BuyTheBid {
+        tickers: usize,
+        trades: usize,
+        updates: Vec<String>,
+        timers: usize,
+        stopped: bool,
+    }
+
+    #[async_trait]
+    impl Strategy for BuyTheBid {
+        fn topics(&self) -> Vec<WSTopic> {
+            vec![
+                WSTopic::Ticker(vec!["BTC-USDT".to_string()]),
+                WSTopic::Match(vec!["BTC-USDT".to_string()]),
+            ]
+        }
+
+        async fn on_ticker(
+            &mut self,
+            ctx: &StrategyContext,
+            symbol: &str,
+            ticker: &SymbolTicker,
+        ) -> Result<(), APIError> {
+            self.tickers += 1;
+            if self.tickers == 1 {
+                let order = OrderRequest::limit("1", symbol, Side::Buy, &ticker.best_bid, "1");
+                ctx.orders().place_order(&order).await?;
+            }
+            Ok(())
+        }
+
+        async fn on_trade(&mut self, _: &StrategyContext, _: &Match) -> Result<(), APIError> {
+            self.trades += 1;
+            Ok(())
+        }
+
+        async fn on_order_update(
+            &mut self,
+            ctx: &StrategyContext,
+            update: &OrderUpdate,
+        ) -> Result<(), APIError> {
+            let kind = match update {
+                OrderUpdate::Open(_) => "open",
+                OrderUpdate::Match(_) => "match",
+                OrderUpdate::Filled(_) => "filled",
+                OrderUpdate::Canceled(_) => "canceled",
+                OrderUpdate::Update(_) => "update",
+            };
+            self.updates.push(kind.to_string());
+            if kind == "filled" {
+                ctx.stop();
+            }
+            Ok(())
+        }
+
+        async fn on_timer(&mut self, _: &StrategyContext) -> Result<(), APIError> {
+            self.timers += 1;
+            Ok(())
+        }
+
+        async fn on_stop(&mut self, _: &StrategyContext) -> Result<(), APIError> {
+            self.stopped = true;
+            Ok(())
+        }
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn msg(frame: &str) -> Result<KucoinWebsocketMsg, APIError> {
+        parse_message(Message::Text(frame.to_string()))
+    }
+
+    #[tokio::test]
+    async fn replayed_market_data_drives_paper_orders() {
+        let paper = PaperExchange::default();
+        paper.deposit("USDT", 1000.0);
+        let ticker = r#"{"type":"message","topic":"/market/ticker:BTC-USDT","subject":"trade.ticker","data":{"sequence":"1545896668986","price":"100.5","size":"0.1","bestAsk":"101","bestAskSize":"1","bestBid":"100","bestBidSize":"1"}}"#;
+        let trade = r#"{"type":"message","topic":"/market/match:BTC-USDT","subject":"trade.l3match","data":{"sequence":"1","type":"match","symbol":"BTC-USDT","side":"sell","price":"99","size":"5","tradeId":"1","takerOrderId":"2","makerOrderId":"3","time":"1545913818099033203"}}"#;
+        let source = stream::iter(vec![msg(ticker), msg("not json"), msg(trade), msg(ticker)]);
+        let strategy = StrategyRuntime::with_paper(BuyTheBid::default(), source, paper.clone())
+            .run()
+            .await
+            .unwrap();
+
+        assert_eq!(strategy.tickers, 1);
+        assert_eq!(strategy.trades, 1);
+        assert_eq!(strategy.updates, vec!["open", "match", "filled"]);
+        assert!(strategy.stopped);
+    }
+
+    #[tokio::test]
+    async fn started_runtime_stops_gracefully() {
+        tokio::time::pause();
+        let paper = PaperExchange::default();
+        let handle = StrategyRuntime::with_paper(BuyTheBid::default(), stream::pending(), paper)
+            .timer(Duration::from_millis(10))
+            .start();
+        // Time is paused, so it jumps from one tick to the next while the runtime is idle
+        tokio::time::sleep(Duration::from_millis(35)).await;
+        let strategy = handle.stop().await.unwrap();
+        assert_eq!(strategy.timers, 3);
+        assert!(strategy.stopped);
+    }
+
+    #[tokio::test]
+    async fn dropped_connection_stops_runtime() {
+        let source = stream::iter(vec![
+            msg("not json"),
+            Err(APIError::Disconnected(vec![
+                "/market/ticker:BTC-USDT".to_string()
+            ])),
+        ]);
+        let runtime =
+            StrategyRuntime::with_paper(BuyTheBid::default(), source, PaperExchange::default());
+        assert!(matches!(
+            runtime.run().await,
+            Err(APIError::Disconnected(_))
+        ));
+    }
+}
@@ -1,13 +1,40 @@
+use futures::stream::{self, StreamExt};
 use reqwest::header;
 use std::collections::HashMap;
+use std::time::Duration;
 
 use super::client::Kucoin;
 use super::error::APIError;
 use super::model::trade::{
-    CancelByClientOidResp, CancelResp, FillsInfo, HistoricalOrder, OrderInfo, OrderResp,
+    AmendResp, CancelByClientOidResp, CancelReport, CancelResp, CancelStatus, FillsInfo,
+    HistoricalOrder, OcoOrder, OcoOrderDetails, OrderInfo, OrderResp,
 };
 use super::model::{APIData, APIDatum, Method, Pagination};
-use super::utils::format_query;
+use super::utils::{format_query, get_time, sub_decimal_str};
+
+// Polling used to confirm an order has left the book after a cancel.
+const CANCEL_CONFIRM_ATTEMPTS: u32 = 10;
+const CANCEL_CONFIRM_INTERVAL: Duration = Duration::from_millis(200);
+// Spacing between bulk cancel requests, keeping well under the cancel order rate limit.
+const BULK_CANCEL_INTERVAL: Duration = Duration::from_millis(60);
+// Kucoin error code of rejected requests, including cancels of orders that are done.
+const INVALID_REQUEST: &str = "400100";
+
+// Whether a rejected cancel was for an order that no longer exists or can't be cancelled,
+// as opposed to a bad id or any other invalid request sharing the same code.
+fn order_not_cancellable(code: &str, msg: Option<&str>) -> bool {
+    let msg = msg.unwrap_or_default().to_lowercase().replace('_', " ");
+    code == INVALID_REQUEST
+        && (msg.contains("not exist")
+            || msg.contains("not allow to cancel")
+            || msg.contains("cannot be cancel")
+            || msg.contains("can not be cancel"))
+}
+
+// Whether get_order rejected the id as not existing, as it does for orders of the HF account.
+fn order_not_found(code: &str, msg: Option<&str>) -> bool {
+    code == INVALID_REQUEST && msg.unwrap_or_default().to_lowercase().contains("not exist")
+}
 
 impl Kucoin {
     /// Places a limit order. Takes required inputs directly and a Some<OrderOptionals> type, or None for
@@ -57,6 +84,30 @@ impl Kucoin {
         size: Option<f32>,
         funds: Option<f32>,
         optionals: Option<OrderOptionals<'_>>,
+    ) -> Result<APIDatum<OrderResp>, APIError> {
+        let size = size.map(|s| s.to_string());
+        let funds = funds.map(|f| f.to_string());
+        self.post_market_order_str(
+            client_oid,
+            symbol,
+            side,
+            size.as_deref(),
+            funds.as_deref(),
+            optionals,
+        )
+        .await
+    }
+
+    /// Places a market order like post_market_order, with the size or funds as decimal strings
+    /// sent as given.
+    pub async fn post_market_order_str(
+        &self,
+        client_oid: &str,
+        symbol: &str,
+        side: &str,
+        size: Option<&str>,
+        funds: Option<&str>,
+        optionals: Option<OrderOptionals<'_>>,
     ) -> Result<APIDatum<OrderResp>, APIError> {
         let endpoint = String::from("/api/v1/orders");
         let url = format!("{}{}", &self.prefix, endpoint);
@@ -143,6 +194,75 @@ impl Kucoin {
         Ok(resp)
     }
 
+    /// Cancels the active orders of a symbol (optional) that match every criteria set on the CancelFilter,
+    /// see CancelFilter for build pattern usage. Up to max_concurrent cancels are in flight at once and
+    /// requests are spaced to stay within rate limits. Returns a report for each selected order stating
+    /// whether it was cancelled, had already been filled or cancelled, or failed.
+    pub async fn cancel_orders_filtered(
+        &self,
+        symbol: Option<&str>,
+        filter: CancelFilter<'_>,
+        max_concurrent: usize,
+    ) -> Result<Vec<CancelReport>, APIError> {
+        let now = get_time() as i64;
+        let mut selected: Vec<OrderInfo> = Vec::new();
+        let mut page = 1;
+        loop {
+            let mut opts = OrderInfoOptionals::new();
+            opts.status("active").current_page(page).page_size(500);
+            if let Some(s) = symbol {
+                opts.symbol(s);
+            }
+            if let Some(s) = filter.side {
+                opts.side(s);
+            }
+            let resp = self.get_orders(Some(opts.build())).await?;
+            let orders = match resp.data {
+                Some(d) => d,
+                None => {
+                    return Err(APIError::Other(format!(
+                        "Failed listing active orders, code: {}, msg: {:?}",
+                        resp.code, resp.msg
+                    )))
+                }
+            };
+            let total_page = orders.total_page;
+            selected.extend(orders.items.into_iter().filter(|o| filter.matches(o, now)));
+            if page >= total_page {
+                break;
+            }
+            page += 1;
+        }
+        // Each cancel is due at a fixed offset from the same start, however long the previous
+        // ones took.
+        let start = tokio::time::Instant::now();
+        let reports = stream::iter(selected.into_iter().enumerate())
+            .map(|(i, order)| async move {
+                tokio::time::sleep_until(start + BULK_CANCEL_INTERVAL * i as u32).await;
+                let status = match self.cancel_order(&order.id).await {
+                    Ok(r) => match r.data {
+                        Some(c) if c.cancelled_order_ids.contains(&order.id) => {
+                            CancelStatus::Cancelled
+                        }
+                        _ if order_not_cancellable(&r.code, r.msg.as_deref()) => {
+                            CancelStatus::AlreadyDone
+                        }
+                        _ => CancelStatus::Failed(format!("code: {}, msg: {:?}", r.code, r.msg)),
+                    },
+                    Err(e) => CancelStatus::Failed(e.to_string()),
+                };
+                CancelReport {
+                    order_id: order.id,
+                    client_oid: order.client_oid,
+                    status,
+                }
+            })
+            .buffer_unordered(max_concurrent.max(1))
+            .collect()
+            .await;
+        Ok(reports)
+    }
+
     // Consider list orders
     pub async fn get_orders(
         &self,
@@ -326,9 +446,305 @@ impl Kucoin {
         let resp = self.get(url, Some(headers)).await?.json().await?;
         Ok(resp)
     }
+
+    /// Cancels and replaces a limit order at a new price and size. The cancel is verified before anything
+    /// is resubmitted: the cancel response has to list the order and the order has to report as no longer
+    /// active, otherwise an error is returned and no new order is placed.
+    ///
+    /// Size is the total size of the amended order, the quantity already filled on the old order is deducted
+    /// from it before resubmitting. When optionals is None the time in force and flags of the old order are reused.
+    ///
+    /// An order get_order reports as not existing, as it does for orders of the HF account, is amended with
+    /// amend_hf_order through the exchange's atomic modify endpoint instead, keeping its client_oid and flags
+    /// and ignoring optionals. Any other failure to get the order is returned as an error.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn amend_order(
+        &self,
+        order_id: &str,
+        client_oid: &str,
+        symbol: &str,
+        price: &str,
+        size: &str,
+        optionals: Option<OrderOptionals<'_>>,
+    ) -> Result<AmendResp, APIError> {
+        let resp = self.get_order(order_id).await?;
+        let order = match resp.data {
+            Some(o) => o,
+            None if order_not_found(&resp.code, resp.msg.as_deref()) => {
+                return self.amend_hf_order(order_id, symbol, price, size).await
+            }
+            None => {
+                return Err(APIError::Other(format!(
+                    "Failed getting order {}, code: {}, msg: {:?}",
+                    order_id, resp.code, resp.msg
+                )))
+            }
+        };
+        if order.is_active == Some(false) {
+            return Err(APIError::Other(format!(
+                "Order {} is no longer active",
+                order_id
+            )));
+        }
+        let cancel = self.cancel_order(order_id).await?;
+        let cancelled = match &cancel.data {
+            Some(c) => c.cancelled_order_ids.iter().any(|id| id == order_id),
+            None => false,
+        };
+        if !cancelled {
+            return Err(APIError::Other(format!(
+                "Cancel of order {} was not confirmed, code: {}, msg: {:?}",
+                order_id, cancel.code, cancel.msg
+            )));
+        }
+        let old = self.confirm_order_done(order_id).await?;
+        let remaining = sub_decimal_str(size, &old.deal_size);
+        if remaining.parse::<f64>().unwrap_or(0.0) <= 0.0 {
+            return Ok(AmendResp {
+                new_order_id: None,
+                old_filled_size: old.deal_size,
+            });
+        }
+        let opts = match optionals {
+            Some(o) => o,
+            None => inherited_optionals(&old),
+        };
+        let resp = self
+            .post_limit_order(
+                client_oid,
+                &old.symbol,
+                &old.side,
+                price,
+                &remaining,
+                Some(opts),
+            )
+            .await?;
+        match resp.data {
+            Some(r) => Ok(AmendResp {
+                new_order_id: Some(r.order_id),
+                old_filled_size: old.deal_size,
+            }),
+            None => Err(APIError::Other(format!(
+                "Order {} was cancelled but the replacement failed, code: {}, msg: {:?}",
+                order_id, resp.code, resp.msg
+            ))),
+        }
+    }
+
+    // Polls an order until it reports as inactive, returning its final state.
+    async fn confirm_order_done(&self, order_id: &str) -> Result<OrderInfo, APIError> {
+        for _ in 0..CANCEL_CONFIRM_ATTEMPTS {
+            if let Some(o) = self.get_order(order_id).await?.data {
+                if o.is_active == Some(false) {
+                    return Ok(o);
+                }
+            }
+            tokio::time::sleep(CANCEL_CONFIRM_INTERVAL).await;
+        }
+        Err(APIError::Other(format!(
+            "Order {} still active after cancel",
+            order_id
+        )))
+    }
+
+    /// Places an OCO (one-cancels-the-other) order, pairing a limit order at price with a stop-limit
+    /// order triggered at stop_price and placed at limit_price. When either leg fills the other is cancelled.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn post_oco_order(
+        &self,
+        client_oid: &str,
+        symbol: &str,
+        side: &str,
+        price: &str,
+        size: &str,
+        stop_price: &str,
+        limit_price: &str,
+        remark: Option<&str>,
+    ) -> Result<APIDatum<OrderResp>, APIError> {
+        let endpoint = String::from("/api/v3/oco/order");
+        let url = format!("{}{}", &self.prefix, endpoint);
+        let mut params: HashMap<String, String> = HashMap::new();
+        params.insert(String::from("clientOid"), client_oid.to_string());
+        params.insert(String::from("symbol"), symbol.to_string());
+        params.insert(String::from("side"), side.to_string());
+        params.insert(String::from("price"), price.to_string());
+        params.insert(String::from("size"), size.to_string());
+        params.insert(String::from("stopPrice"), stop_price.to_string());
+        params.insert(String::from("limitPrice"), limit_price.to_string());
+        params.insert(String::from("tradeType"), String::from("TRADE"));
+        if let Some(r) = remark {
+            params.insert(String::from("remark"), r.to_string());
+        };
+        let headers: header::HeaderMap = self
+            .sign_headers(endpoint, Some(&params), None, Method::POST)
+            .unwrap();
+        let resp = self
+            .post(url, Some(headers), Some(params))
+            .await?
+            .json()
+            .await?;
+        Ok(resp)
+    }
+
+    /// Cancels both legs of an OCO order based on the provided order id (required).
+    pub async fn cancel_oco_order(&self, order_id: &str) -> Result<APIDatum<CancelResp>, APIError> {
+        let endpoint = format!("/api/v3/oco/order/{}", order_id);
+        let url = format!("{}{}", &self.prefix, endpoint);
+        let headers: header::HeaderMap = self
+            .sign_headers(endpoint, None, None, Method::DELETE)
+            .unwrap();
+        let resp = self.delete(url, Some(headers)).await?.json().await?;
+        Ok(resp)
+    }
+
+    /// Cancels both legs of an OCO order based on the provided client oid (required).
+    pub async fn cancel_oco_order_by_client_oid(
+        &self,
+        client_oid: &str,
+    ) -> Result<APIDatum<CancelResp>, APIError> {
+        let endpoint = format!("/api/v3/oco/client-order/{}", client_oid);
+        let url = format!("{}{}", &self.prefix, endpoint);
+        let headers: header::HeaderMap = self
+            .sign_headers(endpoint, None, None, Method::DELETE)
+            .unwrap();
+        let resp = self.delete(url, Some(headers)).await?.json().await?;
+        Ok(resp)
+    }
+
+    /// Cancels OCO orders by a comma delimited list of order ids (optional) and/or symbol (optional).
+    /// With neither provided all open OCO orders are cancelled.
+    pub async fn cancel_oco_orders(
+        &self,
+        order_ids: Option<&str>,
+        symbol: Option<&str>,
+    ) -> Result<APIDatum<CancelResp>, APIError> {
+        let endpoint = String::from("/api/v3/oco/orders");
+        let url: String;
+        let headers: header::HeaderMap;
+        let mut params: HashMap<String, String> = HashMap::new();
+        if let Some(o) = order_ids {
+            params.insert(String::from("orderIds"), o.to_string());
+        };
+        if let Some(s) = symbol {
+            params.insert(String::from("symbol"), s.to_string());
+        };
+        if !params.is_empty() {
+            let query = format_query(&params);
+            url = format!("{}{}{}", &self.prefix, endpoint, query);
+            headers = self
+                .sign_headers(endpoint, None, Some(query), Method::DELETE)
+                .unwrap();
+        } else {
+            url = format!("{}{}", &self.prefix, endpoint);
+            headers = self
+                .sign_headers(endpoint, None, None, Method::DELETE)
+                .unwrap();
+        };
+        let resp = self.delete(url, Some(headers)).await?.json().await?;
+        Ok(resp)
+    }
+
+    pub async fn get_oco_order(&self, order_id: &str) -> Result<APIDatum<OcoOrder>, APIError> {
+        let endpoint = format!("/api/v3/oco/order/{}", order_id);
+        let url = format!("{}{}", &self.prefix, endpoint);
+        let headers: header::HeaderMap = self
+            .sign_headers(endpoint, None, None, Method::GET)
+            .unwrap();
+        let resp = self.get(url, Some(headers)).await?.json().await?;
+        Ok(resp)
+    }
+
+    pub async fn get_oco_order_by_client_oid(
+        &self,
+        client_oid: &str,
+    ) -> Result<APIDatum<OcoOrder>, APIError> {
+        let endpoint = format!("/api/v3/oco/client-order/{}", client_oid);
+        let url = format!("{}{}", &self.prefix, endpoint);
+        let headers: header::HeaderMap = self
+            .sign_headers(endpoint, None, None, Method::GET)
+            .unwrap();
+        let resp = self.get(url, Some(headers)).await?.json().await?;
+        Ok(resp)
+    }
+
+    /// Returns an OCO order along with the details of both of its legs.
+    pub async fn get_oco_order_details(
+        &self,
+        order_id: &str,
+    ) -> Result<APIDatum<OcoOrderDetails>, APIError> {
+        let endpoint = format!("/api/v3/oco/order/details/{}", order_id);
+        let url = format!("{}{}", &self.prefix, endpoint);
+        let headers: header::HeaderMap = self
+            .sign_headers(endpoint, None, None, Method::GET)
+            .unwrap();
+        let resp = self.get(url, Some(headers)).await?.json().await?;
+        Ok(resp)
+    }
+
+    pub async fn get_oco_orders(
+        &self,
+        optionals: Option<OcoOrdersOptionals<'_>>,
+    ) -> Result<APIDatum<Pagination<OcoOrder>>, APIError> {
+        let endpoint = String::from("/api/v3/oco/orders");
+        let url: String;
+        let headers: header::HeaderMap;
+        let mut params: HashMap<String, String> = HashMap::new();
+        if let Some(opts) = optionals {
+            if let Some(o) = opts.symbol {
+                params.insert("symbol".to_string(), o.to_string());
+            };
+            if let Some(o) = opts.order_ids {
+                params.insert("orderIds".to_string(), o.to_string());
+            };
+            if let Some(o) = opts.start_at {
+                params.insert("startAt".to_string(), o.to_string());
+            };
+            if let Some(o) = opts.end_at {
+                params.insert("endAt".to_string(), o.to_string());
+            };
+            if let Some(o) = opts.current_page {
+                params.insert("currentPage".to_string(), o.to_string());
+            };
+            if let Some(o) = opts.page_size {
+                params.insert("pageSize".to_string(), o.to_string());
+            };
+        };
+        if !params.is_empty() {
+            let query = format_query(&params);
+            url = format!("{}{}{}", &self.prefix, endpoint, query);
+            headers = self
+                .sign_headers(endpoint, None, Some(query), Method::GET)
+                .unwrap();
+        } else {
+            url = format!("{}{}", &self.prefix, endpoint);
+            headers = self
+                .sign_headers(endpoint, None, None, Method::GET)
+                .unwrap();
+        };
+        let resp = self.get(url, Some(headers)).await?.json().await?;
+        Ok(resp)
+    }
 }
 
-fn parse_order(optionals: OrderOptionals) -> HashMap<String, String> {
+fn inherited_optionals(order: &OrderInfo) -> OrderOptionals<'_> {
+    let mut opts = OrderOptionals::new();
+    opts.time_in_force(&order.time_in_force)
+        .post_only(order.post_only)
+        .hidden(order.hidden)
+        .iceberg(order.iceberg);
+    if order.iceberg {
+        opts.visible_size(&order.visible_size);
+    }
+    if !order.stp.is_empty() {
+        opts.stp(&order.stp);
+    }
+    if order.time_in_force == "GTT" && order.cancel_after > 0 {
+        opts.cancel_after(order.cancel_after);
+    }
+    opts.build()
+}
+
+pub(crate) fn parse_order(optionals: OrderOptionals) -> HashMap<String, String> {
     let mut params: HashMap<String, String> = HashMap::new();
 
     if let Some(o) = optionals.remark {
@@ -695,9 +1111,196 @@ impl<'a> FillsOptionals<'a> {
     }
 }
 
+/// CancelFilter contains a builder pattern used to select open orders for cancel_orders_filtered.
+/// Every criteria that is set has to match for an order to be cancelled, an empty filter selects all open orders.
+///
+/// Example:
+/// ``` rust
+/// use kucoin_rs::kucoin::trade::CancelFilter;
+///
+///     let filter = CancelFilter::new()
+///         .side("buy")
+///         .min_price(9500.0)
+///         .older_than(60_000)
+///         .client_oid_prefix("grid-")
+///         .build();
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CancelFilter<'a> {
+    pub side: Option<&'a str>,
+    pub min_price: Option<f64>,
+    pub max_price: Option<f64>,
+    pub older_than: Option<i64>,
+    pub client_oid_prefix: Option<&'a str>,
+    pub tag: Option<&'a str>,
+    pub remark: Option<&'a str>,
+}
+
+impl<'a> CancelFilter<'a> {
+    pub fn new() -> Self {
+        CancelFilter {
+            side: None,
+            min_price: None,
+            max_price: None,
+            older_than: None,
+            client_oid_prefix: None,
+            tag: None,
+            remark: None,
+        }
+    }
+
+    pub fn side(&mut self, s: &'a str) -> &mut Self {
+        self.side = Some(s);
+        self
+    }
+
+    /// Inclusive lower bound on the order price.
+    pub fn min_price(&mut self, p: f64) -> &mut Self {
+        self.min_price = Some(p);
+        self
+    }
+
+    /// Inclusive upper bound on the order price.
+    pub fn max_price(&mut self, p: f64) -> &mut Self {
+        self.max_price = Some(p);
+        self
+    }
+
+    /// Minimum age of the order in milliseconds.
+    pub fn older_than(&mut self, ms: i64) -> &mut Self {
+        self.older_than = Some(ms);
+        self
+    }
+
+    pub fn client_oid_prefix(&mut self, p: &'a str) -> &mut Self {
+        self.client_oid_prefix = Some(p);
+        self
+    }
+
+    pub fn tag(&mut self, t: &'a str) -> &mut Self {
+        self.tag = Some(t);
+        self
+    }
+
+    pub fn remark(&mut self, r: &'a str) -> &mut Self {
+        self.remark = Some(r);
+        self
+    }
+
+    pub fn build(&self) -> Self {
+        self.clone()
+    }
+
+    /// Whether an order satisfies the filter, with now being the current time in milliseconds.
+    pub fn matches(&self, order: &OrderInfo, now: i64) -> bool {
+        let price = order.price.parse::<f64>().unwrap_or(0.0);
+        self.side.map_or(true, |s| order.side == s)
+            && self.min_price.map_or(true, |p| price >= p)
+            && self.max_price.map_or(true, |p| price <= p)
+            && self
+                .older_than
+                .map_or(true, |ms| now - order.created_at >= ms)
+            && self
+                .client_oid_prefix
+                .map_or(true, |p| order.client_oid.starts_with(p))
+            && self.tag.map_or(true, |t| order.tags.as_deref() == Some(t))
+            && self
+                .remark
+                .map_or(true, |r| order.remark.as_deref() == Some(r))
+    }
+}
+
+/// OcoOrdersOptionals contains a builder pattern that can be used to more easily take advantage of optional inputs.
+///
+/// Example:
+/// ``` rust
+/// use kucoin_rs::kucoin::trade::OcoOrdersOptionals;
+///
+///     let options = OcoOrdersOptionals::new()
+///         .symbol("BTC-USDT")
+///         .page_size(20)
+///         .build();
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct OcoOrdersOptionals<'a> {
+    pub symbol: Option<&'a str>,
+    pub order_ids: Option<&'a str>,
+    pub start_at: Option<i64>,
+    pub end_at: Option<i64>,
+    pub current_page: Option<i32>,
+    pub page_size: Option<i32>,
+}
+
+impl<'a> OcoOrdersOptionals<'a> {
+    pub fn new() -> Self {
+        OcoOrdersOptionals {
+            symbol: None,
+            order_ids: None,
+            start_at: None,
+            end_at: None,
+            current_page: None,
+            page_size: None,
+        }
+    }
+
+    pub fn symbol(&mut self, s: &'a str) -> &mut Self {
+        self.symbol = Some(s);
+        self
+    }
+
+    /// Comma delimited list of order ids.
+    pub fn order_ids(&mut self, s: &'a str) -> &mut Self {
+        self.order_ids = Some(s);
+        self
+    }
+
+    pub fn start_at(&mut self, i: i64) -> &mut Self {
+        self.start_at = Some(i);
+        self
+    }
+
+    pub fn end_at(&mut self, i: i64) -> &mut Self {
+        self.end_at = Some(i);
+        self
+    }
+
+    pub fn current_page(&mut self, i: i32) -> &mut Self {
+        self.current_page = Some(i);
+        self
+    }
+
+    pub fn page_size(&mut self, i: i32) -> &mut Self {
+        self.page_size = Some(i);
+        self
+    }
+
+    /// Builds an OcoOrdersOptionals Type from chained optional funtions
+    /// to be used with getting lists of OCO orders.
+    pub fn build(&self) -> Self {
+        OcoOrdersOptionals {
+            symbol: self.symbol,
+            order_ids: self.order_ids,
+            start_at: self.start_at,
+            end_at: self.end_at,
+            current_page: self.current_page,
+            page_size: self.page_size,
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use crate::kucoin::trade::{FillsOptionals, OrderInfoOptionals, OrderOptionals};
+    use crate::kucoin::client::{Kucoin, KucoinEnv};
+    use crate::kucoin::fake::fake_rest;
+    use crate::kucoin::model::trade::OrderInfo;
+    use crate::kucoin::trade::{
+        order_not_cancellable, order_not_found, CancelFilter, FillsOptionals, OcoOrdersOptionals,
+        OrderInfoOptionals, OrderOptionals,
+    };
+    use futures::channel::mpsc::UnboundedReceiver;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
     #[test]
     fn use_build_pattern_all_order_optionals() {
         let options = OrderOptionals {
@@ -862,4 +1465,222 @@ mod test {
 
         assert_eq!(options, build_options)
     }
+
+    #[test]
+    fn use_build_pattern_some_oco_orders_optionals() {
+        let options = OcoOrdersOptionals {
+            symbol: Some("BTC-USDT"),
+            order_ids: None,
+            start_at: Some(1_580_683_419_725),
+            end_at: None,
+            current_page: None,
+            page_size: Some(20),
+        };
+
+        let build_options = OcoOrdersOptionals::new()
+            .symbol("BTC-USDT")
+            .start_at(1_580_683_419_725)
+            .page_size(20)
+            .build();
+
+        assert_eq!(options, build_options)
+    }
+
+    #[test]
+    fn cancel_filter_matches_all_criteria() {
+        let order: OrderInfo = serde_json::from_str(
+            r#"{"id":"5c35c02703aa673ceec2a168","symbol":"BTC-USDT","opType":"DEAL","type":"limit","side":"buy","price":"10000","size":"0.001","funds":"0","dealFunds":"0","dealSize":"0","fee":"0","feeCurrency":"USDT","stp":"","stop":"","stopTriggered":false,"stopPrice":"0","timeInForce":"GTC","postOnly":false,"hidden":false,"iceberg":false,"visibleSize":"0","cancelAfter":0,"channel":"API","clientOid":"grid-42","remark":null,"tags":"grid","isActive":true,"cancelExist":false,"createdAt":1547026471000,"tradeType":"TRADE"}"#,
+        )
+        .unwrap();
+        let now = 1_547_026_531_000;
+
+        let filter = CancelFilter::new()
+            .side("buy")
+            .min_price(9500.0)
+            .max_price(10000.0)
+            .older_than(60_000)
+            .client_oid_prefix("grid-")
+            .tag("grid")
+            .build();
+        assert!(filter.matches(&order, now));
+
+        assert!(!CancelFilter::new()
+            .side("sell")
+            .build()
+            .matches(&order, now));
+        assert!(!CancelFilter::new()
+            .max_price(9999.0)
+            .build()
+            .matches(&order, now));
+        assert!(!CancelFilter::new()
+            .older_than(60_001)
+            .build()
+            .matches(&order, now));
+        assert!(!CancelFilter::new()
+            .remark("grid")
+            .build()
+            .matches(&order, now));
+    }
+
+    #[test]
+    fn only_done_orders_rejections_are_not_cancellable() {
+        let done = Some("order_not_exist_or_not_allow_to_cancel");
+        assert!(order_not_cancellable("400100", done));
+        assert!(order_not_cancellable(
+            "400100",
+            Some("The order cannot be canceled.")
+        ));
+        assert!(!order_not_cancellable("400100", Some("orderId is invalid")));
+        assert!(!order_not_cancellable("400100", None));
+        assert!(!order_not_cancellable("429000", done));
+        assert!(order_not_found("400100", Some("order not exist.")));
+        assert!(!order_not_found("429000", Some("Too Many Requests")));
+    }
+
+    const OK: &str = "200000";
+
+    // Requests the fake API has answered so far.
+    fn received(mut requests: UnboundedReceiver<String>) -> Vec<String> {
+        let mut received = Vec::new();
+        while let Ok(Some(req)) = requests.try_next() {
+            received.push(req);
+        }
+        received
+    }
+
+    fn order_json(active: bool, deal_size: &str) -> String {
+        format!(
+            r#"{{"code":"{}","data":{{"id":"o1","symbol":"BTC-USDT","opType":"DEAL","type":"limit","side":"buy","price":"10000","size":"1","funds":"0","dealFunds":"0","dealSize":"{}","fee":"0","feeCurrency":"USDT","stp":"","stop":"","stopTriggered":false,"stopPrice":"0","timeInForce":"GTC","postOnly":true,"hidden":false,"iceberg":false,"visibleSize":"0","cancelAfter":0,"channel":"API","clientOid":"quote-1","remark":null,"tags":null,"isActive":{},"cancelExist":false,"createdAt":1547026471000,"tradeType":"TRADE"}}}}"#,
+            OK, deal_size, active
+        )
+    }
+
+    // Client of a fake REST API answering each "METHOD path" with respond, which gets how many
+    // times the same request was made before. The request bodies are kept in order.
+    async fn fake_api<F>(respond: F) -> (Kucoin, UnboundedReceiver<String>, Arc<Mutex<Vec<String>>>)
+    where
+        F: Fn(&str, usize) -> String + Send + 'static,
+    {
+        let bodies = Arc::new(Mutex::new(Vec::new()));
+        let seen = bodies.clone();
+        let counts = Mutex::new(HashMap::new());
+        let (prefix, requests) = fake_rest(move |method, path, body| {
+            let req = format!("{} {}", method, path);
+            seen.lock().unwrap().push(body.to_string());
+            let mut counts = counts.lock().unwrap();
+            let count = counts.entry(req.clone()).or_insert(0);
+            *count += 1;
+            respond(&req, *count - 1)
+        })
+        .await;
+        let mut api = Kucoin::new(KucoinEnv::Live, None).unwrap();
+        api.prefix = prefix;
+        (api, requests, bodies)
+    }
+
+    #[tokio::test]
+    async fn amend_cancels_verifies_and_replaces_the_unfilled_size() {
+        let (api, requests, bodies) = fake_api(|req, count| match (req, count) {
+            ("GET /api/v1/orders/o1", 0) => order_json(true, "0"),
+            ("GET /api/v1/orders/o1", _) => order_json(false, "0.4"),
+            ("DELETE /api/v1/orders/o1", _) => {
+                format!(
+                    r#"{{"code":"{}","data":{{"cancelledOrderIds":["o1"]}}}}"#,
+                    OK
+                )
+            }
+            ("POST /api/v1/orders", _) => {
+                format!(r#"{{"code":"{}","data":{{"orderId":"o2"}}}}"#, OK)
+            }
+            _ => r#"{"code":"404000","msg":"Unexpected request"}"#.to_string(),
+        })
+        .await;
+        let resp = api
+            .amend_order("o1", "quote-2", "BTC-USDT", "10010", "1", None)
+            .await
+            .unwrap();
+        assert_eq!(resp.new_order_id.as_deref(), Some("o2"));
+        assert_eq!(resp.old_filled_size, "0.4");
+
+        let requests = received(requests);
+        assert_eq!(
+            requests,
+            vec![
+                "GET /api/v1/orders/o1",
+                "DELETE /api/v1/orders/o1",
+                "GET /api/v1/orders/o1",
+                "POST /api/v1/orders"
+            ]
+        );
+        // The replacement carries the unfilled size and the old order's flags
+        let replace: serde_json::Value =
+            serde_json::from_str(bodies.lock().unwrap().last().unwrap()).unwrap();
+        assert_eq!(replace["size"], "0.6");
+        assert_eq!(replace["price"], "10010");
+        assert_eq!(replace["postOnly"], "true");
+    }
+
+    #[tokio::test]
+    async fn amend_without_confirmed_cancel_places_nothing() {
+        let (api, requests, _) = fake_api(|req, _| match req {
+            "GET /api/v1/orders/o1" => order_json(true, "0"),
+            "DELETE /api/v1/orders/o1" => {
+                format!(r#"{{"code":"{}","data":{{"cancelledOrderIds":[]}}}}"#, OK)
+            }
+            _ => r#"{"code":"404000","msg":"Unexpected request"}"#.to_string(),
+        })
+        .await;
+        assert!(api
+            .amend_order("o1", "quote-2", "BTC-USDT", "10010", "1", None)
+            .await
+            .is_err());
+        let requests = received(requests);
+        assert_eq!(
+            requests,
+            vec!["GET /api/v1/orders/o1", "DELETE /api/v1/orders/o1"]
+        );
+    }
+
+    #[tokio::test]
+    async fn amend_of_hf_order_modifies_it_in_place() {
+        let (api, requests, _) = fake_api(|req, _| match req {
+            "GET /api/v1/orders/h1" => {
+                r#"{"code":"400100","msg":"order not exist."}"#.to_string()
+            }
+            "POST /api/v1/hf/orders/alter" => {
+                format!(r#"{{"code":"{}","data":{{"newOrderId":"h2"}}}}"#, OK)
+            }
+            "GET /api/v1/hf/orders/h1?symbol=BTC-USDT" => {
+                format!(
+                    r#"{{"code":"{}","data":{{"id":"h1","symbol":"BTC-USDT","opType":"DEAL","type":"limit","side":"buy","price":"10000","size":"1","funds":"0","dealSize":"0.25","dealFunds":"2500","fee":"0","feeCurrency":"USDT","stp":null,"timeInForce":"GTC","postOnly":false,"hidden":false,"iceberg":false,"visibleSize":"0","cancelAfter":0,"channel":"API","clientOid":"quote-1","remark":null,"tags":null,"active":false,"inOrderBook":false,"cancelExist":true,"createdAt":1547026471000,"lastUpdatedAt":1547026472000,"tradeType":"TRADE"}}}}"#,
+                    OK
+                )
+            }
+            _ => r#"{"code":"404000","msg":"Unexpected request"}"#.to_string(),
+        })
+        .await;
+        let resp = api
+            .amend_order("h1", "quote-2", "BTC-USDT", "10010", "1", None)
+            .await
+            .unwrap();
+        assert_eq!(resp.new_order_id.as_deref(), Some("h2"));
+        assert_eq!(resp.old_filled_size, "0.25");
+        let requests = received(requests);
+        assert_eq!(requests[1], "POST /api/v1/hf/orders/alter");
+    }
+
+    #[tokio::test]
+    async fn amend_returns_other_failures_to_get_the_order() {
+        let (api, requests, _) =
+            fake_api(|_, _| r#"{"code":"429000","msg":"Too Many Requests"}"#.to_string()).await;
+        match api
+            .amend_order("o1", "quote-2", "BTC-USDT", "10010", "1", None)
+            .await
+        {
+            Err(e) => assert!(e.to_string().contains("429000")),
+            Ok(r) => panic!("Unexpected amend {:?}", r),
+        }
+        let requests = received(requests);
+        assert_eq!(requests, vec!["GET /api/v1/orders/o1"]);
+    }
 }
@@ -4,9 +4,10 @@ use std::collections::HashMap;
 use super::client::Kucoin;
 use super::error::APIError;
 use super::model::user::{
-    AccountHolds, AccountId, AccountInfo, AccountType, Accounts, DepositAddress, DepositList,
-    DepositListV1, OrderId, SingleAccount, SubAccountBalances, TransferableBalance, UserInfo,
-    WithdrawalId, WithdrawalList, WithdrawalListV1, WithdrawalQuotas,
+    AccountHolds, AccountId, AccountInfo, AccountType, Accounts, BaseFee, DepositAddress,
+    DepositList, DepositListV1, OrderId, SingleAccount, SubAccountBalances, TradeFee,
+    TransferableBalance, UserInfo, WithdrawalId, WithdrawalList, WithdrawalListV1,
+    WithdrawalQuotas,
 };
 use super::model::{APIData, APIDatum, Method, Pagination};
 use super::utils::format_query;
@@ -34,6 +35,7 @@ impl Kucoin {
             AccountType::Main => params.insert(String::from("type"), String::from("main")),
             AccountType::Margin => params.insert(String::from("type"), String::from("margin")),
             AccountType::Trade => params.insert(String::from("type"), String::from("trade")),
+            AccountType::TradeHf => params.insert(String::from("type"), String::from("trade_hf")),
         };
         params.insert(String::from("currency"), currency.to_string());
         let header = self
@@ -195,6 +197,7 @@ impl Kucoin {
             AccountType::Main => endpoint.push_str("&type=MAIN"),
             AccountType::Margin => endpoint.push_str("&type=MARGIN"),
             AccountType::Trade => endpoint.push_str("&type=TRADE"),
+            AccountType::TradeHf => endpoint.push_str("&type=TRADE_HF"),
         };
         let url = format!("{}{}", &self.prefix, endpoint);
         let headers = self
@@ -535,4 +538,46 @@ impl Kucoin {
         let api_data = resp.text().await?;
         Ok(api_data)
     }
+
+    /// Base fee rates of the user's level, currency_type is 0 for crypto and 1 for fiat.
+    pub async fn get_base_fee(
+        &self,
+        currency_type: Option<i32>,
+    ) -> Result<APIDatum<BaseFee>, APIError> {
+        let endpoint = String::from("/api/v1/base-fee");
+        let url: String;
+        let headers: header::HeaderMap;
+        if let Some(c) = currency_type {
+            let mut params: HashMap<String, String> = HashMap::new();
+            params.insert(String::from("currencyType"), c.to_string());
+            let query = format_query(&params);
+            url = format!("{}{}{}", &self.prefix, endpoint, query);
+            headers = self
+                .sign_headers(endpoint, None, Some(query), Method::GET)
+                .unwrap();
+        } else {
+            url = format!("{}{}", &self.prefix, endpoint);
+            headers = self
+                .sign_headers(endpoint, None, None, Method::GET)
+                .unwrap();
+        }
+        let resp = self.get(url, Some(headers)).await?;
+        let api_data = resp.json().await?;
+        Ok(api_data)
+    }
+
+    /// Actual fee rates of up to 10 symbols.
+    pub async fn get_trade_fees(&self, symbols: &[&str]) -> Result<APIData<TradeFee>, APIError> {
+        let endpoint = String::from("/api/v1/trade-fees");
+        let mut params: HashMap<String, String> = HashMap::new();
+        params.insert(String::from("symbols"), symbols.join(","));
+        let query = format_query(&params);
+        let url = format!("{}{}{}", &self.prefix, endpoint, query);
+        let headers = self
+            .sign_headers(endpoint, None, Some(query), Method::GET)
+            .unwrap();
+        let resp = self.get(url, Some(headers)).await?;
+        let api_data = resp.json().await?;
+        Ok(api_data)
+    }
 }
@@ -1,6 +1,12 @@
 use std::collections::HashMap;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+use futures::channel::mpsc::UnboundedSender;
+use futures::{pin_mut, Stream, StreamExt};
+
+use super::error::APIError;
+use super::model::websocket::KucoinWebsocketMsg;
+
 pub fn get_time() -> u128 {
     let start = SystemTime::now();
     let since_the_epoch = start
@@ -11,6 +17,28 @@ pub fn get_time() -> u128 {
 }
 
 /// Formats a query from a provided referenced hash map. Note, ordering is not assured.
+// Sends a copy of the value to every listener, dropping those that hung up.
+pub(crate) fn broadcast<T: Clone>(listeners: &mut Vec<UnboundedSender<T>>, value: &T) {
+    listeners.retain(|tx| !tx.is_closed());
+    for tx in listeners.iter() {
+        let _ = tx.unbounded_send(value.clone());
+    }
+}
+
+// Hands every message of the stream to on_message, skipping errors, until it ends.
+pub(crate) async fn feed<S, F>(stream: S, mut on_message: F)
+where
+    S: Stream<Item = Result<KucoinWebsocketMsg, APIError>>,
+    F: FnMut(&KucoinWebsocketMsg),
+{
+    pin_mut!(stream);
+    while let Some(msg) = stream.next().await {
+        if let Ok(msg) = msg {
+            on_message(&msg);
+        }
+    }
+}
+
 pub fn format_query<S: ::std::hash::BuildHasher>(params: &HashMap<String, String, S>) -> String {
     let mut query = String::new();
     for (key, val) in params.iter() {
@@ -24,9 +52,88 @@ pub fn format_query<S: ::std::hash::BuildHasher>(params: &HashMap<String, String
     query
 }
 
+/// Subtracts two decimal strings on integers scaled to the larger number of decimal places of
+/// the inputs, so order parameters carry no float artifacts. Invalid inputs count as zero.
+pub fn sub_decimal_str(a: &str, b: &str) -> String {
+    let places = decimal_places(a).max(decimal_places(b));
+    let diff = scaled_decimal(a, places).unwrap_or(0) - scaled_decimal(b, places).unwrap_or(0);
+    let unit = 10i128.pow(places as u32);
+    let sign = if diff < 0 { "-" } else { "" };
+    let (int, frac) = (diff.abs() / unit, diff.abs() % unit);
+    if places == 0 {
+        format!("{}{}", sign, int)
+    } else {
+        format!("{}{}.{:0places$}", sign, int, frac, places = places)
+    }
+}
+
+// Decimal string as an integer count of 10^-places, None when it isn't a decimal or overflows.
+fn scaled_decimal(s: &str, places: usize) -> Option<i128> {
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+    let (int, frac) = match digits.find('.') {
+        Some(i) => (&digits[..i], &digits[i + 1..]),
+        None => (digits, ""),
+    };
+    if (int.is_empty() && frac.is_empty()) || frac.len() > places {
+        return None;
+    }
+    let mut value: i128 = 0;
+    for c in int
+        .chars()
+        .chain(frac.chars())
+        .chain("0".repeat(places - frac.len()).chars())
+    {
+        let digit = c.to_digit(10)? as i128;
+        value = value.checked_mul(10)?.checked_add(digit)?;
+    }
+    Some(if negative { -value } else { value })
+}
+
+/// Number of decimal places in a decimal string such as "0.0010".
+pub fn decimal_places(s: &str) -> usize {
+    match s.find('.') {
+        Some(i) => s.len() - i - 1,
+        None => 0,
+    }
+}
+
+/// Formats a computed amount with up to 10 decimal places and no trailing zeros, e.g. 0.30000000000000004
+/// as "0.3".
+pub fn format_amount(a: f64) -> String {
+    let s = format!("{:.10}", a);
+    let s = s.trim_end_matches('0').trim_end_matches('.');
+    match s {
+        "-0" | "" => "0".to_string(),
+        s => s.to_string(),
+    }
+}
+
+/// Rounds an amount down to a multiple of the increment, e.g. a size to a symbol's base_increment.
+pub fn round_down(a: f64, increment: f64) -> f64 {
+    if increment > 0.0 {
+        (a / increment + 1e-9).floor() * increment
+    } else {
+        a
+    }
+}
+
+/// Rounds an amount up to a multiple of the increment.
+pub fn round_up(a: f64, increment: f64) -> f64 {
+    if increment > 0.0 {
+        (a / increment - 1e-9).ceil() * increment
+    } else {
+        a
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use crate::kucoin::utils::format_query;
+    use crate::kucoin::utils::{
+        format_amount, format_query, round_down, round_up, sub_decimal_str,
+    };
     use std::collections::HashMap;
     #[test]
     fn format_query_test() {
@@ -37,8 +144,31 @@ mod test {
         params.insert("price".to_string(), 124.12.to_string());
 
         let query = format_query(&params);
-        assert_eq!(query.contains("symbol=BTC-USDT"), true);
-        assert_eq!(query.contains("price=124.12"), true);
-        assert_eq!(query.contains("quantity=0.51"), true);
+        assert!(query.contains("symbol=BTC-USDT"));
+        assert!(query.contains("price=124.12"));
+        assert!(query.contains("quantity=0.51"));
+    }
+
+    #[test]
+    fn sub_decimal_str_test() {
+        assert_eq!(sub_decimal_str("0.3", "0.1"), "0.2");
+        assert_eq!(sub_decimal_str("1", "0.25"), "0.75");
+        assert_eq!(sub_decimal_str("0.0010", "0.0010"), "0.0000");
+        assert_eq!(sub_decimal_str("0.1", "0.3"), "-0.2");
+        assert_eq!(sub_decimal_str("5", "2"), "3");
+        // Beyond f64 precision
+        assert_eq!(
+            sub_decimal_str("123456789012.123456789", "0.000000001"),
+            "123456789012.123456788"
+        );
+    }
+
+    #[test]
+    fn round_to_increment_test() {
+        assert_eq!(format_amount(round_down(0.12345, 0.001)), "0.123");
+        assert_eq!(format_amount(round_up(0.12345, 0.001)), "0.124");
+        assert_eq!(format_amount(round_down(0.3, 0.1)), "0.3");
+        assert_eq!(format_amount(0.1 + 0.2), "0.3");
+        assert_eq!(format_amount(-0.0), "0");
     }
 }
@@ -1,10 +1,11 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 
-use futures::{prelude::*, stream::SplitStream, StreamExt};
-use pin_project::*;
+use futures::channel::mpsc::UnboundedReceiver;
+use futures::channel::oneshot;
+use futures::{prelude::*, stream::SplitSink, StreamExt};
 use reqwest::header;
 use std::time::Duration;
-use streamunordered::{StreamUnordered, StreamYield};
 use tokio::net::TcpStream;
 use tokio::sync::Mutex;
 use tokio::time;
@@ -12,73 +13,742 @@ use tokio_tungstenite::{connect_async, tungstenite::Message, WebSocketStream};
 use url::Url;
 
 use failure;
+use serde::de::{Deserialize, DeserializeSeed, Deserializer, IgnoredAny, MapAccess, Visitor};
 use serde_json;
+use serde_json::value::RawValue;
 use std::{
+    fmt,
+    marker::PhantomData,
+    path::Path,
     pin::Pin,
+    sync::atomic::{AtomicUsize, Ordering},
+    sync::Arc,
     task::{Context, Poll},
 };
 
+use super::buffer::{
+    queue, BufferPolicy, Buffering, LagEvent, QueueReceiver, QueueSender, TopicMetrics,
+};
 use super::client::Kucoin;
 use super::error::APIError;
 use super::model::websocket::{
-    DefaultMsg, InstanceServers, KucoinWebsocketMsg, Subscribe, WSTopic, WSType,
+    Balances, DefaultMsg, InstanceServers, KucoinWebsocketMsg, Level2, Level2Depth, Match,
+    ParseMode, Snapshot, Subscribe, SymbolTicker, WSData, WSError, WSResp, WSTopic, WSType,
 };
 use super::model::{APIDatum, Method};
+use super::recorder::{record, Recorder, Taps};
 use super::utils::get_time;
 
-type WSStream = WebSocketStream<
-    tokio_tungstenite::stream::Stream<TcpStream, tokio_native_tls::TlsStream<TcpStream>>,
+// Typed subscriber registered for a topic, shared with the reader task of every connection.
+struct TopicRoute {
+    topic: String,
+    ws_topic: WSTopic,
+    tx: QueueSender,
+}
+
+type Routes = Arc<std::sync::Mutex<Vec<TopicRoute>>>;
+
+type AckSender = oneshot::Sender<Result<(), WSError>>;
+type AckReceiver = oneshot::Receiver<Result<(), WSError>>;
+
+// Requests waiting on the ack with their id, resolved by the reader tasks.
+type Acks = Arc<std::sync::Mutex<HashMap<String, AckSender>>>;
+
+/// Kucoin's limit of topics subscribed over a single connection.
+pub const MAX_TOPICS_PER_CONNECTION: usize = 100;
+
+/// Time to wait for Kucoin to welcome a new connection or acknowledge a subscribe or
+/// unsubscribe request.
+pub const ACK_TIMEOUT: Duration = Duration::from_secs(10);
+
+type WSSink = SplitSink<
+    WebSocketStream<
+        tokio_tungstenite::stream::Stream<TcpStream, tokio_native_tls::TlsStream<TcpStream>>,
+    >,
+    Message,
 >;
-pub type StoredStream = SplitStream<WSStream>;
 
-#[pin_project]
-#[derive(Default)]
+// Topic string and whether it is subscribed on a private channel. A public and a private
+// topic may share a topic string, e.g. Level3Public and StopOrder, and are subscribed apart.
+type TopicKey = (String, bool);
+
+fn topic_key(ws_topic: &WSTopic) -> TopicKey {
+    (ws_topic.topic(), ws_topic.is_private())
+}
+
+// Topics subscribed over a connection. The pool groups connections opened with the same url
+// or bullet token, which topics may be packed onto.
+struct Connection {
+    pool: String,
+    topics: HashSet<TopicKey>,
+}
+
+// Connection a topic key is subscribed over and the topics sharing it, such as DebtRatio
+// and PositionChange on /margin/position. It is unsubscribed once none of them is left.
+struct Subscription {
+    token: usize,
+    topics: HashSet<WSTopic>,
+}
+
+// What new connections of a pool connect to: fixed urls, or the instance servers of a bullet
+// fetched when a connection is opened.
+enum Servers<'a> {
+    Urls(&'a [String]),
+    Bullet(&'a Kucoin, WSType),
+}
+
+// Write half of a connection and the signal stopping its reader task.
+struct Link {
+    sink: Arc<Mutex<WSSink>>,
+    stop: oneshot::Sender<()>,
+}
+
+struct Pool {
+    subscriptions: HashMap<TopicKey, Subscription>,
+    connections: HashMap<usize, Connection>,
+    links: HashMap<usize, Link>,
+    topic_limit: usize,
+    next_server: usize,
+    next_connection: usize,
+    tx: QueueSender,
+}
+
+impl Pool {
+    // Adds the topic to the subscription of its topic key, returns false when there is none
+    // yet.
+    fn share_topic(&mut self, ws_topic: &WSTopic) -> bool {
+        match self.subscriptions.get_mut(&topic_key(ws_topic)) {
+            Some(sub) => {
+                sub.topics.insert(ws_topic.clone());
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn add_topic(&mut self, ws_topic: &WSTopic, token: usize) {
+        let topic = topic_key(ws_topic);
+        if let Some(conn) = self.connections.get_mut(&token) {
+            conn.topics.insert(topic.clone());
+        }
+        let mut topics = HashSet::new();
+        topics.insert(ws_topic.clone());
+        self.subscriptions
+            .insert(topic, Subscription { token, topics });
+    }
+
+    // Removes the topic from its subscription. Returns the connection of its topic key once no
+    // other topic shares it, the topic key is then no longer subscribed.
+    fn remove_topic(&mut self, ws_topic: &WSTopic) -> Option<usize> {
+        let topic = topic_key(ws_topic);
+        let sub = self.subscriptions.get_mut(&topic)?;
+        if !sub.topics.remove(ws_topic) || !sub.topics.is_empty() {
+            return None;
+        }
+        let token = sub.token;
+        self.subscriptions.remove(&topic);
+        if let Some(conn) = self.connections.get_mut(&token) {
+            conn.topics.remove(&topic);
+        }
+        Some(token)
+    }
+
+    // Drops the subscription of a rejected topic, along with the topics that shared its topic
+    // key in the meantime, unless it was unsubscribed and subscribed again since.
+    fn reject_topic(&mut self, ws_topic: &WSTopic, token: usize) {
+        let topic = topic_key(ws_topic);
+        if self.subscriptions.get(&topic).map(|sub| sub.token) != Some(token) {
+            return;
+        }
+        self.subscriptions.remove(&topic);
+        if let Some(conn) = self.connections.get_mut(&token) {
+            conn.topics.remove(&topic);
+        }
+    }
+
+    // Drops a connection that closed and its subscriptions, returns the topic keys that are no
+    // longer subscribed.
+    fn evict(&mut self, token: usize) -> Vec<TopicKey> {
+        self.links.remove(&token);
+        let mut topics: Vec<TopicKey> = match self.connections.remove(&token) {
+            Some(conn) => conn.topics.into_iter().collect(),
+            None => return Vec::new(),
+        };
+        topics.sort();
+        for topic in topics.iter() {
+            self.subscriptions.remove(topic);
+        }
+        topics
+    }
+}
+
+/// Merged stream of the messages of every subscribed topic, see WSControl to change
+/// subscriptions from other tasks while the stream is consumed.
+///
+/// When a connection closes its topics are no longer subscribed: the stream yields an
+/// APIError::Disconnected listing them, typed streams of those topics yield it and end, and
+/// the topics can be subscribed again.
 pub struct KucoinWebsocket {
-    subscriptions: HashMap<WSTopic, usize>,
-    tokens: HashMap<usize, WSTopic>,
-    #[pin]
-    streams: StreamUnordered<StoredStream>,
+    control: WSControl,
+    rx: QueueReceiver,
+}
+
+/// Cloneable handle adding and removing topics on a running KucoinWebsocket,
+/// obtained with KucoinWebsocket::control.
+#[derive(Clone)]
+pub struct WSControl {
+    api: Option<Kucoin>,
+    pool: Arc<Mutex<Pool>>,
+    routes: Routes,
+    acks: Acks,
+    mode: Arc<std::sync::Mutex<ParseMode>>,
+    taps: Taps,
+    buffering: Arc<Buffering>,
+}
+
+impl Default for KucoinWebsocket {
+    fn default() -> Self {
+        KucoinWebsocket::with_api(None)
+    }
+}
+
+impl KucoinWebsocket {
+    // The Kucoin client is used to fetch bullets for subscribe_topics.
+    fn with_api(api: Option<Kucoin>) -> Self {
+        let buffering = Arc::new(Buffering::default());
+        let (tx, rx) = queue(buffering.clone());
+        let pool = Pool {
+            subscriptions: HashMap::new(),
+            connections: HashMap::new(),
+            links: HashMap::new(),
+            topic_limit: MAX_TOPICS_PER_CONNECTION,
+            next_server: 0,
+            next_connection: 0,
+            tx,
+        };
+        KucoinWebsocket {
+            control: WSControl {
+                api,
+                pool: Arc::new(Mutex::new(pool)),
+                routes: Routes::default(),
+                acks: Acks::default(),
+                mode: Arc::default(),
+                taps: Taps::default(),
+                buffering,
+            },
+            rx,
+        }
+    }
 }
 
 impl Stream for KucoinWebsocket {
     type Item = Result<KucoinWebsocketMsg, APIError>;
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        match self.as_mut().project().streams.poll_next(cx) {
-            Poll::Ready(Some((y, _))) => match y {
-                StreamYield::Item(item) => {
-                    // let heartbeat = self.heartbeats.get_mut(&token).unwrap();
-                    Poll::Ready({
-                        Some(
-                            item.map_err(APIError::Websocket)
-                                .and_then(|m| parse_message(m)),
-                        )
-                    })
-                }
-                StreamYield::Finished(_) => Poll::Pending,
-            },
-            Poll::Ready(None) => panic!("No Stream Subscribed"),
-            Poll::Pending => Poll::Pending,
-        }
+        self.rx.poll_next_unpin(cx)
     }
 }
 
 impl KucoinWebsocket {
+    /// Handle for other tasks to subscribe and unsubscribe while this stream is consumed.
+    pub fn control(&self) -> WSControl {
+        self.control.clone()
+    }
+
+    /// Sets the number of topics subscribed over a single connection before a new one is
+    /// opened, defaults to MAX_TOPICS_PER_CONNECTION.
+    pub async fn set_topic_limit(&mut self, limit: usize) {
+        self.control.set_topic_limit(limit).await
+    }
+
+    /// Sets how frames that do not parse are yielded, defaults to ParseMode::Strict. Either way
+    /// a frame that fails to parse is yielded on its own and the stream keeps going.
+    pub fn set_parse_mode(&mut self, mode: ParseMode) {
+        self.control.set_parse_mode(mode)
+    }
+
+    /// Records every raw text frame received from now on, across all connections, to a gzip
+    /// compressed JSON lines file at path. Replay the file with recorder::Replay.
+    #[allow(clippy::result_large_err)]
+    pub fn record<P: AsRef<Path>>(&self, path: P) -> Result<Recorder, APIError> {
+        self.control.record(path)
+    }
+
+    /// Sets how messages of the topic are buffered while the consumer of this stream, or of a
+    /// typed stream of the topic, is behind. Defaults to BufferPolicy::Unbounded.
+    pub fn set_buffer_policy(&self, ws_topic: &WSTopic, policy: BufferPolicy) {
+        self.control.set_buffer_policy(ws_topic, policy)
+    }
+
+    /// Delivery counters and lag of every topic that received messages, keyed by message topic
+    /// such as /market/level2:BTC-USDT.
+    pub fn metrics(&self) -> HashMap<String, TopicMetrics> {
+        self.control.metrics()
+    }
+
+    /// Stream of a LagEvent for every message delivered more than threshold after its exchange
+    /// timestamp, or after it was received when it has none.
+    pub fn lag_events(&self, threshold: Duration) -> UnboundedReceiver<LagEvent> {
+        self.control.lag_events(threshold)
+    }
+
+    /// Subscribes to the topics over connections to the url. Topics are packed onto the open
+    /// connections to the same url up to the topic limit before a new connection is opened,
+    /// and topics that are already subscribed are skipped.
     pub async fn subscribe(&mut self, url: String, ws_topic: Vec<WSTopic>) -> Result<(), APIError> {
-        let endpoint = Url::parse(&url).unwrap();
-        let (ws_stream, _) = connect_async(endpoint).await?;
+        self.control.subscribe(url, ws_topic).await
+    }
 
-        let (sink, read) = ws_stream.split();
-        let sink_mutex = Mutex::new(sink);
+    /// Subscribes to the topics using the token and instance servers returned by a bullet call,
+    /// e.g. ws_bullet_public. New connections are opened round robin across the instance servers.
+    pub async fn subscribe_servers(
+        &mut self,
+        servers: &InstanceServers,
+        ws_topic: Vec<WSTopic>,
+    ) -> Result<(), APIError> {
+        self.control.subscribe_servers(servers, ws_topic).await
+    }
 
-        for topic in ws_topic.iter() {
-            let sub = Subscribe::new(topic);
+    /// Subscribes to the topics without a url, public and private spot and futures topics can be
+    /// mixed. Each topic is routed to a pooled connection of its WSType, and the bullet for a new
+    /// connection is fetched as needed. Requires a websocket created with Kucoin::websocket.
+    pub async fn subscribe_topics(&mut self, ws_topic: Vec<WSTopic>) -> Result<(), APIError> {
+        self.control.subscribe_topics(ws_topic).await
+    }
 
-            sink_mutex
-                .lock()
-                .await
-                .send(Message::Text(serde_json::to_string(&sub).unwrap()))
+    /// Typed subscription to a topic without a url, see subscribe_typed and subscribe_topics.
+    pub async fn subscribe_topic<T: WSData>(
+        &mut self,
+        ws_topic: WSTopic,
+    ) -> Result<TopicStream<T>, APIError> {
+        self.control.subscribe_topic(ws_topic).await
+    }
+
+    /// Subscribes to a topic and returns a stream of its messages typed by their data, e.g.
+    /// `ws.subscribe_typed::<Match>(url, WSTopic::Match(symbols))`. The subscription shares its
+    /// connection with an existing subscription of the same topic, and its messages are no longer
+    /// yielded by the KucoinWebsocket stream.
+    pub async fn subscribe_typed<T: WSData>(
+        &mut self,
+        url: String,
+        ws_topic: WSTopic,
+    ) -> Result<TopicStream<T>, APIError> {
+        self.control.subscribe_typed(url, ws_topic).await
+    }
+
+    pub async fn subscribe_ticker(
+        &mut self,
+        url: String,
+        symbols: Vec<String>,
+    ) -> Result<TopicStream<SymbolTicker>, APIError> {
+        self.subscribe_typed(url, WSTopic::Ticker(symbols)).await
+    }
+
+    pub async fn subscribe_snapshot(
+        &mut self,
+        url: String,
+        symbol: String,
+    ) -> Result<TopicStream<Snapshot>, APIError> {
+        self.subscribe_typed(url, WSTopic::Snapshot(symbol)).await
+    }
+
+    pub async fn subscribe_order_book(
+        &mut self,
+        url: String,
+        symbols: Vec<String>,
+    ) -> Result<TopicStream<Level2>, APIError> {
+        self.subscribe_typed(url, WSTopic::OrderBook(symbols)).await
+    }
+
+    pub async fn subscribe_order_book_depth5(
+        &mut self,
+        url: String,
+        symbols: Vec<String>,
+    ) -> Result<TopicStream<Level2Depth>, APIError> {
+        self.subscribe_typed(url, WSTopic::OrderBookDepth5(symbols))
+            .await
+    }
+
+    pub async fn subscribe_order_book_depth50(
+        &mut self,
+        url: String,
+        symbols: Vec<String>,
+    ) -> Result<TopicStream<Level2Depth>, APIError> {
+        self.subscribe_typed(url, WSTopic::OrderBookDepth50(symbols))
+            .await
+    }
+
+    pub async fn subscribe_match(
+        &mut self,
+        url: String,
+        symbols: Vec<String>,
+    ) -> Result<TopicStream<Match>, APIError> {
+        self.subscribe_typed(url, WSTopic::Match(symbols)).await
+    }
+
+    pub async fn subscribe_balances(
+        &mut self,
+        url: String,
+    ) -> Result<TopicStream<Balances>, APIError> {
+        self.subscribe_typed(url, WSTopic::Balances).await
+    }
+
+    /// Unsubscribes from the topic, see WSControl::unsubscribe.
+    pub async fn unsubscribe(&mut self, ws_topic: WSTopic) -> Result<(), APIError> {
+        self.control.unsubscribe(ws_topic).await
+    }
+
+    /// Topics currently subscribed, across every connection.
+    pub async fn topics(&self) -> Vec<WSTopic> {
+        self.control.topics().await
+    }
+
+    /// Number of open connections.
+    pub async fn connection_count(&self) -> usize {
+        self.control.connection_count().await
+    }
+}
+
+impl WSControl {
+    pub async fn set_topic_limit(&self, limit: usize) {
+        self.pool.lock().await.topic_limit = limit.max(1);
+    }
+
+    pub fn set_parse_mode(&self, mode: ParseMode) {
+        *self.mode.lock().unwrap() = mode;
+    }
+
+    #[allow(clippy::result_large_err)]
+    pub fn record<P: AsRef<Path>>(&self, path: P) -> Result<Recorder, APIError> {
+        Recorder::start(path, &self.taps)
+    }
+
+    pub fn set_buffer_policy(&self, ws_topic: &WSTopic, policy: BufferPolicy) {
+        self.buffering.set_policy(ws_topic.topic(), policy)
+    }
+
+    pub fn metrics(&self) -> HashMap<String, TopicMetrics> {
+        self.buffering.metrics()
+    }
+
+    pub fn lag_events(&self, threshold: Duration) -> UnboundedReceiver<LagEvent> {
+        self.buffering.lag_events(threshold.as_millis() as u64)
+    }
+
+    pub async fn subscribe(&self, url: String, ws_topic: Vec<WSTopic>) -> Result<(), APIError> {
+        let urls = vec![url.clone()];
+        self.subscribe_pooled(&url, &Servers::Urls(&urls), ws_topic)
+            .await
+    }
+
+    pub async fn subscribe_servers(
+        &self,
+        servers: &InstanceServers,
+        ws_topic: Vec<WSTopic>,
+    ) -> Result<(), APIError> {
+        if servers.instance_servers.is_empty() {
+            return Err(APIError::Other(
+                "No instance servers to connect to".to_string(),
+            ));
+        }
+        let urls = server_urls(servers)?;
+        self.subscribe_pooled(&servers.token, &Servers::Urls(&urls), ws_topic)
+            .await
+    }
+
+    pub async fn subscribe_typed<T: WSData>(
+        &self,
+        url: String,
+        ws_topic: WSTopic,
+    ) -> Result<TopicStream<T>, APIError> {
+        let rx = self.add_queue(&ws_topic);
+        let subscribed = self.subscribe(url, vec![ws_topic]).await;
+        Ok(TopicStream::new(self.keep_queue(rx, subscribed)?))
+    }
+
+    pub async fn subscribe_topics(&self, ws_topic: Vec<WSTopic>) -> Result<(), APIError> {
+        let api = self.api.as_ref().ok_or_else(|| {
+            APIError::Other(
+                "Subscribing without a url requires a websocket created with Kucoin::websocket"
+                    .to_string(),
+            )
+        })?;
+        let mut groups: Vec<(WSType, Vec<WSTopic>)> = Vec::new();
+        for topic in ws_topic {
+            let ws_type = topic.ws_type();
+            match groups.iter_mut().find(|(t, _)| *t == ws_type) {
+                Some((_, topics)) => topics.push(topic),
+                None => groups.push((ws_type, vec![topic])),
+            }
+        }
+        for (ws_type, topics) in groups {
+            let pool_key = format!("{:?}", ws_type);
+            self.subscribe_pooled(&pool_key, &Servers::Bullet(api, ws_type), topics)
                 .await?;
         }
+        Ok(())
+    }
+
+    pub async fn subscribe_topic<T: WSData>(
+        &self,
+        ws_topic: WSTopic,
+    ) -> Result<TopicStream<T>, APIError> {
+        let rx = self.add_queue(&ws_topic);
+        let subscribed = self.subscribe_topics(vec![ws_topic]).await;
+        Ok(TopicStream::new(self.keep_queue(rx, subscribed)?))
+    }
+
+    // Subscribes to a topic without a url, returning the queue its messages are routed to.
+    pub(crate) async fn subscribe_queue(
+        &self,
+        ws_topic: WSTopic,
+    ) -> Result<QueueReceiver, APIError> {
+        let rx = self.add_queue(&ws_topic);
+        let subscribed = self.subscribe_topics(vec![ws_topic]).await;
+        self.keep_queue(rx, subscribed)
+    }
+
+    // Returns the queue of a subscription, or removes its route when the subscription failed.
+    #[allow(clippy::result_large_err)]
+    fn keep_queue(
+        &self,
+        rx: QueueReceiver,
+        subscribed: Result<(), APIError>,
+    ) -> Result<QueueReceiver, APIError> {
+        if let Err(e) = subscribed {
+            drop(rx);
+            self.routes.lock().unwrap().retain(|r| !r.tx.is_closed());
+            return Err(e);
+        }
+        Ok(rx)
+    }
+
+    fn add_queue(&self, ws_topic: &WSTopic) -> QueueReceiver {
+        let (tx, rx) = queue(self.buffering.clone());
+        self.routes.lock().unwrap().push(TopicRoute {
+            topic: ws_topic.topic(),
+            ws_topic: ws_topic.clone(),
+            tx,
+        });
+        rx
+    }
+
+    /// Sends an unsubscribe message for the topic and waits for Kucoin to acknowledge it, up to
+    /// ACK_TIMEOUT. Typed streams of the topic end, and a connection left without topics is closed
+    /// instead. Other topics sharing the connection are unaffected, as are topics sharing its
+    /// topic string, e.g. PositionChange when DebtRatio is unsubscribed, which stays subscribed
+    /// until the last of them is.
+    pub async fn unsubscribe(&self, ws_topic: WSTopic) -> Result<(), APIError> {
+        let ack = {
+            let mut pool = self.pool.lock().await;
+            let subscribed = pool
+                .subscriptions
+                .get(&topic_key(&ws_topic))
+                .is_some_and(|sub| sub.topics.contains(&ws_topic));
+            if !subscribed {
+                return Ok(());
+            }
+            self.routes
+                .lock()
+                .unwrap()
+                .retain(|r| r.ws_topic != ws_topic);
+            let token = match pool.remove_topic(&ws_topic) {
+                Some(token) => token,
+                None => return Ok(()),
+            };
+
+            let last_topic = match pool.connections.get(&token) {
+                Some(conn) => conn.topics.is_empty(),
+                None => true,
+            };
+            if last_topic {
+                pool.connections.remove(&token);
+                if let Some(link) = pool.links.remove(&token) {
+                    let _ = link.stop.send(());
+                    let _ = link.sink.lock().await.close().await;
+                }
+                return Ok(());
+            }
+
+            let unsub = Subscribe::unsubscribe(&ws_topic);
+            let ack_rx = self.request(&pool.links[&token].sink, &unsub).await?;
+            (unsub.id, ack_rx)
+        };
+        let (id, ack_rx) = ack;
+        self.await_ack(&id, ack_rx, "unsubscribe from", &ws_topic)
+            .await
+    }
+
+    pub async fn topics(&self) -> Vec<WSTopic> {
+        self.pool
+            .lock()
+            .await
+            .subscriptions
+            .values()
+            .flat_map(|sub| sub.topics.iter().cloned())
+            .collect()
+    }
+
+    pub async fn connection_count(&self) -> usize {
+        self.pool.lock().await.connections.len()
+    }
+
+    // Sends every subscribe request before waiting on the acks, so a connection's requests are
+    // acknowledged concurrently, and releases the pool while waiting. Topics that are rejected
+    // or not acknowledged are dropped and the first failure is returned. When a request can't
+    // be sent the requests already sent are still settled before failing.
+    async fn subscribe_pooled(
+        &self,
+        pool_key: &str,
+        servers: &Servers<'_>,
+        ws_topic: Vec<WSTopic>,
+    ) -> Result<(), APIError> {
+        let mut pending = Vec::new();
+        let mut result = Ok(());
+        {
+            let mut pool = self.pool.lock().await;
+            for topic in ws_topic {
+                match self
+                    .send_subscribe(&mut pool, pool_key, servers, &topic)
+                    .await
+                {
+                    Ok(Some((token, id, ack_rx))) => pending.push((topic, token, id, ack_rx)),
+                    Ok(None) => {}
+                    Err(e) => {
+                        result = Err(e);
+                        break;
+                    }
+                }
+            }
+        }
+
+        for (topic, token, id, ack_rx) in pending {
+            if let Err(e) = self.await_ack(&id, ack_rx, "subscribe to", &topic).await {
+                self.pool.lock().await.reject_topic(&topic, token);
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+        result
+    }
+
+    // Sends the subscribe request of the topic over a connection of the pool with room, opening
+    // one when there is none. Returns the connection and the request waiting on its ack, or
+    // None when the topic string is already subscribed.
+    async fn send_subscribe(
+        &self,
+        pool: &mut Pool,
+        pool_key: &str,
+        servers: &Servers<'_>,
+        topic: &WSTopic,
+    ) -> Result<Option<(usize, String, AckReceiver)>, APIError> {
+        if pool.share_topic(topic) {
+            return Ok(None);
+        }
+        let token = match pick_connection(&pool.connections, pool_key, pool.topic_limit) {
+            Some(token) => token,
+            None => self.connect_any(pool, pool_key, servers).await?,
+        };
+        let sub = Subscribe::new(topic);
+        let ack_rx = self.request(&pool.links[&token].sink, &sub).await?;
+        pool.add_topic(topic, token);
+        Ok(Some((token, sub.id, ack_rx)))
+    }
+
+    // Sends a subscribe or unsubscribe request, returning the receiver of its ack.
+    async fn request(
+        &self,
+        sink: &Mutex<WSSink>,
+        req: &Subscribe,
+    ) -> Result<AckReceiver, APIError> {
+        let (ack_tx, ack_rx) = oneshot::channel();
+        self.acks.lock().unwrap().insert(req.id.clone(), ack_tx);
+        let sent = sink
+            .lock()
+            .await
+            .send(Message::Text(serde_json::to_string(req).unwrap()))
+            .await;
+        if let Err(e) = sent {
+            self.acks.lock().unwrap().remove(&req.id);
+            return Err(APIError::Websocket(e));
+        }
+        Ok(ack_rx)
+    }
+
+    async fn await_ack(
+        &self,
+        id: &str,
+        ack_rx: AckReceiver,
+        action: &str,
+        ws_topic: &WSTopic,
+    ) -> Result<(), APIError> {
+        match time::timeout(ACK_TIMEOUT, ack_rx).await {
+            Ok(Ok(Ok(()))) => Ok(()),
+            Ok(Ok(Err(e))) => Err(APIError::Other(format!(
+                "Kucoin rejected the request to {} {}, code: {}, msg: {}",
+                action,
+                ws_topic.topic(),
+                e.code,
+                e.data
+            ))),
+            _ => {
+                self.acks.lock().unwrap().remove(id);
+                Err(APIError::Other(format!(
+                    "No acknowledgement to {} {}",
+                    action,
+                    ws_topic.topic()
+                )))
+            }
+        }
+    }
+
+    // Connects to the urls round robin, failing over to the next url when a server refuses
+    // the connection. A pool without fixed urls fetches its bullet here, while the pool is
+    // locked and a new connection is known to be needed.
+    async fn connect_any(
+        &self,
+        pool: &mut Pool,
+        pool_key: &str,
+        servers: &Servers<'_>,
+    ) -> Result<usize, APIError> {
+        let fetched;
+        let urls = match servers {
+            Servers::Urls(urls) => *urls,
+            Servers::Bullet(api, ws_type) => {
+                fetched = server_urls(&api.get_socket_servers(*ws_type).await?)?;
+                &fetched
+            }
+        };
+        if urls.is_empty() {
+            return Err(APIError::Other(
+                "No instance servers to connect to".to_string(),
+            ));
+        }
+        let mut errors = Vec::new();
+        for _ in 0..urls.len() {
+            let url = &urls[pool.next_server % urls.len()];
+            pool.next_server += 1;
+            match self.connect(pool, pool_key, url).await {
+                Ok(token) => return Ok(token),
+                Err(e) => errors.push(e.to_string()),
+            }
+        }
+        Err(APIError::Other(format!(
+            "Failed connecting to any instance server: {}",
+            errors.join("; ")
+        )))
+    }
+
+    #[allow(clippy::result_large_err)]
+    async fn connect(&self, pool: &mut Pool, pool_key: &str, url: &str) -> Result<usize, APIError> {
+        let endpoint = with_connect_id(url)?;
+        let (ws_stream, _) = connect_async(endpoint).await?;
+        let token = pool.next_connection;
+        pool.next_connection += 1;
+
+        let (sink, mut read) = ws_stream.split();
+        let sink_mutex = Arc::new(Mutex::new(sink));
+        let heartbeat_sink = sink_mutex.clone();
 
         // Ping heartbeat
         tokio::spawn(async move {
@@ -88,7 +758,7 @@ impl KucoinWebsocket {
                     id: get_time().to_string(),
                     r#type: "ping".to_string(),
                 };
-                let resp = sink_mutex
+                let resp = heartbeat_sink
                     .lock()
                     .await
                     .send(Message::Text(serde_json::to_string(&ping).unwrap()))
@@ -107,160 +777,500 @@ impl KucoinWebsocket {
             }
         });
 
-        let token = self.streams.push(read);
-        self.subscriptions.insert(ws_topic[0].clone(), token);
-        self.tokens.insert(token, ws_topic[0].clone());
+        // Frames are parsed as they arrive, resolve the welcome and pending acks and are handed
+        // to typed subscribers of their topic. Everything else is yielded by the KucoinWebsocket
+        // stream. A connection that closes on its own is evicted from the pool.
+        let tx = pool.tx.clone();
+        let evict_pool = self.pool.clone();
+        let routes = self.routes.clone();
+        let acks = self.acks.clone();
+        let parse_mode = self.mode.clone();
+        let taps = self.taps.clone();
+        let (stop_tx, mut stop_rx) = oneshot::channel();
+        let (welcome_tx, welcome_rx) = oneshot::channel();
+        let mut welcome = Some(welcome_tx);
+        tokio::spawn(async move {
+            loop {
+                let frame = tokio::select! {
+                    frame = read.next() => frame,
+                    _ = &mut stop_rx => return,
+                };
+                let frame = match frame {
+                    Some(frame) => frame,
+                    None => break,
+                };
+                let received = get_time() as u64;
+                if let Ok(Message::Text(text)) = &frame {
+                    record(&taps, text, received);
+                }
+                let mode = *parse_mode.lock().unwrap();
+                let msg = frame
+                    .map_err(APIError::Websocket)
+                    .and_then(|m| parse_message_with(m, mode));
+                if !resolve_ack(&acks, &msg) {
+                    resolve_welcome(&mut welcome, &msg);
+                }
+                // Delivery waits while a blocking buffer policy is full, and so do the acks of
+                // the connection's later frames
+                let delivered = tokio::select! {
+                    delivered = route_message(&routes, &tx, msg, received) => delivered,
+                    _ = &mut stop_rx => return,
+                };
+                if !delivered {
+                    return;
+                }
+            }
+            // A connection still waiting on its welcome fails now instead of at the timeout
+            drop(welcome);
+            let topics = evict_pool.lock().await.evict(token);
+            if !topics.is_empty() {
+                disconnected(&routes, &tx, topics).await;
+            }
+        });
 
-        Ok(())
+        let welcomed = match time::timeout(ACK_TIMEOUT, welcome_rx).await {
+            Ok(Ok(Ok(()))) => Ok(()),
+            Ok(Ok(Err(e))) => Err(APIError::Other(format!(
+                "Kucoin rejected the connection to {}, code: {}, msg: {}",
+                url, e.code, e.data
+            ))),
+            _ => Err(APIError::Other(format!("No welcome message from {}", url))),
+        };
+        if let Err(e) = welcomed {
+            let _ = stop_tx.send(());
+            let _ = sink_mutex.lock().await.close().await;
+            return Err(e);
+        }
+
+        pool.links.insert(
+            token,
+            Link {
+                sink: sink_mutex,
+                stop: stop_tx,
+            },
+        );
+        pool.connections.insert(
+            token,
+            Connection {
+                pool: pool_key.to_string(),
+                topics: HashSet::new(),
+            },
+        );
+        Ok(token)
     }
+}
 
-    pub fn unsubscribe(&mut self, ws_topic: WSTopic) -> Option<StoredStream> {
-        let streams = Pin::new(&mut self.streams);
-        self.subscriptions
-            .get(&ws_topic)
-            .and_then(|token| StreamUnordered::take(streams, *token))
+// Socket urls of the instance servers returned by a bullet call.
+#[allow(clippy::result_large_err)]
+fn server_urls(servers: &InstanceServers) -> Result<Vec<String>, APIError> {
+    servers
+        .instance_servers
+        .iter()
+        .map(|s| socket_url(&s.endpoint, &servers.token))
+        .collect()
+}
+
+// Completes the request waiting on an ack or error message's id, returns whether one was.
+fn resolve_ack(
+    acks: &std::sync::Mutex<HashMap<String, AckSender>>,
+    msg: &Result<KucoinWebsocketMsg, APIError>,
+) -> bool {
+    let (id, outcome) = match msg {
+        Ok(KucoinWebsocketMsg::AckMsg(m)) => (&m.id, Ok(())),
+        Ok(KucoinWebsocketMsg::Error(e)) => (&e.id, Err(e.clone())),
+        _ => return false,
+    };
+    match acks.lock().unwrap().remove(id) {
+        Some(tx) => {
+            let _ = tx.send(outcome);
+            true
+        }
+        None => false,
     }
 }
 
-fn parse_message(msg: Message) -> Result<KucoinWebsocketMsg, APIError> {
-    match msg {
-        Message::Text(msg) => {
-            if msg.contains("\"type\":\"welcome\"") || msg.contains("\"type\":\"ack\"") {
-                Ok(KucoinWebsocketMsg::WelcomeMsg(serde_json::from_str(&msg)?))
-            } else if msg.contains("\"type\":\"ping\"") {
-                Ok(KucoinWebsocketMsg::PingMsg(serde_json::from_str(&msg)?))
-            } else if msg.contains("\"type\":\"pong\"") {
-                Ok(KucoinWebsocketMsg::PongMsg(serde_json::from_str(&msg)?))
-            } else if msg.contains("\"subject\":\"trade.ticker\"") {
-                Ok(KucoinWebsocketMsg::TickerMsg(serde_json::from_str(&msg)?))
-            } else if msg.contains("\"topic\":\"/market/ticker:all\"") {
-                Ok(KucoinWebsocketMsg::AllTickerMsg(serde_json::from_str(
-                    &msg,
-                )?))
-            } else if msg.contains("\"subject\":\"trade.snapshot\"") {
-                Ok(KucoinWebsocketMsg::SnapshotMsg(serde_json::from_str(&msg)?))
-            } else if msg.contains("\"subject\":\"trade.l2update\"") {
-                Ok(KucoinWebsocketMsg::OrderBookMsg(serde_json::from_str(
-                    &msg,
-                )?))
-            } else if msg.contains("/market/match:") {
-                Ok(KucoinWebsocketMsg::MatchMsg(serde_json::from_str(&msg)?))
-            } else if msg.contains("\"subject\":\"trade.l3received\"") {
-                Ok(KucoinWebsocketMsg::Level3ReceivedMsg(serde_json::from_str(
-                    &msg,
-                )?))
-            } else if msg.contains("\"subject\":\"trade.l3open\"") {
-                Ok(KucoinWebsocketMsg::Level3OpenMsg(serde_json::from_str(
-                    &msg,
-                )?))
-            } else if msg.contains("\"subject\":\"trade.l3done\"") {
-                Ok(KucoinWebsocketMsg::Level3DoneMsg(serde_json::from_str(
-                    &msg,
-                )?))
-            } else if msg.contains("\"subject\":\"trade.l3match\"") {
-                Ok(KucoinWebsocketMsg::Level3MatchMsg(serde_json::from_str(
-                    &msg,
-                )?))
-            } else if msg.contains("\"subject\":\"trade.l3change\"") {
-                Ok(KucoinWebsocketMsg::Level3ChangeMsg(serde_json::from_str(
-                    &msg,
-                )?))
-            } else if msg.contains("\"subject\":\"level2\"") {
-                Ok(KucoinWebsocketMsg::OrderBookDepthMsg(serde_json::from_str(
-                    &msg,
-                )?))
-            } else if msg.contains("\"subject\":\"received\"") {
-                Ok(KucoinWebsocketMsg::FullMatchReceivedMsg(
-                    serde_json::from_str(&msg)?,
-                ))
-            } else if msg.contains("\"subject\":\"open\"") {
-                Ok(KucoinWebsocketMsg::FullMatchOpenMsg(serde_json::from_str(
-                    &msg,
-                )?))
-            } else if msg.contains("\"subject\":\"done\"") {
-                Ok(KucoinWebsocketMsg::FullMatchDoneMsg(serde_json::from_str(
-                    &msg,
-                )?))
-            } else if msg.contains("\"subject\":\"match\"") {
-                Ok(KucoinWebsocketMsg::FullMatchMatchMsg(serde_json::from_str(
-                    &msg,
-                )?))
-            } else if msg.contains("\"subject\":\"update\"") {
-                Ok(KucoinWebsocketMsg::FullMatchChangeMsg(
-                    serde_json::from_str(&msg)?,
-                ))
-            } else if msg.contains("/indicator/index:") {
-                Ok(KucoinWebsocketMsg::IndexPriceMsg(serde_json::from_str(
-                    &msg,
-                )?))
-            } else if msg.contains("/indicator/markPrice:") {
-                Ok(KucoinWebsocketMsg::MarketPriceMsg(serde_json::from_str(
-                    &msg,
-                )?))
-            } else if msg.contains("/margin/fundingBook:") {
-                Ok(KucoinWebsocketMsg::OrderBookChangeMsg(
-                    serde_json::from_str(&msg)?,
-                ))
-            } else if msg.contains("\"type\":\"stop\"") || msg.contains("\"type\":\"activate\"") {
-                Ok(KucoinWebsocketMsg::StopOrderMsg(serde_json::from_str(
-                    &msg,
-                )?))
-            } else if msg.contains("/account/balance") {
-                Ok(KucoinWebsocketMsg::BalancesMsg(serde_json::from_str(&msg)?))
-            } else if msg.contains("debt.ratio") {
-                Ok(KucoinWebsocketMsg::DebtRatioMsg(serde_json::from_str(
-                    &msg,
-                )?))
-            } else if msg.contains("position.status") {
-                Ok(KucoinWebsocketMsg::PositionChangeMsg(serde_json::from_str(
-                    &msg,
-                )?))
-            } else if msg.contains("order.open") {
-                Ok(KucoinWebsocketMsg::MarginTradeOpenMsg(
-                    serde_json::from_str(&msg)?,
-                ))
-            } else if msg.contains("order.update") {
-                Ok(KucoinWebsocketMsg::MarginTradeUpdateMsg(
-                    serde_json::from_str(&msg)?,
-                ))
-            } else if msg.contains("order.done") {
-                Ok(KucoinWebsocketMsg::MarginTradeDoneMsg(
-                    serde_json::from_str(&msg)?,
-                ))
-            } else if msg.contains("error") {
-                Ok(KucoinWebsocketMsg::Error(msg))
-            } else if msg.contains("\"topic\":\"/spotMarket/tradeOrders\"") {
-                if msg.contains("\"type\":\"open\"") {
-                    Ok(KucoinWebsocketMsg::TradeOpenMsg(serde_json::from_str(
-                        &msg,
-                    )?))
-                } else if msg.contains("\"type\":\"match\"") {
-                    Ok(KucoinWebsocketMsg::TradeMatchMsg(serde_json::from_str(
-                        &msg,
-                    )?))
-                } else if msg.contains("\"type\":\"filled\"") {
-                    Ok(KucoinWebsocketMsg::TradeFilledMsg(serde_json::from_str(
-                        &msg,
-                    )?))
-                } else if msg.contains("\"type\":\"canceled\"") {
-                    Ok(KucoinWebsocketMsg::TradeCanceledMsg(serde_json::from_str(
-                        &msg,
-                    )?))
-                } else if msg.contains("\"type\":\"update\"") {
-                    Ok(KucoinWebsocketMsg::TradeUpdateMsg(serde_json::from_str(
-                        &msg,
-                    )?))
-                } else {
-                    Err(APIError::Other(
-                        "No KucoinWebSocketMsg type to parse".to_string(),
-                    ))
+// Completes the wait on a new connection with its welcome message, or the first error
+// message unrelated to a request.
+fn resolve_welcome(welcome: &mut Option<AckSender>, msg: &Result<KucoinWebsocketMsg, APIError>) {
+    let outcome = match msg {
+        Ok(KucoinWebsocketMsg::WelcomeMsg(_)) => Ok(()),
+        Ok(KucoinWebsocketMsg::Error(e)) => Err(e.clone()),
+        _ => return,
+    };
+    if let Some(tx) = welcome.take() {
+        let _ = tx.send(outcome);
+    }
+}
+
+// Returns the first opened connection of the pool with room for another topic.
+fn pick_connection(
+    connections: &HashMap<usize, Connection>,
+    pool: &str,
+    limit: usize,
+) -> Option<usize> {
+    connections
+        .iter()
+        .filter(|(_, c)| c.pool == pool && c.topics.len() < limit)
+        .map(|(token, _)| *token)
+        .min()
+}
+
+// String that borrows from the frame unless it contains escapes.
+#[derive(Deserialize)]
+struct Str<'a>(#[serde(borrow)] Cow<'a, str>);
+
+// Routing fields shared by every Kucoin websocket frame.
+#[derive(Default)]
+struct Head<'a> {
+    r#type: Option<Str<'a>>,
+    id: Option<Str<'a>>,
+    topic: Option<Str<'a>>,
+    subject: Option<Str<'a>>,
+}
+
+impl<'a> Head<'a> {
+    fn r#type(&self) -> &str {
+        self.r#type.as_ref().map_or("", |s| &s.0)
+    }
+
+    fn topic(&self) -> &str {
+        self.topic.as_ref().map_or("", |s| &s.0)
+    }
+
+    fn subject(&self) -> &str {
+        self.subject.as_ref().map_or("", |s| &s.0)
+    }
+
+    // Topic up to the symbol list, e.g. /market/match for /market/match:BTC-USDT
+    fn prefix(&self) -> &str {
+        self.topic().split(':').next().unwrap_or_default()
+    }
+
+    // Private order feeds carry their event kind in data.type rather than the subject.
+    fn has_order_events(&self) -> bool {
+        matches!(
+            self.prefix(),
+            "/spotMarket/tradeOrders" | "/spotMarket/tradeOrdersV2"
+        )
+    }
+
+    // Whether data can be deserialized as soon as it is reached.
+    fn routable(&self) -> bool {
+        self.r#type() == "message"
+            && self.topic.is_some()
+            && self.subject.is_some()
+            && !self.has_order_events()
+    }
+
+    fn default_msg(&self) -> DefaultMsg {
+        DefaultMsg {
+            id: self.id.as_ref().map_or("", |s| &s.0).to_string(),
+            r#type: self.r#type().to_string(),
+        }
+    }
+
+    fn resp<'de, T, D>(&self, d: D) -> Result<WSResp<T>, D::Error>
+    where
+        T: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        Ok(WSResp {
+            r#type: self.r#type().to_string(),
+            topic: self.topic().to_string(),
+            subject: self.subject().to_string(),
+            data: T::deserialize(d)?,
+        })
+    }
+}
+
+#[allow(clippy::large_enum_variant)]
+enum Data<'a> {
+    Missing,
+    Parsed(Option<KucoinWebsocketMsg>),
+    Raw(&'a RawValue),
+}
+
+// A frame decoded in a single pass. Kucoin sends type, topic and subject ahead of data,
+// so data is normally deserialized straight into its message type. Otherwise it is kept
+// raw and routed once the whole envelope has been read.
+struct Frame<'a> {
+    head: Head<'a>,
+    data: Data<'a>,
+}
+
+impl<'de> Deserialize<'de> for Frame<'de> {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        d.deserialize_map(FrameVisitor)
+    }
+}
+
+struct FrameVisitor;
+
+impl<'de> Visitor<'de> for FrameVisitor {
+    type Value = Frame<'de>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a Kucoin websocket frame")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut head = Head::default();
+        let mut data = Data::Missing;
+        while let Some(key) = map.next_key::<Str>()? {
+            match key.0.as_ref() {
+                "type" => head.r#type = Some(map.next_value()?),
+                "id" => head.id = Some(map.next_value()?),
+                "topic" => head.topic = Some(map.next_value()?),
+                "subject" => head.subject = Some(map.next_value()?),
+                "data" if head.routable() => {
+                    data = Data::Parsed(map.next_value_seed(DataSeed {
+                        head: &head,
+                        event: None,
+                    })?)
+                }
+                "data" => data = Data::Raw(map.next_value()?),
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
                 }
-            } else {
-                Err(APIError::Other(
-                    "No KucoinWebSocketMsg type to parse".to_string(),
-                ))
             }
         }
+        Ok(Frame { head, data })
+    }
+}
+
+// Deserializes the data of a message frame into the KucoinWebsocketMsg its topic prefix
+// and subject (or data.type for order feeds) route to, or None for an unknown route.
+struct DataSeed<'h, 'a> {
+    head: &'h Head<'a>,
+    event: Option<&'h str>,
+}
+
+impl<'de> DeserializeSeed<'de> for DataSeed<'_, '_> {
+    type Value = Option<KucoinWebsocketMsg>;
+
+    fn deserialize<D: Deserializer<'de>>(self, d: D) -> Result<Self::Value, D::Error> {
+        let head = self.head;
+        let msg = match head.prefix() {
+            "/market/ticker" if head.topic() == "/market/ticker:all" => {
+                KucoinWebsocketMsg::AllTickerMsg(head.resp(d)?)
+            }
+            "/market/ticker" => KucoinWebsocketMsg::TickerMsg(head.resp(d)?),
+            "/market/snapshot" => KucoinWebsocketMsg::SnapshotMsg(head.resp(d)?),
+            "/market/level2" => KucoinWebsocketMsg::OrderBookMsg(head.resp(d)?),
+            "/spotMarket/level2Depth5" | "/spotMarket/level2Depth50" => {
+                KucoinWebsocketMsg::OrderBookDepthMsg(head.resp(d)?)
+            }
+            "/market/match" => KucoinWebsocketMsg::MatchMsg(head.resp(d)?),
+            "/market/level3" => match head.subject() {
+                "trade.l3received" => KucoinWebsocketMsg::Level3ReceivedMsg(head.resp(d)?),
+                "trade.l3open" => KucoinWebsocketMsg::Level3OpenMsg(head.resp(d)?),
+                "trade.l3done" => KucoinWebsocketMsg::Level3DoneMsg(head.resp(d)?),
+                "trade.l3match" => KucoinWebsocketMsg::Level3MatchMsg(head.resp(d)?),
+                "trade.l3change" => KucoinWebsocketMsg::Level3ChangeMsg(head.resp(d)?),
+                "stop" | "activate" => KucoinWebsocketMsg::StopOrderMsg(head.resp(d)?),
+                _ => return skip(d),
+            },
+            "/spotMarket/advancedOrders" => KucoinWebsocketMsg::StopOrderMsg(head.resp(d)?),
+            "/spotMarket/level3" => match head.subject() {
+                "received" => KucoinWebsocketMsg::FullMatchReceivedMsg(head.resp(d)?),
+                "open" => KucoinWebsocketMsg::FullMatchOpenMsg(head.resp(d)?),
+                "done" => KucoinWebsocketMsg::FullMatchDoneMsg(head.resp(d)?),
+                "match" => KucoinWebsocketMsg::FullMatchMatchMsg(head.resp(d)?),
+                "update" => KucoinWebsocketMsg::FullMatchChangeMsg(head.resp(d)?),
+                _ => return skip(d),
+            },
+            "/indicator/index" => KucoinWebsocketMsg::IndexPriceMsg(head.resp(d)?),
+            "/indicator/markPrice" => KucoinWebsocketMsg::MarketPriceMsg(head.resp(d)?),
+            "/margin/fundingBook" => KucoinWebsocketMsg::OrderBookChangeMsg(head.resp(d)?),
+            "/account/balance" => KucoinWebsocketMsg::BalancesMsg(head.resp(d)?),
+            "/margin/position" => match head.subject() {
+                "debt.ratio" => KucoinWebsocketMsg::DebtRatioMsg(head.resp(d)?),
+                "position.status" => KucoinWebsocketMsg::PositionChangeMsg(head.resp(d)?),
+                _ => return skip(d),
+            },
+            "/margin/loan" => match head.subject() {
+                "order.open" => KucoinWebsocketMsg::MarginTradeOpenMsg(head.resp(d)?),
+                "order.update" => KucoinWebsocketMsg::MarginTradeUpdateMsg(head.resp(d)?),
+                "order.done" => KucoinWebsocketMsg::MarginTradeDoneMsg(head.resp(d)?),
+                _ => return skip(d),
+            },
+            "/spotMarket/tradeOrders" => match self.event {
+                Some("open") => KucoinWebsocketMsg::TradeOpenMsg(head.resp(d)?),
+                Some("match") => KucoinWebsocketMsg::TradeMatchMsg(head.resp(d)?),
+                Some("filled") => KucoinWebsocketMsg::TradeFilledMsg(head.resp(d)?),
+                Some("canceled") => KucoinWebsocketMsg::TradeCanceledMsg(head.resp(d)?),
+                Some("update") => KucoinWebsocketMsg::TradeUpdateMsg(head.resp(d)?),
+                _ => return skip(d),
+            },
+            "/spotMarket/tradeOrdersV2" => match self.event {
+                Some("received") => KucoinWebsocketMsg::HfTradeReceivedMsg(head.resp(d)?),
+                Some("open") => KucoinWebsocketMsg::HfTradeOpenMsg(head.resp(d)?),
+                Some("match") => KucoinWebsocketMsg::HfTradeMatchMsg(head.resp(d)?),
+                Some("filled") => KucoinWebsocketMsg::HfTradeFilledMsg(head.resp(d)?),
+                Some("canceled") => KucoinWebsocketMsg::HfTradeCanceledMsg(head.resp(d)?),
+                Some("update") => KucoinWebsocketMsg::HfTradeUpdateMsg(head.resp(d)?),
+                _ => return skip(d),
+            },
+            "/contractMarket/tickerV2" => KucoinWebsocketMsg::FuturesTickerMsg(head.resp(d)?),
+            "/contractMarket/level2" => KucoinWebsocketMsg::FuturesOrderBookMsg(head.resp(d)?),
+            "/contractMarket/execution" => KucoinWebsocketMsg::FuturesExecutionMsg(head.resp(d)?),
+            "/contract/instrument" => match head.subject() {
+                "mark.index.price" => KucoinWebsocketMsg::FuturesMarkIndexPriceMsg(head.resp(d)?),
+                "funding.rate" => KucoinWebsocketMsg::FuturesFundingRateMsg(head.resp(d)?),
+                _ => return skip(d),
+            },
+            "/contract/position" => KucoinWebsocketMsg::FuturesPositionChangeMsg(head.resp(d)?),
+            "/contractMarket/tradeOrders" => KucoinWebsocketMsg::FuturesOrderMsg(head.resp(d)?),
+            "/contractAccount/wallet" => match head.subject() {
+                "orderMargin.change" => KucoinWebsocketMsg::FuturesOrderMarginMsg(head.resp(d)?),
+                "availableBalance.change" => {
+                    KucoinWebsocketMsg::FuturesAvailableBalanceMsg(head.resp(d)?)
+                }
+                _ => return skip(d),
+            },
+            _ => return skip(d),
+        };
+        Ok(Some(msg))
+    }
+}
+
+fn skip<'de, D: Deserializer<'de>>(d: D) -> Result<Option<KucoinWebsocketMsg>, D::Error> {
+    IgnoredAny::deserialize(d)?;
+    Ok(None)
+}
+
+#[derive(Deserialize)]
+struct OrderEvent<'a> {
+    #[serde(borrow)]
+    r#type: Str<'a>,
+}
+
+/// Stream of the messages of a single topic returned by KucoinWebsocket::subscribe_typed.
+/// Messages of the topic with a different data type, such as the stop order events sharing
+/// the private level3 topic, are skipped.
+pub struct TopicStream<T> {
+    rx: QueueReceiver,
+    data: PhantomData<fn() -> T>,
+}
+
+impl<T> TopicStream<T> {
+    fn new(rx: QueueReceiver) -> Self {
+        TopicStream {
+            rx,
+            data: PhantomData,
+        }
+    }
+}
+
+impl<T: WSData> Stream for TopicStream<T> {
+    type Item = Result<WSResp<T>, APIError>;
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            match self.rx.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(msg))) => {
+                    if let Some(resp) = T::from_msg(msg) {
+                        return Poll::Ready(Some(Ok(resp)));
+                    }
+                }
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+// Sends a message to every typed subscriber of its topic, or to the KucoinWebsocket stream
+// when it has none. Returns false once the KucoinWebsocket stream is dropped.
+async fn route_message(
+    routes: &std::sync::Mutex<Vec<TopicRoute>>,
+    tx: &QueueSender,
+    msg: Result<KucoinWebsocketMsg, APIError>,
+    received: u64,
+) -> bool {
+    let envelope = msg
+        .as_ref()
+        .ok()
+        .and_then(|m| Some((m.topic()?, m.subject()?)));
+    let subscribers: Vec<QueueSender> = match envelope {
+        Some((topic, subject)) => {
+            let mut routes = routes.lock().unwrap();
+            routes.retain(|r| !r.tx.is_closed());
+            routes
+                .iter()
+                .filter(|r| topic_matches(&r.topic, topic) && r.ws_topic.has_subject(subject))
+                .map(|r| r.tx.clone())
+                .collect()
+        }
+        None => Vec::new(),
+    };
+    match msg {
+        Ok(m) if !subscribers.is_empty() => {
+            for subscriber in subscribers {
+                subscriber.send(Ok(m.clone()), received).await;
+            }
+            true
+        }
+        msg => tx.send(msg, received).await,
+    }
+}
+
+// Reports topics of a closed connection to the KucoinWebsocket stream and to their typed
+// subscribers, whose streams then end.
+async fn disconnected(
+    routes: &std::sync::Mutex<Vec<TopicRoute>>,
+    tx: &QueueSender,
+    keys: Vec<TopicKey>,
+) {
+    let received = get_time() as u64;
+    let subscribers: Vec<QueueSender> = {
+        let mut routes = routes.lock().unwrap();
+        let (closed, open) = routes
+            .drain(..)
+            .partition(|r| keys.contains(&topic_key(&r.ws_topic)));
+        *routes = open;
+        closed.into_iter().map(|r: TopicRoute| r.tx).collect()
+    };
+    let mut topics: Vec<String> = keys.into_iter().map(|(topic, _)| topic).collect();
+    topics.dedup();
+    for subscriber in subscribers {
+        subscriber
+            .send(Err(APIError::Disconnected(topics.clone())), received)
+            .await;
+    }
+    tx.send(Err(APIError::Disconnected(topics)), received).await;
+}
+
+// Whether a message topic such as /market/match:BTC-USDT belongs to a subscribed topic
+// such as /market/match:BTC-USDT,ETH-USDT
+pub(crate) fn topic_matches(subscribed: &str, topic: &str) -> bool {
+    match (subscribed.split_once(':'), topic.split_once(':')) {
+        (Some((sub_prefix, symbols)), Some((prefix, symbol))) => {
+            sub_prefix == prefix && symbols.split(',').any(|s| s == symbol)
+        }
+        (None, None) => subscribed == topic,
+        _ => false,
+    }
+}
+
+/// Parses a raw websocket frame into a KucoinWebsocketMsg. Text frames are decoded in a single
+/// pass and routed on their type, then on the topic prefix (the part before ':') and subject.
+#[allow(clippy::result_large_err)]
+pub fn parse_message(msg: Message) -> Result<KucoinWebsocketMsg, APIError> {
+    parse_message_with(msg, ParseMode::Strict)
+}
+
+/// Parses a raw websocket frame like parse_message. A text frame of an unknown type or topic,
+/// or whose data does not parse, is an APIError::Parse carrying the frame in ParseMode::Strict
+/// and a KucoinWebsocketMsg::Unknown in ParseMode::Lenient.
+#[allow(clippy::result_large_err)]
+pub fn parse_message_with(msg: Message, mode: ParseMode) -> Result<KucoinWebsocketMsg, APIError> {
+    match msg {
+        Message::Text(msg) => match parse_text(&msg) {
+            Ok(Some(parsed)) => Ok(parsed),
+            Ok(None) => unknown_message(&msg, mode, "No KucoinWebSocketMsg type to parse"),
+            Err(e) => unknown_message(&msg, mode, &e.to_string()),
+        },
         Message::Binary(b) => Ok(KucoinWebsocketMsg::Binary(b)),
         Message::Pong(..) => Ok(KucoinWebsocketMsg::Pong),
         Message::Ping(..) => Ok(KucoinWebsocketMsg::Ping),
@@ -268,6 +1278,89 @@ fn parse_message(msg: Message) -> Result<KucoinWebsocketMsg, APIError> {
     }
 }
 
+#[allow(clippy::result_large_err)]
+fn unknown_message(
+    frame: &str,
+    mode: ParseMode,
+    msg: &str,
+) -> Result<KucoinWebsocketMsg, APIError> {
+    if mode == ParseMode::Lenient {
+        if let Ok(value) = serde_json::from_str(frame) {
+            return Ok(KucoinWebsocketMsg::Unknown(value));
+        }
+    }
+    Err(APIError::Parse {
+        msg: msg.to_string(),
+        frame: frame.to_string(),
+    })
+}
+
+// None when the frame's type or topic has no KucoinWebsocketMsg.
+fn parse_text(msg: &str) -> Result<Option<KucoinWebsocketMsg>, serde_json::Error> {
+    let Frame { head, data } = serde_json::from_str(msg)?;
+    let parsed = match head.r#type() {
+        "welcome" => KucoinWebsocketMsg::WelcomeMsg(head.default_msg()),
+        "ack" => KucoinWebsocketMsg::AckMsg(head.default_msg()),
+        "ping" => KucoinWebsocketMsg::PingMsg(head.default_msg()),
+        "pong" => KucoinWebsocketMsg::PongMsg(head.default_msg()),
+        "error" => KucoinWebsocketMsg::Error(serde_json::from_str(msg)?),
+        "message" => {
+            let parsed = match data {
+                Data::Parsed(m) => m,
+                Data::Raw(raw) => {
+                    let event = if head.has_order_events() {
+                        Some(serde_json::from_str::<OrderEvent>(raw.get())?.r#type)
+                    } else {
+                        None
+                    };
+                    let seed = DataSeed {
+                        head: &head,
+                        event: event.as_ref().map(|e| e.0.as_ref()),
+                    };
+                    seed.deserialize(&mut serde_json::Deserializer::from_str(raw.get()))?
+                }
+                Data::Missing => None,
+            };
+            return Ok(parsed);
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(parsed))
+}
+
+// Builds the socket url of an instance server endpoint, e.g.
+// wss://ws-api-spot.kucoin.com/?token=X&acceptUserMessage=true&connectId=Y
+#[allow(clippy::result_large_err)]
+fn socket_url(endpoint: &str, token: &str) -> Result<String, APIError> {
+    let mut url = parse_url(endpoint)?;
+    url.query_pairs_mut()
+        .append_pair("token", token)
+        .append_pair("acceptUserMessage", "true");
+    Ok(with_connect_id(url.as_str())?.to_string())
+}
+
+// Sets a new connectId on a socket url so every connection has a unique id, which Kucoin
+// echoes as the id of the welcome message.
+#[allow(clippy::result_large_err)]
+fn with_connect_id(url: &str) -> Result<Url, APIError> {
+    let mut url = parse_url(url)?;
+    let query: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(k, _)| k != "connectId")
+        .map(|(k, v)| (k.into_owned(), v.into_owned()))
+        .collect();
+    url.query_pairs_mut()
+        .clear()
+        .extend_pairs(query)
+        .append_pair("connectId", &request_id());
+    Ok(url)
+}
+
+#[allow(clippy::result_large_err)]
+fn parse_url(url: &str) -> Result<Url, APIError> {
+    Url::parse(url).map_err(|e| APIError::Other(format!("Invalid websocket url {}: {}", url, e)))
+}
+
 pub async fn close_socket(
     heartbeat: &mut tokio::task::JoinHandle<()>,
 ) -> Result<(), failure::Error> {
@@ -277,7 +1370,7 @@ pub async fn close_socket(
 
 impl Kucoin {
     pub fn websocket(&self) -> KucoinWebsocket {
-        KucoinWebsocket::default()
+        KucoinWebsocket::with_api(Some(self.clone()))
     }
 
     pub async fn ws_bullet_private(&self) -> Result<APIDatum<InstanceServers>, APIError> {
@@ -302,39 +1395,113 @@ impl Kucoin {
         Ok(api_data)
     }
 
-    pub async fn get_socket_endpoint(&self, ws_type: WSType) -> Result<String, APIError> {
-        let mut endpoint: String = String::new();
-        let mut token: String = String::new();
-        let timestamp = get_time();
-        match ws_type {
-            WSType::Private => {
-                let resp = &self.ws_bullet_private().await?;
-                if let Some(r) = &resp.data {
-                    token = r.token.to_owned();
-                    endpoint = r.instance_servers[0].endpoint.to_owned();
-                }
-            }
-            WSType::Public => {
-                let resp = &self.ws_bullet_public().await?;
-                if let Some(r) = &resp.data {
-                    token = r.token.to_owned();
-                    endpoint = r.instance_servers[0].endpoint.to_owned();
-                }
-            }
+    pub async fn ws_bullet_futures_private(&self) -> Result<APIDatum<InstanceServers>, APIError> {
+        let endpoint = String::from("/api/v1/bullet-private");
+        let url: String = format!("{}{}", &self.futures_prefix, endpoint);
+        let header: header::HeaderMap = self
+            .sign_headers(endpoint, None, None, Method::POST)
+            .unwrap();
+        let resp = self.post(url, Some(header), None).await?;
+        let api_data: APIDatum<InstanceServers> = resp.json().await?;
+        Ok(api_data)
+    }
+
+    pub async fn ws_bullet_futures_public(&self) -> Result<APIDatum<InstanceServers>, APIError> {
+        let endpoint = String::from("/api/v1/bullet-public");
+        let url: String = format!("{}{}", &self.futures_prefix, endpoint);
+        let header: header::HeaderMap = self
+            .sign_headers(endpoint, None, None, Method::POST)
+            .unwrap();
+        let resp = self.post(url, Some(header), None).await?;
+        let api_data: APIDatum<InstanceServers> = resp.json().await?;
+        Ok(api_data)
+    }
+
+    /// Token and instance servers of a bullet call for the websocket type, with an error when
+    /// Kucoin returns no token or no servers.
+    pub async fn get_socket_servers(&self, ws_type: WSType) -> Result<InstanceServers, APIError> {
+        let resp = match ws_type {
+            WSType::Private => self.ws_bullet_private().await?,
+            WSType::Public => self.ws_bullet_public().await?,
+            WSType::FuturesPrivate => self.ws_bullet_futures_private().await?,
+            WSType::FuturesPublic => self.ws_bullet_futures_public().await?,
+        };
+        match resp.data {
+            Some(servers) if !servers.instance_servers.is_empty() => Ok(servers),
+            Some(_) => Err(APIError::Other(
+                "No instance servers to connect to".to_string(),
+            )),
+            None => Err(APIError::Other(format!(
+                "Failed getting websocket token, code: {}, msg: {:?}",
+                resp.code, resp.msg
+            ))),
         }
-        let url = format!(
-            "{}?token={}&[connectId={}]?acceptUserMessage=\"true\"",
-            endpoint, token, timestamp
-        );
-        Ok(url)
+    }
+
+    /// Socket url of the first instance server returned by the bullet call for the websocket type.
+    /// subscribe_servers with the result of get_socket_servers fails over to the other servers.
+    pub async fn get_socket_endpoint(&self, ws_type: WSType) -> Result<String, APIError> {
+        let servers = self.get_socket_servers(ws_type).await?;
+        socket_url(&servers.instance_servers[0].endpoint, &servers.token)
     }
 }
 
-impl Subscribe {
-    pub fn new(topic_type: &WSTopic) -> Self {
-        let id = get_time().to_string();
-        let mut private_channel = false;
-        let topic = match topic_type {
+impl WSTopic {
+    /// Whether the topic requires a private token.
+    pub fn is_private(&self) -> bool {
+        matches!(
+            self,
+            WSTopic::Level3Private(_)
+                | WSTopic::Balances
+                | WSTopic::StopOrder(_)
+                | WSTopic::DebtRatio
+                | WSTopic::PositionChange
+                | WSTopic::MarginTradeOrder(_)
+                | WSTopic::TradeOrders
+                | WSTopic::TradeOrdersV2
+                | WSTopic::FuturesPosition(_)
+                | WSTopic::FuturesTradeOrders
+                | WSTopic::FuturesBalances
+        )
+    }
+
+    /// Whether the topic is served by the Kucoin Futures websocket.
+    pub fn is_futures(&self) -> bool {
+        matches!(
+            self,
+            WSTopic::FuturesTicker(_)
+                | WSTopic::FuturesOrderBook(_)
+                | WSTopic::FuturesExecution(_)
+                | WSTopic::FuturesInstrument(_)
+                | WSTopic::FuturesPosition(_)
+                | WSTopic::FuturesTradeOrders
+                | WSTopic::FuturesBalances
+        )
+    }
+
+    /// Type of the websocket token the topic has to be subscribed with.
+    pub fn ws_type(&self) -> WSType {
+        match (self.is_futures(), self.is_private()) {
+            (false, false) => WSType::Public,
+            (false, true) => WSType::Private,
+            (true, false) => WSType::FuturesPublic,
+            (true, true) => WSType::FuturesPrivate,
+        }
+    }
+
+    /// Whether a message of the topic path with the subject belongs to this topic, false for the
+    /// subjects of another topic sharing the path, e.g. debt.ratio for PositionChange.
+    pub fn has_subject(&self, subject: &str) -> bool {
+        match self {
+            WSTopic::DebtRatio => subject == "debt.ratio",
+            WSTopic::PositionChange => subject == "position.status",
+            _ => true,
+        }
+    }
+
+    /// Topic path sent in the subscribe message, e.g. /market/match:BTC-USDT,ETH-USDT
+    pub fn topic(&self) -> String {
+        match self {
             WSTopic::Ticker(ref symbols) => format!("/market/ticker:{}", symbols.join(",")),
             WSTopic::AllTicker => String::from("/market/ticker:all"),
             WSTopic::Snapshot(ref symbol) => format!("/market/snapshot:{}", symbol),
@@ -356,41 +1523,494 @@ impl Subscribe {
             WSTopic::Level3Public(ref symbols) => format!("/market/level3:{}", symbols.join(",")),
             WSTopic::FullMatch(ref symbols) => format!("/spotMarket/level3:{}", symbols.join(",")),
             WSTopic::Level3Private(ref symbols) => {
-                private_channel = true;
                 format!("/market/level3:{}", symbols.join(","))
             }
-            WSTopic::Balances => {
-                private_channel = true;
-                String::from("/account/balance")
-            }
+            WSTopic::Balances => String::from("/account/balance"),
             WSTopic::StopOrder(ref symbols) => {
-                private_channel = true;
                 format!("/market/level3:{}", symbols.join(","))
             }
-            WSTopic::DebtRatio => {
-                private_channel = true;
-                String::from("/margin/position")
-            }
-            WSTopic::PositionChange => {
-                private_channel = true;
-                String::from("/margin/position")
-            }
+            WSTopic::DebtRatio => String::from("/margin/position"),
+            WSTopic::PositionChange => String::from("/margin/position"),
             WSTopic::MarginTradeOrder(ref symbol) => {
-                private_channel = true;
                 format!("/margin/loan:{}", symbol)
             }
-            WSTopic::TradeOrders => {
-                private_channel = true;
-                String::from("/spotMarket/tradeOrders")
+            WSTopic::TradeOrders => String::from("/spotMarket/tradeOrders"),
+            WSTopic::TradeOrdersV2 => String::from("/spotMarket/tradeOrdersV2"),
+            WSTopic::FuturesTicker(ref symbols) => {
+                format!("/contractMarket/tickerV2:{}", symbols.join(","))
             }
-        };
+            WSTopic::FuturesOrderBook(ref symbols) => {
+                format!("/contractMarket/level2:{}", symbols.join(","))
+            }
+            WSTopic::FuturesExecution(ref symbols) => {
+                format!("/contractMarket/execution:{}", symbols.join(","))
+            }
+            WSTopic::FuturesInstrument(ref symbols) => {
+                format!("/contract/instrument:{}", symbols.join(","))
+            }
+            WSTopic::FuturesPosition(ref symbol) => {
+                format!("/contract/position:{}", symbol)
+            }
+            WSTopic::FuturesTradeOrders => String::from("/contractMarket/tradeOrders"),
+            WSTopic::FuturesBalances => String::from("/contractAccount/wallet"),
+        }
+    }
+}
+
+// Unique id for subscribe and unsubscribe requests, matched against their ack.
+fn request_id() -> String {
+    static SEQ: AtomicUsize = AtomicUsize::new(0);
+    format!("{}-{}", get_time(), SEQ.fetch_add(1, Ordering::Relaxed))
+}
 
+impl Subscribe {
+    pub fn new(topic_type: &WSTopic) -> Self {
+        let id = request_id();
         Subscribe {
             id,
             r#type: String::from("subscribe"),
-            topic,
-            private_channel,
+            topic: topic_type.topic(),
+            private_channel: topic_type.is_private(),
             response: true,
         }
     }
+
+    pub fn unsubscribe(topic_type: &WSTopic) -> Self {
+        Subscribe {
+            r#type: String::from("unsubscribe"),
+            ..Subscribe::new(topic_type)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::kucoin::buffer::{queue, Buffering};
+    use crate::kucoin::error::APIError;
+    use crate::kucoin::fake::fake_websocket;
+    use crate::kucoin::model::websocket::{
+        KucoinWebsocketMsg, Match, ParseMode, Subscribe, WSTopic, WSType,
+    };
+    use crate::kucoin::websocket::{
+        disconnected, parse_message, parse_message_with, pick_connection, resolve_ack,
+        route_message, socket_url, topic_key, with_connect_id, Connection, KucoinWebsocket, Pool,
+        TopicKey, TopicRoute, TopicStream,
+    };
+    use futures::channel::oneshot;
+    use futures::StreamExt;
+    use std::collections::{HashMap, HashSet};
+    use std::sync::{Arc, Mutex};
+    use tokio_tungstenite::tungstenite::Message;
+
+    #[test]
+    fn parse_futures_execution_before_spot_match() {
+        let msg = r#"{"type":"message","topic":"/contractMarket/execution:XBTUSDM","subject":"match","data":{"symbol":"XBTUSDM","sequence":36,"side":"buy","size":1,"price":"3568","takerOrderId":"5c9dd3a74bd0d92ce2e3a6a8","makerOrderId":"5c9dd3a74bd0d92ce2e3a6a7","tradeId":"5c9dd3a74bd0d92ce2e3a6a9","ts":1553846281766256031}}"#;
+        match parse_message(Message::Text(msg.to_string())).unwrap() {
+            KucoinWebsocketMsg::FuturesExecutionMsg(m) => assert_eq!(m.data.size, 1),
+            m => panic!("Unexpected message {:?}", m),
+        }
+    }
+
+    #[test]
+    fn parse_futures_level2_before_spot_depth() {
+        let msg = r#"{"type":"message","topic":"/contractMarket/level2:XBTUSDM","subject":"level2","data":{"sequence":18,"change":"5000.0,sell,83","timestamp":1551770400000}}"#;
+        match parse_message(Message::Text(msg.to_string())).unwrap() {
+            KucoinWebsocketMsg::FuturesOrderBookMsg(m) => {
+                assert_eq!(m.data.change, "5000.0,sell,83")
+            }
+            m => panic!("Unexpected message {:?}", m),
+        }
+    }
+
+    #[test]
+    fn parse_futures_instrument_subjects() {
+        let mark = r#"{"type":"message","topic":"/contract/instrument:XBTUSDM","subject":"mark.index.price","data":{"granularity":1000,"indexPrice":4000.23,"markPrice":4010.52,"timestamp":1551770400000}}"#;
+        let funding = r#"{"type":"message","topic":"/contract/instrument:XBTUSDM","subject":"funding.rate","data":{"granularity":60000,"fundingRate":-0.002966,"timestamp":1551770400000}}"#;
+        assert!(matches!(
+            parse_message(Message::Text(mark.to_string())).unwrap(),
+            KucoinWebsocketMsg::FuturesMarkIndexPriceMsg(_)
+        ));
+        assert!(matches!(
+            parse_message(Message::Text(funding.to_string())).unwrap(),
+            KucoinWebsocketMsg::FuturesFundingRateMsg(_)
+        ));
+    }
+
+    #[test]
+    fn parse_trade_orders_ignores_payload_text() {
+        let msg = r#"{"type":"message","topic":"/spotMarket/tradeOrders","subject":"orderChange","channelType":"private","data":{"symbol":"KCS-USDT","orderType":"limit","side":"buy","type":"open","orderId":"5efab07953bdea00089965d2","orderTime":1593487481683297666,"size":"0.1","filledSize":"0","price":"0.937","clientOid":"order.done-error","remainSize":"0.1","status":"open","ts":1593487481683297666}}"#;
+        match parse_message(Message::Text(msg.to_string())).unwrap() {
+            KucoinWebsocketMsg::TradeOpenMsg(m) => {
+                assert_eq!(m.data.client_oid, "order.done-error")
+            }
+            m => panic!("Unexpected message {:?}", m),
+        }
+    }
+
+    #[test]
+    fn parse_hf_trade_received() {
+        let msg = r#"{"type":"message","topic":"/spotMarket/tradeOrdersV2","subject":"orderChange","channelType":"private","data":{"symbol":"BTC-USDT","orderType":"limit","side":"buy","orderId":"6400a6e8c39d6e0001e8aa31","orderTime":1677764328000,"price":"20000","clientOid":"5c52e11203aa677f33e493fb","type":"received","size":"0.1","status":"new","ts":1677764328000000000}}"#;
+        match parse_message(Message::Text(msg.to_string())).unwrap() {
+            KucoinWebsocketMsg::HfTradeReceivedMsg(m) => {
+                assert_eq!(m.data.size.as_deref(), Some("0.1"))
+            }
+            m => panic!("Unexpected message {:?}", m),
+        }
+    }
+
+    #[test]
+    fn parse_hf_trade_open() {
+        let msg = r#"{"type":"message","topic":"/spotMarket/tradeOrdersV2","subject":"orderChange","channelType":"private","data":{"symbol":"BTC-USDT","orderType":"limit","side":"buy","orderId":"6400a6e8c39d6e0001e8aa31","orderTime":1677764328000,"price":"20000","clientOid":"5c52e11203aa677f33e493fb","type":"open","size":"0.1","filledSize":"0","remainSize":"0.1","status":"open","ts":1677764328000000001}}"#;
+        match parse_message(Message::Text(msg.to_string())).unwrap() {
+            KucoinWebsocketMsg::HfTradeOpenMsg(m) => assert_eq!(m.data.remain_size, "0.1"),
+            m => panic!("Unexpected message {:?}", m),
+        }
+    }
+
+    #[test]
+    fn parse_hf_trade_match() {
+        let msg = r#"{"type":"message","topic":"/spotMarket/tradeOrdersV2","subject":"orderChange","channelType":"private","data":{"symbol":"BTC-USDT","orderType":"limit","side":"buy","orderId":"6400a6e8c39d6e0001e8aa31","orderTime":1677764328000,"price":"20000","clientOid":"5c52e11203aa677f33e493fb","type":"match","liquidity":"maker","size":"0.1","filledSize":"0.04","matchPrice":"20000","matchSize":"0.04","tradeId":"11116472408358913","remainSize":"0.06","status":"match","ts":1677764328000000002}}"#;
+        match parse_message(Message::Text(msg.to_string())).unwrap() {
+            KucoinWebsocketMsg::HfTradeMatchMsg(m) => assert_eq!(m.data.match_size, "0.04"),
+            m => panic!("Unexpected message {:?}", m),
+        }
+    }
+
+    #[test]
+    fn parse_hf_trade_filled() {
+        let msg = r#"{"type":"message","topic":"/spotMarket/tradeOrdersV2","subject":"orderChange","channelType":"private","data":{"symbol":"BTC-USDT","orderType":"limit","side":"buy","orderId":"6400a6e8c39d6e0001e8aa31","orderTime":1677764328000,"price":"20000","clientOid":"5c52e11203aa677f33e493fb","type":"filled","size":"0.1","filledSize":"0.1","remainSize":"0","status":"done","ts":1677764328000000003}}"#;
+        match parse_message(Message::Text(msg.to_string())).unwrap() {
+            KucoinWebsocketMsg::HfTradeFilledMsg(m) => assert_eq!(m.data.filled_size, "0.1"),
+            m => panic!("Unexpected message {:?}", m),
+        }
+    }
+
+    #[test]
+    fn parse_hf_trade_canceled() {
+        let msg = r#"{"type":"message","topic":"/spotMarket/tradeOrdersV2","subject":"orderChange","channelType":"private","data":{"symbol":"BTC-USDT","orderType":"limit","side":"buy","orderId":"6400a6e8c39d6e0001e8aa31","orderTime":1677764328000,"price":"20000","clientOid":"5c52e11203aa677f33e493fb","type":"canceled","size":"0.1","filledSize":"0.04","remainSize":"0","status":"done","ts":1677764328000000004}}"#;
+        match parse_message(Message::Text(msg.to_string())).unwrap() {
+            KucoinWebsocketMsg::HfTradeCanceledMsg(m) => assert_eq!(m.data.status, "done"),
+            m => panic!("Unexpected message {:?}", m),
+        }
+    }
+
+    #[test]
+    fn parse_hf_trade_update() {
+        let msg = r#"{"type":"message","topic":"/spotMarket/tradeOrdersV2","subject":"orderChange","channelType":"private","data":{"symbol":"BTC-USDT","orderType":"limit","side":"buy","orderId":"6400a6e8c39d6e0001e8aa31","orderTime":1677764328000,"price":"20000","clientOid":"5c52e11203aa677f33e493fb","type":"update","oldSize":"0.1","size":"0.08","filledSize":"0.04","remainSize":"0.04","status":"open","ts":1677764328000000005}}"#;
+        match parse_message(Message::Text(msg.to_string())).unwrap() {
+            KucoinWebsocketMsg::HfTradeUpdateMsg(m) => assert_eq!(m.data.old_size, "0.1"),
+            m => panic!("Unexpected message {:?}", m),
+        }
+    }
+
+    #[test]
+    fn parse_frame_with_data_before_topic() {
+        let msg = r#"{"data":{"sequence":"1545896669145","type":"match","symbol":"BTC-USDT","side":"buy","price":"0.082","size":"0.0102","tradeId":"5c24c5da03aa673885cd67aa","takerOrderId":"5c24c5d903aa6772d55b371e","makerOrderId":"5c2187d003aa677bd09d5c93","time":"1545913818099033203"},"subject":"trade.l3match","topic":"/market/match:BTC-USDT","type":"message"}"#;
+        match parse_message(Message::Text(msg.to_string())).unwrap() {
+            KucoinWebsocketMsg::MatchMsg(m) => assert_eq!(m.data.price, "0.082"),
+            m => panic!("Unexpected message {:?}", m),
+        }
+    }
+
+    #[tokio::test]
+    async fn route_topic_messages_to_typed_stream() {
+        let buffering = Arc::new(Buffering::default());
+        let (tx, rx) = queue(buffering.clone());
+        let (main_tx, mut main_rx) = queue(buffering);
+        let ws_topic = WSTopic::Match(vec!["ETH-USDT".to_string(), "BTC-USDT".to_string()]);
+        let routes = Mutex::new(vec![TopicRoute {
+            topic: ws_topic.topic(),
+            ws_topic,
+            tx,
+        }]);
+        let mut matches: TopicStream<Match> = TopicStream::new(rx);
+
+        let frame = r#"{"type":"message","topic":"/market/match:BTC-USDT","subject":"trade.l3match","data":{"sequence":"1545896669145","type":"match","symbol":"BTC-USDT","side":"buy","price":"0.082","size":"0.0102","tradeId":"5c24c5da03aa673885cd67aa","takerOrderId":"5c24c5d903aa6772d55b371e","makerOrderId":"5c2187d003aa677bd09d5c93","time":"1545913818099033203"}}"#;
+        let msg = parse_message(Message::Text(frame.to_string()));
+        assert!(route_message(&routes, &main_tx, msg, 0).await);
+        assert_eq!(matches.next().await.unwrap().unwrap().data.price, "0.082");
+
+        let other = frame.replace("BTC-USDT", "KCS-USDT");
+        let msg = parse_message(Message::Text(other));
+        assert!(route_message(&routes, &main_tx, msg, 0).await);
+        match main_rx.next().await {
+            Some(Ok(KucoinWebsocketMsg::MatchMsg(m))) => assert_eq!(m.data.symbol, "KCS-USDT"),
+            m => panic!("Unexpected message {:?}", m),
+        }
+    }
+
+    #[test]
+    fn unknown_frames_by_parse_mode() {
+        let unknown = r#"{"type":"message","topic":"/market/newFeed:BTC-USDT","subject":"new","data":{"a":1}}"#;
+        match parse_message(Message::Text(unknown.to_string())) {
+            Err(APIError::Parse { frame, .. }) => assert_eq!(frame, unknown),
+            m => panic!("Unexpected message {:?}", m),
+        }
+        match parse_message_with(Message::Text(unknown.to_string()), ParseMode::Lenient) {
+            Ok(msg @ KucoinWebsocketMsg::Unknown(_)) => {
+                assert_eq!(msg.topic(), Some("/market/newFeed:BTC-USDT"))
+            }
+            m => panic!("Unexpected message {:?}", m),
+        }
+
+        // A known topic whose data no longer matches its type
+        let changed = r#"{"type":"message","topic":"/market/match:BTC-USDT","subject":"trade.l3match","data":{"price":1}}"#;
+        match parse_message(Message::Text(changed.to_string())) {
+            Err(APIError::Parse { frame, .. }) => assert_eq!(frame, changed),
+            m => panic!("Unexpected message {:?}", m),
+        }
+        match parse_message_with(Message::Text(changed.to_string()), ParseMode::Lenient) {
+            Ok(KucoinWebsocketMsg::Unknown(v)) => assert_eq!(v["data"]["price"], 1),
+            m => panic!("Unexpected message {:?}", m),
+        }
+
+        // Not JSON at all is an error in either mode
+        assert!(parse_message_with(Message::Text("{".to_string()), ParseMode::Lenient).is_err());
+    }
+
+    #[test]
+    fn pick_connection_packs_topics_per_pool() {
+        let topics = |n: usize| -> HashSet<TopicKey> {
+            (0..n)
+                .map(|i| topic_key(&WSTopic::Match(vec![format!("SYM{}-USDT", i)])))
+                .collect()
+        };
+        let mut connections = HashMap::new();
+        connections.insert(
+            3,
+            Connection {
+                pool: "public".to_string(),
+                topics: topics(2),
+            },
+        );
+        connections.insert(
+            1,
+            Connection {
+                pool: "public".to_string(),
+                topics: topics(1),
+            },
+        );
+        connections.insert(
+            0,
+            Connection {
+                pool: "private".to_string(),
+                topics: topics(0),
+            },
+        );
+        assert_eq!(pick_connection(&connections, "public", 2), Some(1));
+        assert_eq!(pick_connection(&connections, "public", 1), None);
+        assert_eq!(pick_connection(&connections, "private", 1), Some(0));
+        assert_eq!(pick_connection(&connections, "futures", 1), None);
+    }
+
+    #[test]
+    fn topics_sharing_a_topic_string_are_counted() {
+        let (tx, _rx) = queue(Arc::new(Buffering::default()));
+        let mut pool = Pool {
+            subscriptions: HashMap::new(),
+            connections: HashMap::new(),
+            links: HashMap::new(),
+            topic_limit: 2,
+            next_server: 0,
+            next_connection: 1,
+            tx,
+        };
+        pool.connections.insert(
+            0,
+            Connection {
+                pool: "private".to_string(),
+                topics: HashSet::new(),
+            },
+        );
+        assert!(!pool.share_topic(&WSTopic::DebtRatio));
+        pool.add_topic(&WSTopic::DebtRatio, 0);
+        assert!(pool.share_topic(&WSTopic::PositionChange));
+        assert_eq!(pool.connections[&0].topics.len(), 1);
+
+        assert_eq!(pool.remove_topic(&WSTopic::DebtRatio), None);
+        assert_eq!(pool.remove_topic(&WSTopic::DebtRatio), None);
+        let position = ("/margin/position".to_string(), true);
+        assert!(pool.connections[&0].topics.contains(&position));
+        assert_eq!(pool.remove_topic(&WSTopic::PositionChange), Some(0));
+        assert!(pool.subscriptions.is_empty());
+        assert!(pool.connections[&0].topics.is_empty());
+
+        // A rejected topic string is dropped with the topics that shared it while pending
+        pool.add_topic(&WSTopic::DebtRatio, 0);
+        assert!(pool.share_topic(&WSTopic::PositionChange));
+        pool.reject_topic(&WSTopic::DebtRatio, 1);
+        assert_eq!(pool.subscriptions.len(), 1);
+        pool.reject_topic(&WSTopic::DebtRatio, 0);
+        assert!(pool.subscriptions.is_empty());
+        assert!(pool.connections[&0].topics.is_empty());
+    }
+
+    #[tokio::test]
+    async fn closed_connection_is_evicted_and_reported() {
+        let buffering = Arc::new(Buffering::default());
+        let (main_tx, mut main_rx) = queue(buffering.clone());
+        let mut pool = Pool {
+            subscriptions: HashMap::new(),
+            connections: HashMap::new(),
+            links: HashMap::new(),
+            topic_limit: 2,
+            next_server: 0,
+            next_connection: 2,
+            tx: main_tx.clone(),
+        };
+        for token in 0..2 {
+            pool.connections.insert(
+                token,
+                Connection {
+                    pool: "public".to_string(),
+                    topics: HashSet::new(),
+                },
+            );
+        }
+        let btc = WSTopic::Match(vec!["BTC-USDT".to_string()]);
+        let eth = WSTopic::Match(vec!["ETH-USDT".to_string()]);
+        pool.add_topic(&btc, 0);
+        pool.add_topic(&eth, 1);
+        let topics = pool.evict(0);
+        assert_eq!(topics, vec![topic_key(&btc)]);
+        assert_eq!(pool.connections.len(), 1);
+        assert!(!pool.share_topic(&btc));
+        assert!(pool.evict(0).is_empty());
+
+        let (btc_tx, btc_rx) = queue(buffering.clone());
+        let (eth_tx, _eth_rx) = queue(buffering);
+        let routes = Mutex::new(vec![
+            TopicRoute {
+                topic: btc.topic(),
+                ws_topic: btc,
+                tx: btc_tx,
+            },
+            TopicRoute {
+                topic: eth.topic(),
+                ws_topic: eth,
+                tx: eth_tx,
+            },
+        ]);
+        disconnected(&routes, &main_tx, topics).await;
+        assert_eq!(routes.lock().unwrap().len(), 1);
+        let mut matches: TopicStream<Match> = TopicStream::new(btc_rx);
+        assert!(matches!(
+            matches.next().await,
+            Some(Err(APIError::Disconnected(t))) if t == vec!["/market/match:BTC-USDT"]
+        ));
+        assert!(matches.next().await.is_none());
+        assert!(matches!(
+            main_rx.next().await,
+            Some(Err(APIError::Disconnected(_)))
+        ));
+    }
+
+    #[test]
+    fn unsubscribe_ack_resolves_pending_request() {
+        let unsub = Subscribe::unsubscribe(&WSTopic::Match(vec!["BTC-USDT".to_string()]));
+        assert_eq!(unsub.r#type, "unsubscribe");
+        assert_eq!(unsub.topic, "/market/match:BTC-USDT");
+
+        let (tx, mut rx) = oneshot::channel();
+        let acks = Mutex::new(HashMap::new());
+        acks.lock().unwrap().insert(unsub.id.clone(), tx);
+        let ack = format!(r#"{{"id":"{}","type":"ack"}}"#, unsub.id);
+        assert!(resolve_ack(&acks, &parse_message(Message::Text(ack))));
+        assert_eq!(rx.try_recv(), Ok(Some(Ok(()))));
+        assert!(acks.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn error_message_rejects_pending_subscribe() {
+        let sub = Subscribe::new(&WSTopic::Ticker(vec!["FOO-USDT".to_string()]));
+        let (tx, mut rx) = oneshot::channel();
+        let acks = Mutex::new(HashMap::new());
+        acks.lock().unwrap().insert(sub.id.clone(), tx);
+        let error = format!(
+            r#"{{"id":"{}","type":"error","code":404,"data":"topic /market/ticker:FOO-USDT is not found"}}"#,
+            sub.id
+        );
+        let msg = parse_message(Message::Text(error));
+        match &msg {
+            Ok(KucoinWebsocketMsg::Error(e)) => assert_eq!(e.code, 404),
+            m => panic!("Unexpected message {:?}", m),
+        }
+        assert!(resolve_ack(&acks, &msg));
+        let rejected = rx.try_recv().unwrap().unwrap().unwrap_err();
+        assert_eq!(rejected.data, "topic /market/ticker:FOO-USDT is not found");
+    }
+
+    #[test]
+    fn topics_classified_by_ws_type() {
+        let symbol = vec!["BTC-USDT".to_string()];
+        assert_eq!(WSTopic::Ticker(symbol.clone()).ws_type(), WSType::Public);
+        assert_eq!(WSTopic::TradeOrders.ws_type(), WSType::Private);
+        assert_eq!(
+            WSTopic::StopOrder(symbol.clone()).ws_type(),
+            WSType::Private
+        );
+        assert_eq!(
+            WSTopic::FuturesTicker(symbol).ws_type(),
+            WSType::FuturesPublic
+        );
+        assert_eq!(WSTopic::FuturesBalances.ws_type(), WSType::FuturesPrivate);
+    }
+
+    #[tokio::test]
+    async fn public_and_private_topics_sharing_a_topic_string_subscribe_apart() {
+        let (url, mut requests, _frames) = fake_websocket().await;
+        let ws = KucoinWebsocket::default();
+        let control = ws.control();
+        let symbol = vec!["BTC-USDT".to_string()];
+        let public = WSTopic::Level3Public(symbol.clone());
+        let private = WSTopic::StopOrder(symbol);
+        control
+            .subscribe(url, vec![public.clone(), private.clone()])
+            .await
+            .unwrap();
+        let mut subscribed = Vec::new();
+        for _ in 0..2 {
+            let req = requests.next().await.unwrap();
+            assert_eq!(req["type"], "subscribe");
+            assert_eq!(req["topic"], "/market/level3:BTC-USDT");
+            subscribed.push(req["privateChannel"].as_bool().unwrap());
+        }
+        subscribed.sort();
+        assert_eq!(subscribed, vec![false, true]);
+
+        // Unsubscribing one leaves the other subscribed
+        control.unsubscribe(private).await.unwrap();
+        let req = requests.next().await.unwrap();
+        assert_eq!(req["type"], "unsubscribe");
+        assert_eq!(req["privateChannel"], true);
+        assert_eq!(control.topics().await, vec![public]);
+    }
+
+    #[tokio::test]
+    async fn subscribe_topics_requires_api() {
+        let ws = KucoinWebsocket::default();
+        let control = ws.control();
+        let err = control.subscribe_topics(vec![WSTopic::Balances]).await;
+        assert!(err.is_err());
+        // The route of a failed typed subscription is removed
+        assert!(control.subscribe_queue(WSTopic::Balances).await.is_err());
+        assert!(control.routes.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn socket_url_has_unique_connect_id() {
+        let url = socket_url("wss://ws-api-spot.kucoin.com/", "2neAiuYvAU61ZD").unwrap();
+        assert!(url.starts_with(
+            "wss://ws-api-spot.kucoin.com/?token=2neAiuYvAU61ZD&acceptUserMessage=true&connectId="
+        ));
+
+        let next = with_connect_id(&url).unwrap();
+        let ids: Vec<_> = next
+            .query_pairs()
+            .filter(|(k, _)| k == "connectId")
+            .collect();
+        assert_eq!(ids.len(), 1);
+        assert!(!url.ends_with(&*ids[0].1));
+        assert!(socket_url("not a url", "token").is_err());
+    }
 }
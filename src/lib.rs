@@ -21,6 +21,7 @@
 //! * [`Kucoin Client`](./kucoin/client/struct.Kucoin.html)
 //! * [`API General Response Models`](./kucoin/model/index.html)                  
 //! * [`Market Response Models`](./kucoin/model/market/index.html)
+//! * [`HF Trading Response Models`](./kucoin/model/hf/index.html)
 //! * [`Margin Response Models`](./kucoin/model/margin/index.html)
 //! * [`Trade Response Models`](./kucoin/model/trade/index.html)        
 //! * [`User Response Models`](./kucoin/model/user/index.html)          
@@ -124,13 +125,74 @@
 //! }
 //! ```
 //!
+//! Strategy code that should run against either Kucoin or a simulator can be written against the traits in
+//! [`exchange`](./kucoin/exchange/index.html) instead of the client: `MarketData` (symbols, tickers, order books,
+//! trades and candles), `OrderEntry` (placing, cancelling and querying orders from an `OrderRequest`) and `Account`
+//! (balances). `Kucoin` implements all three, unwrapping each response with `into_data` so a missing `data` becomes an
+//! `APIError` with Kucoin's code and message.
+//!
+//! `paper::PaperExchange` implements `OrderEntry` and `Account` against simulated balances for trading without risking
+//! funds. Feed it market data with `paper.on_message(&msg)` or `paper.feed(ws)` (a `Replay` works too): depth, level2 and
+//! ticker messages build its book and public matches fill resting orders they trade through. It takes the same calls as
+//! trade.rs, such as `post_limit_order`, `cancel_order`, `get_orders` and `get_fills`, charges the maker and taker fees
+//! and latency of its `PaperConfig`, and `paper.events()` yields `TradeOpenMsg`, `TradeMatchMsg`, `TradeFilledMsg` and
+//! `TradeCanceledMsg` as the private `/spotMarket/tradeOrders` topic would.
+//!
+//! `backtest::Backtester` evaluates a strategy on history. Its events are candles, e.g. from `backtest::load_candles`
+//! which pages `get_klines` over a time window, or websocket messages from a `Replay` through `run_stream`. The strategy,
+//! a closure or `BacktestStrategy`, trades through a `SimBroker` that fills market orders on the next event with a
+//! `SlippageModel`, fills resting limit orders by a `QueueModel` and rounds orders to the increments of the symbol's
+//! `SymbolList`. The `BacktestReport` gives PnL, maximum drawdown, turnover, fees, the fills and the equity curve.
+//!
+//! A `strategy::Strategy` implements the hooks it needs out of `on_start`, `on_ticker`, `on_trade`, `on_book`,
+//! `on_order_update`, `on_timer` and `on_stop`, and trades through the `StrategyContext` passed to each. The same
+//! strategy runs unchanged under `StrategyRuntime::live` on Kucoin, `StrategyRuntime::paper` on a `PaperExchange` fed
+//! with live data, or `StrategyRuntime::replay` on a `PaperExchange` fed with a recording. Set a timer with
+//! `runtime.timer(interval)`, then `runtime.run().await` or `runtime.start()` for a `RuntimeHandle` whose `stop` ends
+//! the run gracefully after calling `on_stop`.
+//!
+//! `portfolio::Portfolio` tracks the available, hold and total balance of every account. Seed it with
+//! `portfolio.seed(&api).await?`, then feed it the `WSTopic::Balances` pushes with `portfolio.feed(ws)`; pushes older
+//! than the balance last requested are ignored. `portfolio.spawn_reconcile(api, interval)` compares it with
+//! `get_accounts_list` periodically and corrects drifted balances. `snapshot()` copies every balance at once and
+//! `changes()` yields a `BalanceChange` for each update with its reason: seed, push or reconcile.
+//!
+//! `pnl::PnlEngine` computes PnL from fills, `engine.on_fills(&fills)` with the result of `get_fills` or
+//! `engine.on_message(&msg)` with `TradeMatchMsg` events, skipping trades it has already seen. Each symbol closes its
+//! lots by `CostMethod::Fifo`, `Lifo` or `Average`. Ticker messages mark open positions for unrealized PnL, and
+//! `engine.report()` converts the quotes and fees of every symbol to the reporting currency, e.g. USDT, using rates set
+//! with `engine.rate("KCS", rate)` or the marks of each currency's symbol against it.
+//!
+//! `ledger::LedgerExport::new(start_at, end_at).fetch(&api)` walks the ledgers of every account, optionally of one
+//! currency or account type, querying the range in 24 hour windows page by page. Each `LedgerEntry` is normalized to a
+//! `BizType` (trade, deposit, withdrawal, transfer, fee, interest or other) with amounts kept as exact decimal strings,
+//! and `ledger::write_csv` or `ledger::write_jsonl` write the entries for accounting and tax tools. Requests are spaced
+//! and retried with backoff when rate limited, and `export.export_csv(&api, file)` writes entries as they are fetched so
+//! a failed export keeps what it got.
+//!
+//! Fee rates come from `api.get_base_fee(None)` and `api.get_trade_fees(&["BTC-USDT"])` instead of being hard-coded. A
+//! `fee::FeeSchedule` caches them for an hour by default, `schedule.rates(&api, "BTC-USDT").await?` fetching them only
+//! when missing or expired. `fee::estimate(&order, &rates, market_price, kcs)` gives the expected fee and net proceeds of
+//! an `OrderRequest`, in the quote currency or in KCS at its discount when given a `KcsDeduction`.
+//!
+//! `execution::Execution` works a large order over a time window instead of slicing it by hand with `post_limit_order`.
+//! `ExecutionConfig::new(symbol, side, size, window)` sends TWAP slices by default. `ExecutionAlgo::Vwap` weights the
+//! slices by a volume profile of historical candles set with `config.volume_profile(&candles)`, and `ExecutionAlgo::Pov`
+//! slices are a share of the volume traded since the previous slice, leaving unfilled what the volume didn't allow. Each slice is a limit order priced from the ticker and
+//! capped by `limit_price`, and is rounded to the `SymbolRules` increments. `execution.run(ws).await` trades through any
+//! `OrderEntry`, Kucoin or a `PaperExchange`, and returns the `ExecutionProgress`: filled size, average price, slippage
+//! against the arrival price in basis points, and whether the order completed. `execution.progress()` streams the same
+//! after every slice.
+//!
 //!
 //! ### Websocket Usage
 //!
-//! Websockets require several steps to initalize. A single websocket can accept up to 10 subscriptions,
-//! as per Kucoin limitations. Due to this, the instantiation of the socket takes a Vec\<[WSTopic](./kucoin/model/websocket/enum.WSTopic.html)\>.
-//! The reason is because multiple subscriptions can be initialized from one call. Below is a simplified single subscription with a line-by-line
-//! short explanation including some basic options for specified error handling.
+//! Websockets require several steps to initalize. Subscriptions take a Vec\<[WSTopic](./kucoin/model/websocket/enum.WSTopic.html)\>
+//! so multiple topics can be initialized from one call. Topics are packed onto pooled connections up to Kucoin's per-connection
+//! topic limit (`MAX_TOPICS_PER_CONNECTION`, adjustable with `set_topic_limit`) and new connections are opened as needed.
+//! `subscribe_servers` takes the token and instance servers of a bullet call, e.g. from `get_socket_servers`, instead of a url
+//! and spreads new connections across the instance servers, failing over to the next server when one refuses the connection.
+//! Below is a simplified single subscription with a line-by-line short explanation including some basic options for specified error handling.
 //!
 //! ```ignore
 //! extern crate kucoin_rs;
@@ -189,6 +251,48 @@
 //! [`WSTopic`](./kucoin/model/websocket/enum.WSTopic.html) has all the available websocket topics/endpoints that are
 //! available for subscription.
 //!
+//! Futures topics, the `WSTopic::Futures*` variants, are served from the Kucoin Futures websocket and need a url
+//! generated with `WSType::FuturesPublic` or `WSType::FuturesPrivate`. Connections are only shared between topics
+//! subscribed with the same url, so spot and futures subscriptions can be added to the same `KucoinWebsocket` and consumed from one stream.
+//!
+//! `subscribe_topics` (and its typed counterpart `subscribe_topic`) skips the url entirely: each topic is classified
+//! with `WSTopic::ws_type`, the matching public, private or futures bullet is fetched when a new connection is needed,
+//! and the topic joins a pooled connection of its type. This requires the websocket to be created with `api.websocket()`.
+//!
+//! When a task only cares about one topic, `subscribe_typed` (or a helper such as `subscribe_match` or
+//! `subscribe_order_book`) returns a stream of that topic's messages already typed by their data, e.g.
+//! `Stream<Item = Result<WSResp<Match>, APIError>>`. Typed subscriptions share the connection of an existing
+//! subscription to the same topic, and their messages are no longer yielded by the `KucoinWebsocket` stream.
+//!
+//! `subscribe` waits for each new connection's welcome message and for Kucoin to acknowledge every topic, returning an
+//! error naming the topic when Kucoin rejects it or does not answer within `ACK_TIMEOUT`. The welcome, ack and error
+//! messages are still yielded as `WelcomeMsg`, `AckMsg` and `Error` with the id and code of the request.
+//!
+//! `unsubscribe` sends Kucoin an unsubscribe message for a single topic and waits for its acknowledgement, leaving any
+//! other topic on the same connection subscribed. To change subscriptions while another task consumes the stream,
+//! take a cloneable handle with `ws.control()` and call `subscribe` or `unsubscribe` on it.
+//!
+//! A frame that fails to parse is yielded as its own `Err` item and the stream keeps going, so consume it with
+//! `ws.next()` rather than `ws.try_next()?` to outlive them. By default (`ParseMode::Strict`) the error is an
+//! `APIError::Parse` carrying the raw frame for logging. `ws.set_parse_mode(ParseMode::Lenient)` instead yields
+//! frames of unknown topics, or whose data no longer matches its type, as `KucoinWebsocketMsg::Unknown` with the frame's JSON.
+//!
+//! `ws.record(path)` writes every raw frame received from then on, with its receive time, to a gzip compressed JSON lines
+//! file until the returned `Recorder` is finished or dropped. `recorder::Replay::open(path, speed)` streams the recording
+//! back as the same `Result<KucoinWebsocketMsg, APIError>` items at `ReplaySpeed::Original`, `Accelerated(factor)` or
+//! `AsFastAsPossible`, so code consuming a `KucoinWebsocket` can be run against historical feeds.
+//!
+//! Messages wait in a buffer until the `KucoinWebsocket` stream, or the typed stream of their topic, is polled. By default
+//! the buffer is unbounded; `ws.set_buffer_policy(&topic, policy)` bounds it per symbol with `BufferPolicy::Block(n)`,
+//! which stops reading the connection until the consumer catches up, `DropOldest(n)` or `Conflate`, which keeps only the
+//! latest message of each symbol. `ws.metrics()` reports the received, delivered, dropped and queued messages of every topic
+//! along with their lag, the time from the exchange timestamp (or receipt) to delivery, and `ws.lag_events(threshold)`
+//! yields a `LagEvent` for each message delivered later than the threshold.
+//!
+//! When several tasks want the same feed, `api.market_hub()` returns a cloneable `MarketHub` that owns the connections.
+//! `hub.subscribe(topic)` hands each task its own `HubSubscription` stream fed from a broadcast channel, subscribing the
+//! topic on Kucoin only for the first subscriber and unsubscribing it once the last `HubSubscription` is dropped.
+//!
 //! Note that Level3 data has been separated by message type despite it requiring only a single subscription.
 //! All other subscriptions coincide 1:1 with their response type and KucoinWebsocketMsg,
 //! excluding their Ping, Pong and Welcome messages. Ping, Pong and Welcome can be tracked through their own match arm.
@@ -225,6 +329,7 @@
 //!
 //! This project is open source and uses the MIT license. Feel free to utilize it in whatever way you see fit.
 
+pub extern crate async_trait;
 pub extern crate futures;
 pub extern crate pin_project;
 pub extern crate reqwest;